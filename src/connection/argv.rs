@@ -0,0 +1,136 @@
+//! Building shell commands from a program and its arguments
+//!
+//! Commands are run by a shell on the host. Each argument is quoted so that the shell gives it to the program as it
+//! is, whatever it contains (spaces, quotes, $, ;...) : a value coming from a TaskList or a variable can't change the command.
+
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Argv {
+    env: Vec<(String, String)>,
+    args: Vec<String>,
+    discard_stdout: bool,
+}
+
+impl Argv {
+    pub fn new(program: &str) -> Argv {
+        Argv {
+            env: Vec::new(),
+            args: vec![program.to_string()],
+            discard_stdout: false,
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<str>) -> Argv {
+        self.args.push(arg.as_ref().to_string());
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Argv
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        for arg in args {
+            self.args.push(arg.as_ref().to_string());
+        }
+        self
+    }
+
+    /// Environment variable given to the program only (KEY=value before the program)
+    pub fn env(mut self, key: &str, value: &str) -> Argv {
+        self.env.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// Standard output goes to /dev/null (ex: tee writing its input to a file)
+    pub fn discard_stdout(mut self) -> Argv {
+        self.discard_stdout = true;
+        self
+    }
+
+    /// Command line to give to a shell
+    pub fn to_shell(&self) -> String {
+        let mut words: Vec<String> = self
+            .env
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, quote(value)))
+            .collect();
+        words.extend(self.args.iter().map(|arg| quote(arg)));
+        if self.discard_stdout {
+            words.push("> /dev/null".to_string());
+        }
+        words.join(" ")
+    }
+}
+
+impl fmt::Display for Argv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_shell())
+    }
+}
+
+/// POSIX quoting : the value is kept as it is when the shell wouldn't change it, otherwise it is put between single quotes.
+/// Single quotes keep everything as is for the shell, except single quotes themselves ('\'' closes, escapes and reopens).
+pub fn quote(value: &str) -> String {
+    let unchanged_by_shell = !value.is_empty()
+        && value
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || "_-+=/.,:@%".contains(character));
+    if unchanged_by_shell {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::connectionmode::localhost::LocalHostHandler;
+
+    #[test]
+    fn arguments_reach_the_program_as_they_are() {
+        let handler = LocalHostHandler::new();
+        let values = [
+            "plain",
+            "",
+            "two words",
+            "it's",
+            "\"double\"",
+            "$HOME `id` $(id)",
+            "a; rm -rf /tmp/nothing",
+            "*",
+            "back\\slash",
+            "new\nline",
+            "~",
+            "'",
+        ];
+
+        let argv = Argv::new("printf").arg("%s|").args(values);
+        let cmd_result = handler.run_cmd(&argv.to_shell()).unwrap();
+
+        assert_eq!(cmd_result.rc, 0);
+        assert_eq!(cmd_result.stdout, format!("{}|", values.join("|")));
+    }
+
+    #[test]
+    fn command_lines_stay_readable() {
+        assert_eq!(
+            Argv::new("apt-get")
+                .env("DEBIAN_FRONTEND", "noninteractive")
+                .args(["install", "-y", "libssl3:amd64"])
+                .to_shell(),
+            "DEBIAN_FRONTEND=noninteractive apt-get install -y libssl3:amd64"
+        );
+        assert_eq!(
+            Argv::new("tee")
+                .arg("-a")
+                .arg("/etc/my app.conf")
+                .discard_stdout()
+                .to_shell(),
+            "tee -a '/etc/my app.conf' > /dev/null"
+        );
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote(""), "''");
+    }
+}
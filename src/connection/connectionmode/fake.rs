@@ -0,0 +1,432 @@
+//! Scripted transport standing in for a real host in tests : connection failures, command outputs and broken sessions are all decided in advance.
+
+use crate::connection::argv::Argv;
+use crate::error::connection::{ConnectionError, SshError};
+use crate::error::Error;
+use crate::result::cmd::CmdResult;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FakeTransport {
+    pub init_failure: Option<FakeInitFailure>,
+    /// Connecting again after a session drop fails this way
+    pub reconnect_failure: Option<FakeInitFailure>,
+    /// The first response whose pattern is contained in the command is used. Other commands succeed with no output.
+    pub responses: Vec<(String, FakeResponse)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FakeInitFailure {
+    Unreachable,
+    AuthenticationRefused,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum FakeResponse {
+    Output(i32, String),      // (rc, stdout)
+    TransportFailure,         // The session breaks while running the command
+    SessionDrop(i32, String), // The session is lost the first time the command runs. Once reconnected, it gives this (rc, stdout).
+}
+
+impl FakeTransport {
+    pub fn new() -> FakeTransport {
+        FakeTransport {
+            init_failure: None,
+            reconnect_failure: None,
+            responses: Vec::new(),
+        }
+    }
+
+    pub fn failing_init(mut self, init_failure: FakeInitFailure) -> FakeTransport {
+        self.init_failure = Some(init_failure);
+        self
+    }
+
+    pub fn failing_reconnect(mut self, reconnect_failure: FakeInitFailure) -> FakeTransport {
+        self.reconnect_failure = Some(reconnect_failure);
+        self
+    }
+
+    pub fn on(mut self, pattern: &str, response: FakeResponse) -> FakeTransport {
+        self.responses.push((pattern.to_string(), response));
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct FakeHostHandler {
+    pub hostaddress: String,
+    pub transport: FakeTransport,
+    pub session_lost: bool,
+    pub dropped_patterns: Vec<String>, // SessionDrop responses already used
+}
+
+impl FakeHostHandler {
+    pub fn from(hostaddress: String, transport: FakeTransport) -> FakeHostHandler {
+        FakeHostHandler {
+            hostaddress,
+            transport,
+            session_lost: false,
+            dropped_patterns: Vec::new(),
+        }
+    }
+
+    pub fn init(&mut self) -> Result<(), Error> {
+        self.connect(self.transport.init_failure.clone())
+    }
+
+    pub fn reconnect(&mut self) -> Result<(), Error> {
+        self.connect(self.transport.reconnect_failure.clone())
+    }
+
+    fn connect(&mut self, failure: Option<FakeInitFailure>) -> Result<(), Error> {
+        match failure {
+            None => {
+                self.session_lost = false;
+                Ok(())
+            }
+            Some(FakeInitFailure::Unreachable) => {
+                Err(Error::Connection(ConnectionError::HostUnreachable {
+                    address: self.hostaddress.clone(),
+                    source: std::io::Error::from(std::io::ErrorKind::ConnectionRefused),
+                }))
+            }
+            Some(FakeInitFailure::AuthenticationRefused) => {
+                Err(Error::Connection(ConnectionError::AuthenticationFailed {
+                    address: self.hostaddress.clone(),
+                    username: "fake".to_string(),
+                    source: None,
+                }))
+            }
+        }
+    }
+
+    pub fn is_this_cmd_available(&mut self, cmd: &str) -> Result<bool, Error> {
+        let cmd_result =
+            self.run_cmd(Argv::new("command").arg("-v").arg(cmd).to_shell().as_str())?;
+        Ok(cmd_result.rc == 0)
+    }
+
+    pub fn run_cmd(&mut self, cmd: &str) -> Result<CmdResult, Error> {
+        if self.session_lost {
+            return Err(self.transport_failure());
+        }
+
+        let response = self
+            .transport
+            .responses
+            .iter()
+            .find(|(pattern, _)| cmd.contains(pattern.as_str()));
+
+        match response {
+            None => Ok(CmdResult::new()),
+            Some((_, FakeResponse::Output(rc, stdout))) => Ok(CmdResult {
+                rc: *rc,
+                stdout: stdout.clone(),
+                stderr: String::new(),
+            }),
+            Some((_, FakeResponse::TransportFailure)) => Err(self.transport_failure()),
+            Some((pattern, FakeResponse::SessionDrop(rc, stdout))) => {
+                if self.dropped_patterns.contains(pattern) {
+                    return Ok(CmdResult {
+                        rc: *rc,
+                        stdout: stdout.clone(),
+                        stderr: String::new(),
+                    });
+                }
+                self.dropped_patterns.push(pattern.clone());
+                self.session_lost = true;
+                Err(self.transport_failure())
+            }
+        }
+    }
+
+    fn transport_failure(&self) -> Error {
+        Error::Connection(ConnectionError::SessionFailed {
+            address: self.hostaddress.clone(),
+            source: SshError::Protocol("Fake transport failure".to_string()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::host_connection::HostConnectionInfo;
+    use crate::host::hosts::Host;
+    use crate::job::job::Job;
+    use crate::job::joblist::JobList;
+    use crate::result::apicallresult::ApiCallStatus;
+    use crate::task::tasklist::TaskListFileType;
+    use crate::workflow::hostworkflow::HostWorkFlowStatus;
+    use crate::workflow::stepflow::StepStatus;
+
+    const COMMAND_TASKLIST: &str = "---
+- name: Run a command
+  steps:
+    - name: The command
+      command:
+        content: systemctl restart my_app
+";
+
+    const APT_TASKLIST: &str = "---
+- name: Install a package
+  steps:
+    - name: The package
+      apt:
+        package: git
+        state: present
+";
+
+    fn fake_job(address: &str, transport: FakeTransport, tasklist: &str) -> Job {
+        let mut job = Job::from_host(Host::from_string(address.into()));
+        job.set_connection(HostConnectionInfo::Fake(transport))
+            .unwrap()
+            .set_tasklist_from_str(tasklist, TaskListFileType::Yaml)
+            .unwrap();
+        job
+    }
+
+    fn first_step_status(job: &Job) -> StepStatus {
+        job.hostworkflow.as_ref().unwrap().task_flows[0].step_flows[0]
+            .step_status
+            .clone()
+    }
+
+    #[test]
+    fn connection_failures_end_in_connection_init_failed() {
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused).to_string();
+        for (init_failure, cause) in [
+            (FakeInitFailure::Unreachable, Some(refused)),
+            (FakeInitFailure::AuthenticationRefused, None),
+        ] {
+            let mut job = fake_job(
+                "10.20.30.51",
+                FakeTransport::new().failing_init(init_failure),
+                COMMAND_TASKLIST,
+            );
+            job.apply();
+            match &job.final_status {
+                HostWorkFlowStatus::ConnectionInitFailed(message) => {
+                    assert!(message.contains("10.20.30.51"));
+                    // With what caused it
+                    if let Some(cause) = cause {
+                        assert!(message.ends_with(&format!(" : {}", cause)), "{}", message);
+                    }
+                }
+                other => panic!("unexpected status : {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn job_without_tasklist_fails_to_initialize() {
+        let mut job = Job::from_host(Host::from_string("10.20.30.51".into()));
+        job.set_connection(HostConnectionInfo::Fake(FakeTransport::new()))
+            .unwrap();
+        job.apply();
+        assert!(matches!(
+            job.final_status,
+            HostWorkFlowStatus::JobInitFailed(_)
+        ));
+    }
+
+    #[test]
+    fn missing_tool_ends_in_dry_run_failed() {
+        let mut job = fake_job(
+            "10.20.30.51",
+            FakeTransport::new().on("command -v apt-get", FakeResponse::Output(1, String::new())),
+            APT_TASKLIST,
+        );
+        job.dry_run();
+        match &job.final_status {
+            HostWorkFlowStatus::DryRunFailed(message) => assert!(message.contains("APT")),
+            other => panic!("unexpected status : {:?}", other),
+        }
+    }
+
+    #[test]
+    fn broken_session_during_checks_ends_in_dry_run_failed() {
+        let mut job = fake_job(
+            "10.20.30.51",
+            FakeTransport::new().on("dpkg -s -- git", FakeResponse::TransportFailure),
+            APT_TASKLIST,
+        );
+        job.dry_run();
+        match &job.final_status {
+            HostWorkFlowStatus::DryRunFailed(message) => {
+                assert!(message.contains("10.20.30.51"));
+            }
+            other => panic!("unexpected status : {:?}", other),
+        }
+    }
+
+    #[test]
+    fn failing_command_ends_in_apply_failed() {
+        let mut job = fake_job(
+            "10.20.30.51",
+            FakeTransport::new().on(
+                "systemctl restart",
+                FakeResponse::Output(5, "Unit my_app.service not found.".into()),
+            ),
+            COMMAND_TASKLIST,
+        );
+        job.apply();
+        assert!(matches!(job.final_status, HostWorkFlowStatus::ApplyFailed));
+        assert!(matches!(first_step_status(&job), StepStatus::ApplyFailed));
+    }
+
+    #[test]
+    fn broken_session_during_apply_is_a_step_failure() {
+        let mut job = fake_job(
+            "10.20.30.51",
+            FakeTransport::new().on("systemctl restart", FakeResponse::TransportFailure),
+            COMMAND_TASKLIST,
+        );
+        job.apply();
+        assert!(matches!(job.final_status, HostWorkFlowStatus::ApplyFailed));
+
+        let step_flow = &job.hostworkflow.as_ref().unwrap().task_flows[0].step_flows[0];
+        let api_call_result = &step_flow.step_result.as_ref().unwrap().apicallresults[0];
+        assert!(api_call_result.rc.is_none());
+        match &api_call_result.status {
+            ApiCallStatus::Failure(message) => assert!(message.contains("10.20.30.51")),
+            other => panic!("unexpected status : {:?}", other),
+        }
+    }
+
+    #[test]
+    fn checks_are_run_again_after_reconnecting() {
+        let mut job = fake_job(
+            "10.20.30.51",
+            FakeTransport::new().on(
+                "dpkg -s -- git",
+                FakeResponse::SessionDrop(0, "Status: install ok installed".into()),
+            ),
+            APT_TASKLIST,
+        );
+        job.dry_run();
+        assert!(matches!(
+            job.final_status,
+            HostWorkFlowStatus::AlreadyMatched
+        ));
+
+        let reconnections = &job.connection_report.as_ref().unwrap().reconnections;
+        assert_eq!(reconnections.len(), 1);
+        assert!(reconnections[0].reconnected);
+        assert!(reconnections[0].command_retried);
+        assert!(reconnections[0]
+            .cause
+            .ends_with(" : Fake transport failure"));
+        assert!(job.display().contains("reconnections"));
+    }
+
+    #[test]
+    fn changes_are_not_run_again_after_reconnecting() {
+        let mut job = fake_job(
+            "10.20.30.51",
+            FakeTransport::new().on(
+                "systemctl restart",
+                FakeResponse::SessionDrop(0, String::new()),
+            ),
+            COMMAND_TASKLIST,
+        );
+        job.apply();
+        // The command may or may not have restarted the service : it is reported as failed rather than run twice
+        assert!(matches!(job.final_status, HostWorkFlowStatus::ApplyFailed));
+
+        let reconnections = &job.connection_report.as_ref().unwrap().reconnections;
+        assert_eq!(reconnections.len(), 1);
+        assert!(reconnections[0].reconnected);
+        assert!(!reconnections[0].command_retried);
+    }
+
+    #[test]
+    fn host_not_coming_back_fails_the_run() {
+        let mut job = fake_job(
+            "10.20.30.51",
+            FakeTransport::new()
+                .on(
+                    "dpkg -s -- git",
+                    FakeResponse::SessionDrop(0, String::new()),
+                )
+                .failing_reconnect(FakeInitFailure::Unreachable),
+            APT_TASKLIST,
+        );
+        job.dry_run();
+        match &job.final_status {
+            HostWorkFlowStatus::DryRunFailed(message) => assert!(message.contains("unreachable")),
+            other => panic!("unexpected status : {:?}", other),
+        }
+
+        let reconnections = &job.connection_report.as_ref().unwrap().reconnections;
+        assert_eq!(reconnections.len(), 1);
+        assert!(!reconnections[0].reconnected);
+        assert_eq!(reconnections[0].attempts, 2);
+    }
+
+    #[test]
+    fn one_bad_host_does_not_stop_the_others() {
+        let mut job_list = JobList::new();
+        job_list.add_job(fake_job(
+            "good-host",
+            FakeTransport::new(),
+            COMMAND_TASKLIST,
+        ));
+        job_list.add_job(fake_job(
+            "unreachable-host",
+            FakeTransport::new().failing_init(FakeInitFailure::Unreachable),
+            COMMAND_TASKLIST,
+        ));
+        job_list.add_job(fake_job(
+            "broken-host",
+            FakeTransport::new().on("systemctl", FakeResponse::TransportFailure),
+            COMMAND_TASKLIST,
+        ));
+
+        job_list.apply();
+
+        for job in job_list.job_list.unwrap() {
+            match job.get_address().as_str() {
+                "good-host" => assert!(matches!(
+                    job.final_status,
+                    HostWorkFlowStatus::ApplySuccesful
+                )),
+                "unreachable-host" => assert!(matches!(
+                    job.final_status,
+                    HostWorkFlowStatus::ConnectionInitFailed(_)
+                )),
+                _ => assert!(matches!(job.final_status, HostWorkFlowStatus::ApplyFailed)),
+            }
+        }
+    }
+
+    #[test]
+    fn leading_dash_packages_are_never_read_as_options() {
+        let mut job = fake_job(
+            "10.20.30.51",
+            FakeTransport::new()
+                .on(
+                    "-- '-oDPkg::Pre-Invoke::=touch /tmp/pwned'",
+                    FakeResponse::Output(0, String::new()),
+                )
+                .on(
+                    "-oDPkg",
+                    FakeResponse::Output(100, "read as an option".into()),
+                ),
+            "---
+- name: Install a package
+  steps:
+    - name: The package
+      apt:
+        package: '-oDPkg::Pre-Invoke::=touch /tmp/pwned'
+        state: present
+",
+        );
+        job.apply();
+        assert!(matches!(
+            job.final_status,
+            HostWorkFlowStatus::ApplySuccesful
+        ));
+    }
+}
@@ -1,8 +1,19 @@
+use crate::connection::argv::Argv;
 use crate::connection::specification::Credentials;
+use crate::connection::transfer::{temp_path, DEFAULT_FILE_MODE};
+use crate::error::connection::ConnectionError;
 use crate::error::Error;
+use crate::output::streaming::{LineBuffer, OnLine, OutputStream};
 use crate::result::cmd::CmdResult;
 use serde::{Deserialize, Serialize};
-use std::process::Command;
+use std::fs::{OpenOptions, Permissions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Output, Stdio};
+use std::sync::mpsc;
+use std::thread;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LocalHostConnectionDetails {
@@ -41,51 +52,249 @@ impl LocalHostHandler {
     pub fn is_this_cmd_available(&self, cmd: &str) -> Result<bool, Error> {
         let check_cmd_result = Command::new("sh")
             .arg("-c")
-            .arg(format!("command -v {}", cmd))
+            .arg(Argv::new("command").arg("-v").arg(cmd).to_shell())
             .output();
 
         match check_cmd_result {
             Ok(cmd_result) => {
-                if cmd_result.status.code().unwrap() == 0 {
+                if cmd_result.status.success() {
                     return Ok(true);
                 } else {
                     return Ok(false);
                 }
             }
             Err(e) => {
-                return Err(Error::FailureToRunCommand(format!("{:?}", e)));
+                return Err(Error::Connection(ConnectionError::LocalCommandFailed(e)));
             }
         }
     }
 
     pub fn run_cmd(&self, cmd: &str) -> Result<CmdResult, Error> {
-        let result = match &self.user {
-            WhichUser::CurrentUser => Command::new("sh").arg("-c").arg(cmd).output(),
-            WhichUser::PasswordLessUser(username) => Command::new("su")
-                .arg("-")
-                .arg(username)
-                .arg("-c")
-                .arg("sh")
-                .arg("-c")
-                .arg(cmd)
-                .output(),
-            WhichUser::UsernamePassword(credentials) => {
-                let command_content = format!(
-                    "echo \"{}\" | su - {} -c \"{}\"",
-                    credentials.password, credentials.username, cmd
-                );
+        self.run_cmd_with_input(cmd, None, None)
+    }
 
-                Command::new("sh").arg("-c").arg(command_content).output()
+    /// stdin is written to the command as is, followed by EOF. Each line of output is given to on_line as soon as the command writes it.
+    pub fn run_cmd_with_input(
+        &self,
+        cmd: &str,
+        stdin: Option<&[u8]>,
+        on_line: Option<&OnLine<'_>>,
+    ) -> Result<CmdResult, Error> {
+        let mut command = match &self.user {
+            WhichUser::CurrentUser => {
+                let mut command = Command::new("sh");
+                command.arg("-c").arg(cmd);
+                command
+            }
+            WhichUser::PasswordLessUser(username) => {
+                let mut command = Command::new("su");
+                command
+                    .arg("-")
+                    .arg(username)
+                    .arg("-c")
+                    .arg("sh")
+                    .arg("-c")
+                    .arg(cmd);
+                command
+            }
+            WhichUser::UsernamePassword(credentials) => {
+                let mut command = Command::new("sh");
+                command
+                    .arg("-c")
+                    .arg(su_with_password(credentials, cmd, stdin.is_some()));
+                command
             }
         };
 
+        let result = match (stdin, on_line) {
+            (None, None) => command.output(),
+            (stdin, on_line) => run_piped(&mut command, stdin, on_line),
+        };
+
         match result {
             Ok(output) => Ok(CmdResult {
-                rc: output.status.code().unwrap(),
+                rc: exit_code(&output.status),
                 stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
             }),
-            Err(e) => Err(Error::FailureToRunCommand(format!("{}", e))),
+            Err(e) => Err(Error::Connection(ConnectionError::LocalCommandFailed(e))),
+        }
+    }
+
+    /// Written as the current user to a temporary file next to the destination, then renamed. Without a mode, the permissions of the replaced file are kept.
+    pub fn upload(&self, content: &[u8], path: &str, mode: Option<u32>) -> Result<(), Error> {
+        let destination = Path::new(path);
+        let temp = temp_path(destination);
+        let perm = match mode {
+            Some(mode) => mode,
+            None => match std::fs::metadata(destination) {
+                Ok(metadata) => metadata.permissions().mode() & 0o7777,
+                Err(_) => DEFAULT_FILE_MODE,
+            },
+        };
+
+        let write_temp = || -> Result<(), std::io::Error> {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&temp)?;
+            file.write_all(content)?;
+            file.sync_all()?;
+            // Not subject to the umask, unlike the mode given when opening
+            file.set_permissions(Permissions::from_mode(perm))?;
+            std::fs::rename(&temp, destination)
+        };
+
+        match write_temp() {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let _ = std::fs::remove_file(&temp);
+                Err(transfer_failed(path, e))
+            }
+        }
+    }
+
+    pub fn download(&self, path: &str) -> Result<Vec<u8>, Error> {
+        std::fs::read(path).map_err(|e| transfer_failed(path, e))
+    }
+}
+
+// su's input is the password. Any input for the command reaches it through another file descriptor,
+// so that su can't take it for the password or swallow it while reading the password.
+fn su_with_password(credentials: &Credentials, cmd: &str, with_stdin: bool) -> String {
+    let password = Argv::new("printf").arg("%s\\n").arg(&credentials.password);
+    if !with_stdin {
+        return format!(
+            "{} | {}",
+            password,
+            Argv::new("su")
+                .arg("-")
+                .arg(&credentials.username)
+                .arg("-c")
+                .arg(cmd)
+        );
+    }
+    return format!(
+        "exec 3<&0; {} | {}",
+        password,
+        Argv::new("su")
+            .arg("-")
+            .arg(&credentials.username)
+            .arg("-c")
+            .arg(format!("exec 0<&3 3<&-; {}", cmd))
+    );
+}
+
+fn transfer_failed(path: &str, source: std::io::Error) -> Error {
+    Error::Connection(ConnectionError::TransferFailed {
+        address: "localhost".to_string(),
+        path: path.to_string(),
+        source,
+    })
+}
+
+// stdin is written and both output pipes are read by their own thread so that none of them can fill up and block the command
+fn run_piped(
+    command: &mut Command,
+    stdin: Option<&[u8]>,
+    on_line: Option<&OnLine<'_>>,
+) -> std::io::Result<Output> {
+    let mut child = command
+        .stdin(match stdin {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    thread::scope(|scope| {
+        // A command exiting without reading all its input is not an error : its exit code tells what happened
+        if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+            scope.spawn(move || {
+                let _ = pipe.write_all(input);
+            });
+        }
+
+        match on_line {
+            Some(on_line) => collect_streaming(child, on_line),
+            None => child.wait_with_output(),
+        }
+    })
+}
+
+// Lines are handed to on_line from the calling thread, in the order they arrive
+fn collect_streaming(mut child: Child, on_line: &OnLine<'_>) -> std::io::Result<Output> {
+    let (sender, receiver) = mpsc::channel::<(OutputStream, Vec<u8>)>();
+    let mut readers = Vec::new();
+    if let Some(stdout) = child.stdout.take() {
+        readers.push(read_chunks(stdout, OutputStream::Stdout, sender.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        readers.push(read_chunks(stderr, OutputStream::Stderr, sender.clone()));
+    }
+    drop(sender);
+
+    let mut stdout: Vec<u8> = Vec::new();
+    let mut stderr: Vec<u8> = Vec::new();
+    let mut stdout_lines = LineBuffer::new();
+    let mut stderr_lines = LineBuffer::new();
+    // Ends once both readers are done and dropped their sender
+    for (stream, chunk) in receiver {
+        match stream {
+            OutputStream::Stdout => {
+                stdout.extend_from_slice(&chunk);
+                stdout_lines.push(&chunk, &mut |line| on_line(stream, line));
+            }
+            OutputStream::Stderr => {
+                stderr.extend_from_slice(&chunk);
+                stderr_lines.push(&chunk, &mut |line| on_line(stream, line));
+            }
+        }
+    }
+    stdout_lines.flush(&mut |line| on_line(OutputStream::Stdout, line));
+    stderr_lines.flush(&mut |line| on_line(OutputStream::Stderr, line));
+
+    for reader in readers {
+        let _ = reader.join();
+    }
+    let status = child.wait()?;
+
+    Ok(Output {
+        status,
+        stdout,
+        stderr,
+    })
+}
+
+fn read_chunks<R: Read + Send + 'static>(
+    mut pipe: R,
+    stream: OutputStream,
+    sender: mpsc::Sender<(OutputStream, Vec<u8>)>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buffer = [0u8; 8192];
+        loop {
+            match pipe.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(size) => {
+                    if sender.send((stream, buffer[..size].to_vec())).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            }
         }
+    })
+}
+
+// A process killed by a signal has no exit code : follow the shell convention (128 + signal)
+fn exit_code(status: &ExitStatus) -> i32 {
+    match status.code() {
+        Some(code) => code,
+        None => 128 + status.signal().unwrap_or(0),
     }
 }
 
@@ -95,3 +304,60 @@ pub enum WhichUser {
     PasswordLessUser(String), // The String being the username
     UsernamePassword(Credentials),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stands for su : the password is "secret". Like PAM, it reads all of its input, not only the password line.
+    const FAKE_SU: &str = r#"#!/bin/sh
+[ "$1" = - ] && shift
+shift
+[ "$1" = -c ] && shift
+IFS= read -r password
+cat > /dev/null
+if [ "$password" = secret ]; then exec sh -c "$1"; fi
+echo "su: Authentication failure" >&2
+exit 1
+"#;
+
+    #[test]
+    fn input_reaches_command_run_as_user_with_password() {
+        let directory = std::env::temp_dir().join(format!("dux-su-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let su = directory.join("su");
+        std::fs::write(&su, FAKE_SU).unwrap();
+        std::fs::set_permissions(&su, Permissions::from_mode(0o755)).unwrap();
+
+        let handler = LocalHostHandler::new();
+        let run = |password: &str, stdin: Option<&[u8]>| {
+            let credentials = Credentials::from("someone".to_string(), password.to_string());
+            let script = su_with_password(&credentials, "cat; echo done", stdin.is_some());
+            handler
+                .run_cmd_with_input(
+                    &format!(
+                        "PATH={}:\"$PATH\" {}",
+                        directory.display(),
+                        Argv::new("sh").arg("-c").arg(&script)
+                    ),
+                    stdin,
+                    None,
+                )
+                .unwrap()
+        };
+
+        let with_input = run("secret", Some(b"first\nsecond\n"));
+        assert_eq!(with_input.rc, 0);
+        assert_eq!(with_input.stdout, "first\nsecond\ndone\n");
+
+        let without_input = run("secret", None);
+        assert_eq!(without_input.stdout, "done\n");
+
+        let refused = run("wrong", Some(b"first\n"));
+        assert_eq!(refused.rc, 1);
+        assert_eq!(refused.stdout, "");
+        assert_eq!(refused.stderr, "su: Authentication failure\n");
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
@@ -1,2 +1,6 @@
+#[cfg(test)]
+pub mod fake;
 pub mod localhost;
+#[cfg(feature = "russh")]
+pub mod russhmode;
 pub mod ssh2mode;
@@ -1,14 +1,46 @@
 //! Most frequent case : reach host through SSHv2
+//!
+//! The settings (Ssh2Settings, Ssh2AuthMode...) are shared by both SSH backends. The libssh2 handler itself needs the cargo feature "ssh2" (default).
 
-use crate::connection::specification::Credentials;
+#[cfg(feature = "ssh2")]
+use crate::connection::argv::Argv;
+use crate::connection::knownhosts::{check_known_host, key_type, known_host_name, KnownHostCheck};
+#[cfg(feature = "ssh2")]
+use crate::connection::specification::ConnectionReport;
+use crate::connection::specification::{Credentials, ReconnectPolicy};
+use crate::connection::sshconfig::{expand_tokens, local_username, SshConfig, SshConfigHost};
+#[cfg(feature = "ssh2")]
+use crate::connection::transfer::{temp_path, DEFAULT_FILE_MODE};
+use crate::error::connection::ConnectionError;
+#[cfg(feature = "ssh2")]
+use crate::error::connection::SshError;
 use crate::error::Error;
+#[cfg(feature = "ssh2")]
+use crate::output::streaming::{LineBuffer, OnLine, OutputStream};
+#[cfg(feature = "ssh2")]
 use crate::result::cmd::CmdResult;
+use base64::Engine;
 use pem::Pem;
 use serde::{Deserialize, Serialize};
-use ssh2::Session;
-use std::io::Read;
-use std::net::TcpStream;
-use std::path::PathBuf;
+use sha2::{Digest, Sha256};
+#[cfg(feature = "ssh2")]
+use ssh2::{Agent, BlockDirections, Channel, FileStat, OpenFlags, OpenType, PublicKey, Session};
+use std::io::{Read, Write};
+#[cfg(feature = "ssh2")]
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(feature = "ssh2")]
+use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(feature = "ssh2")]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "ssh2")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+#[cfg(feature = "ssh2")]
+use std::thread::JoinHandle;
+use std::time::Duration;
+#[cfg(feature = "ssh2")]
+use std::time::Instant;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Ssh2ConnectionDetails {
@@ -25,19 +57,443 @@ impl Ssh2ConnectionDetails {
     }
 }
 
+pub const DEFAULT_SSH2_PORT: u16 = 22;
+
+/// rc of a remote command, whichever SSH backend ran it. A command killed by a signal has no exit status : 128 + the
+/// signal number, like on localhost. When the server sent neither, 0 : libssh2 can't tell it from an exit status of 0.
+pub fn remote_exit_code(exit_status: Option<u32>, exit_signal: Option<&str>) -> i32 {
+    if let Some(exit_status) = exit_status {
+        return exit_status as i32;
+    }
+    // Signal names of RFC 4254, without the SIG prefix
+    let signal_number = match exit_signal {
+        None => return 0,
+        Some("HUP") => 1,
+        Some("INT") => 2,
+        Some("QUIT") => 3,
+        Some("ILL") => 4,
+        Some("ABRT") => 6,
+        Some("FPE") => 8,
+        Some("KILL") => 9,
+        Some("USR1") => 10,
+        Some("SEGV") => 11,
+        Some("USR2") => 12,
+        Some("PIPE") => 13,
+        Some("ALRM") => 14,
+        Some("TERM") => 15,
+        Some(_) => 0,
+    };
+    return 128 + signal_number;
+}
+
+/// How to reach a host through SSH2 : authentication and connection parameters
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ssh2Settings {
+    /// Unset : left to the OpenSSH configuration (SSH agent as the local user if it doesn't say otherwise)
+    pub authmode: Ssh2AuthMode,
+    /// None : the OpenSSH configuration's Port, or 22
+    pub port: Option<u16>,
+    pub key_options: Ssh2KeyOptions,
+    pub host_key: HostKeyVerification,
+    /// Hosts to go through before reaching the target, in order (like OpenSSH's ProxyJump)
+    pub jump_hosts: Vec<JumpHost>,
+    /// Seconds of inactivity before a keepalive message asking for a reply is sent (like OpenSSH's ServerAliveInterval).
+    /// russh disconnects after 3 unanswered ones. libssh2 doesn't count replies : a dead host is only noticed once TCP gives up.
+    #[serde(default)]
+    pub keepalive_interval: Option<u32>,
+    #[serde(default = "Ssh2Timeouts::new")]
+    pub timeouts: Ssh2Timeouts,
+    /// What to do when the session is lost in the middle of a run
+    #[serde(default = "ReconnectPolicy::new")]
+    pub reconnect: ReconnectPolicy,
+    /// HostName, Port, User, IdentityFile, ProxyJump and ServerAliveInterval are taken from this OpenSSH configuration when the handler is built
+    pub ssh_config: Option<SshConfigSource>,
+    /// Implementation of the protocol, used for the jump hosts as well
+    #[serde(default)]
+    pub backend: SshBackend,
+}
+
+impl Ssh2Settings {
+    pub fn from(authmode: Ssh2AuthMode) -> Ssh2Settings {
+        Ssh2Settings {
+            authmode,
+            port: None,
+            key_options: Ssh2KeyOptions::new(),
+            host_key: HostKeyVerification::new(),
+            jump_hosts: Vec::new(),
+            keepalive_interval: None,
+            timeouts: Ssh2Timeouts::new(),
+            reconnect: ReconnectPolicy::new(),
+            ssh_config: None,
+            backend: SshBackend::default(),
+        }
+    }
+
+    /// Port to connect to, 22 unless set
+    pub fn port_or_default(&self) -> u16 {
+        self.port.unwrap_or(DEFAULT_SSH2_PORT)
+    }
+
+    /// Like OpenSSH, values set explicitly take precedence over the configuration, which only fills the ones left unset :
+    /// port, authentication (SSH agent as the local user otherwise), keepalive and jump hosts. HostName always applies.
+    /// Returns the address to connect to (HostName) along with the resolved settings.
+    pub fn resolve_ssh_config(self, host: &str) -> Result<(String, Ssh2Settings), Error> {
+        let config = match &self.ssh_config {
+            Some(source) => source.load()?,
+            None => return Ok((host.to_string(), self)),
+        };
+
+        let config_host = config.resolve(host);
+        let proxy_jump = config_host.proxy_jump.clone();
+        let (hostaddress, mut settings) = self.with_ssh_config_host(&config_host, host);
+
+        match proxy_jump.as_deref() {
+            None | Some("none") => {}
+            Some(_) if !settings.jump_hosts.is_empty() => {}
+            Some(proxy_jump) => {
+                let mut jump_hosts: Vec<JumpHost> = Vec::new();
+                // [user@]host[:port], comma-separated
+                for hop in proxy_jump.split(',') {
+                    let (hop_user, hop_host_and_port) = match hop.rsplit_once('@') {
+                        Some((hop_user, hop_host_and_port)) => (Some(hop_user), hop_host_and_port),
+                        None => (None, hop),
+                    };
+                    let (hop_host, hop_port) = match hop_host_and_port.rsplit_once(':') {
+                        Some((hop_host, hop_port)) => match hop_port.parse::<u16>() {
+                            Ok(hop_port) => (hop_host, Some(hop_port)),
+                            Err(_) => {
+                                return Err(Error::Connection(ConnectionError::SshConfigInvalid {
+                                    source: source_description(&settings.ssh_config),
+                                    details: format!("invalid port in ProxyJump {}", hop),
+                                }));
+                            }
+                        },
+                        None => (hop_host_and_port, None),
+                    };
+
+                    // Jump hosts are resolved through the configuration as well, with the same host key verification.
+                    // The user and port given in ProxyJump are explicit.
+                    let jump_base = Ssh2Settings {
+                        port: hop_port,
+                        host_key: settings.host_key.clone(),
+                        keepalive_interval: settings.keepalive_interval,
+                        timeouts: settings.timeouts.clone(),
+                        ..Ssh2Settings::from(match hop_user {
+                            Some(hop_user) => Ssh2AuthMode::Agent((hop_user.to_string(), None)),
+                            None => Ssh2AuthMode::Unset,
+                        })
+                    };
+                    let (hop_address, hop_settings) =
+                        jump_base.with_ssh_config_host(&config.resolve(hop_host), hop_host);
+                    jump_hosts.push(JumpHost {
+                        address: hop_address,
+                        settings: hop_settings,
+                    });
+                }
+                settings.jump_hosts = jump_hosts;
+            }
+        }
+
+        Ok((hostaddress, settings))
+    }
+
+    fn with_ssh_config_host(
+        self,
+        config_host: &SshConfigHost,
+        host: &str,
+    ) -> (String, Ssh2Settings) {
+        let default_authmode = self.authmode == Ssh2AuthMode::Unset;
+        let username = if default_authmode {
+            config_host.user.clone().or(self.authmode.username())
+        } else {
+            self.authmode.username()
+        }
+        .unwrap_or_else(local_username);
+        let hostaddress = match &config_host.hostname {
+            Some(hostname) => expand_tokens(hostname, host, &username),
+            None => host.to_string(),
+        };
+        // Like OpenSSH, identity files which don't exist are skipped
+        let identity_file = if default_authmode {
+            config_host
+                .identity_files
+                .iter()
+                .map(|identity_file| expand_tokens(identity_file, &hostaddress, &username))
+                .find(|identity_file| Path::new(identity_file).exists())
+        } else {
+            None
+        };
+
+        let port = self.port.or(config_host.port).unwrap_or(DEFAULT_SSH2_PORT);
+        let keepalive_interval = match (self.keepalive_interval, config_host.server_alive_interval)
+        {
+            (None, Some(interval)) if interval > 0 => Some(interval),
+            (keepalive_interval, _) => keepalive_interval,
+        };
+        let mut settings = match identity_file {
+            Some(identity_file) => self.with_key_file(&username, &identity_file),
+            None if default_authmode => Ssh2Settings {
+                authmode: Ssh2AuthMode::Agent((username, None)),
+                ..self
+            },
+            None => Ssh2Settings {
+                authmode: self.authmode.with_username(&username),
+                ..self
+            },
+        };
+        settings.port = Some(port);
+        settings.keepalive_interval = keepalive_interval;
+
+        (hostaddress, settings)
+    }
+
+    /// Switches to another private key, keeping the port and host key verification. Only the passphrase callback is kept from the key options.
+    pub fn with_key_file(self, username: &str, privatekeypath: &str) -> Ssh2Settings {
+        Ssh2Settings {
+            authmode: Ssh2AuthMode::KeyFile((username.to_string(), PathBuf::from(privatekeypath))),
+            key_options: Ssh2KeyOptions {
+                passphrase_callback: self.key_options.passphrase_callback.clone(),
+                ..Ssh2KeyOptions::new()
+            },
+            ..self
+        }
+    }
+}
+
+/// Which implementation of SSHv2 talks to the host. Both behave the same for Jobs.
+/// The default is libssh2, or russh when duxcore is built without libssh2.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum SshBackend {
+    /// libssh2 (C library) through the ssh2 crate (cargo feature "ssh2", enabled by default)
+    #[cfg(feature = "ssh2")]
+    #[default]
+    Libssh2,
+    /// russh, written in Rust (cargo feature "russh")
+    #[cfg(feature = "russh")]
+    #[cfg_attr(not(feature = "ssh2"), default)]
+    Russh,
+}
+
+/// None means no time limit
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ssh2Timeouts {
+    /// Establishing the TCP connection
+    pub connect: Option<Duration>,
+    /// Each step of the SSH handshake and authentication, not both together : libssh2 applies it to each exchange
+    /// with the server, russh to the handshake and then to the authentication
+    pub handshake: Option<Duration>,
+    /// Waiting for a command's output without receiving anything
+    pub read: Option<Duration>,
+}
+
+impl Ssh2Timeouts {
+    /// 30 seconds to connect, 60 seconds for each handshake and authentication step, no limit for commands
+    pub fn new() -> Ssh2Timeouts {
+        Ssh2Timeouts {
+            connect: Some(Duration::from_secs(30)),
+            handshake: Some(Duration::from_secs(60)),
+            read: None,
+        }
+    }
+}
+
+/// Where the OpenSSH configuration is read from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SshConfigSource {
+    /// ~/.ssh/config of the user running the Job (considered empty if it doesn't exist)
+    UserConfig,
+    File(PathBuf),
+}
+
+impl SshConfigSource {
+    fn load(&self) -> Result<SshConfig, Error> {
+        let path = match self {
+            SshConfigSource::UserConfig => {
+                let path = crate::connection::sshconfig::home_directory()
+                    .map(|home| home.join(".ssh").join("config"));
+                match path {
+                    Some(path) if path.exists() => path,
+                    // Without home directory, there is no user configuration either
+                    _ => return Ok(SshConfig::new()),
+                }
+            }
+            SshConfigSource::File(path) => path.clone(),
+        };
+
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) => {
+                return Err(Error::Connection(ConnectionError::SshConfigUnreadable {
+                    path,
+                    source: e,
+                }));
+            }
+        };
+        match content.parse::<SshConfig>() {
+            Ok(config) => Ok(config),
+            Err(details) => Err(Error::Connection(ConnectionError::SshConfigInvalid {
+                source: path.display().to_string(),
+                details,
+            })),
+        }
+    }
+}
+
+fn source_description(ssh_config: &Option<SshConfigSource>) -> String {
+    match ssh_config {
+        Some(SshConfigSource::File(path)) => path.display().to_string(),
+        _ => "~/.ssh/config".to_string(),
+    }
+}
+
+/// A host to go through, with its own connection parameters (its own jump hosts are not considered)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JumpHost {
+    pub address: String,
+    pub settings: Ssh2Settings,
+}
+
+/// How the host key presented by the server is checked, before authenticating
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostKeyVerification {
+    pub policy: HostKeyPolicy,
+    pub known_hosts: KnownHostsStore,
+}
+
+impl HostKeyVerification {
+    /// Default is AcceptNew with the current user's known_hosts file, like OpenSSH's StrictHostKeyChecking=accept-new
+    pub fn new() -> HostKeyVerification {
+        HostKeyVerification {
+            policy: HostKeyPolicy::AcceptNew,
+            known_hosts: KnownHostsStore::File(None),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum HostKeyPolicy {
+    /// Only hosts already in the store are accepted
+    Strict,
+    /// Unknown hosts are accepted and added to the store, changed host keys are refused
+    AcceptNew,
+    /// No verification : the fingerprint is only recorded
+    Off,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum KnownHostsStore {
+    /// OpenSSH known_hosts file (~/.ssh/known_hosts if None)
+    File(Option<PathBuf>),
+    /// Lines in the OpenSSH known_hosts format. Hosts accepted with AcceptNew are not kept after the connection.
+    Memory(Vec<String>),
+}
+
+/// Used with KeyFile and KeyMemory authentications
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ssh2KeyOptions {
+    /// Public key or certificate matching the private key (deduced from the private key if None)
+    pub public_key: Option<PathBuf>,
+    /// Passphrase of an encrypted private key. It is serialized with the Job : prefer passphrase_callback to keep it out of it.
+    pub passphrase: Option<String>,
+    /// Asked for the passphrase when connecting, if no passphrase is given. Never serialized.
+    #[serde(skip)]
+    pub passphrase_callback: Option<PassphraseCallback>,
+}
+
+impl Ssh2KeyOptions {
+    pub fn new() -> Ssh2KeyOptions {
+        Ssh2KeyOptions {
+            public_key: None,
+            passphrase: None,
+            passphrase_callback: None,
+        }
+    }
+
+    // A given passphrase takes precedence over the callback
+    pub(crate) fn resolve_passphrase(&self, key_description: &str) -> Option<String> {
+        match (&self.passphrase, &self.passphrase_callback) {
+            (Some(passphrase), _) => Some(passphrase.clone()),
+            (None, Some(callback)) => (callback.0)(key_description),
+            (None, None) => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for Ssh2KeyOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Ssh2KeyOptions {{ public_key: {:?}, passphrase: {}, passphrase_callback: {:?} }}",
+            self.public_key,
+            match self.passphrase {
+                Some(_) => "Some(\"HIDDEN PASSPHRASE\")",
+                None => "None",
+            },
+            self.passphrase_callback
+        )
+    }
+}
+
+/// Gets the description of the key (path or username for in-memory keys) and returns its passphrase, if any
+#[derive(Clone)]
+pub struct PassphraseCallback(pub Arc<PassphraseFn>);
+
+pub type PassphraseFn = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+impl PassphraseCallback {
+    pub fn from<F>(callback: F) -> PassphraseCallback
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        PassphraseCallback(Arc::new(callback))
+    }
+}
+
+impl std::fmt::Debug for PassphraseCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "PassphraseCallback")
+    }
+}
+
+impl PartialEq for PassphraseCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[cfg(feature = "ssh2")]
 #[derive(Clone)]
 pub struct Ssh2HostHandler {
     pub hostaddress: String,
     pub sshsession: Session,
     pub authmode: Ssh2AuthMode,
+    pub port: u16,
+    pub key_options: Ssh2KeyOptions,
+    pub host_key: HostKeyVerification,
+    pub jump_hosts: Vec<JumpHost>,
+    pub keepalive_interval: Option<u32>,
+    pub timeouts: Ssh2Timeouts,
+    pub reconnect_policy: ReconnectPolicy,
+    pub report: ConnectionReport,
+    forwarders: Arc<Vec<JumpForwarder>>, // Stopped once the session and its clones are gone
 }
 
+#[cfg(feature = "ssh2")]
 impl Ssh2HostHandler {
     pub fn new() -> Ssh2HostHandler {
         Ssh2HostHandler {
             hostaddress: String::new(),
             sshsession: Session::new().unwrap(),
             authmode: Ssh2AuthMode::Unset,
+            port: DEFAULT_SSH2_PORT,
+            key_options: Ssh2KeyOptions::new(),
+            host_key: HostKeyVerification::new(),
+            jump_hosts: Vec::new(),
+            keepalive_interval: None,
+            timeouts: Ssh2Timeouts::new(),
+            reconnect_policy: ReconnectPolicy::new(),
+            report: ConnectionReport::new(),
+            forwarders: Arc::new(Vec::new()),
         }
     }
 
@@ -46,14 +502,41 @@ impl Ssh2HostHandler {
             hostaddress: String::from(""),
             sshsession: Session::new().unwrap(), // TODO: remove this unnecessary construction
             authmode: Ssh2AuthMode::Unset,
+            port: DEFAULT_SSH2_PORT,
+            key_options: Ssh2KeyOptions::new(),
+            host_key: HostKeyVerification::new(),
+            jump_hosts: Vec::new(),
+            keepalive_interval: None,
+            timeouts: Ssh2Timeouts::new(),
+            reconnect_policy: ReconnectPolicy::new(),
+            report: ConnectionReport::new(),
+            forwarders: Arc::new(Vec::new()),
         }
     }
 
-    pub fn from(hostaddress: String, authmode: Ssh2AuthMode) -> Ssh2HostHandler {
-        Ssh2HostHandler {
-            hostaddress,
-            sshsession: Session::new().unwrap(),
-            authmode,
+    /// With an OpenSSH configuration, the address and settings are resolved for this host first
+    pub fn from(hostaddress: String, settings: Ssh2Settings) -> Result<Ssh2HostHandler, Error> {
+        let (hostaddress, settings) = settings.resolve_ssh_config(&hostaddress)?;
+        let port = settings.port_or_default();
+        match Session::new() {
+            Ok(sshsession) => Ok(Ssh2HostHandler {
+                hostaddress,
+                sshsession,
+                authmode: settings.authmode,
+                port,
+                key_options: settings.key_options,
+                host_key: settings.host_key,
+                jump_hosts: settings.jump_hosts,
+                keepalive_interval: settings.keepalive_interval,
+                timeouts: settings.timeouts,
+                reconnect_policy: settings.reconnect,
+                report: ConnectionReport::new(),
+                forwarders: Arc::new(Vec::new()),
+            }),
+            Err(e) => Err(Error::Connection(ConnectionError::SessionFailed {
+                address: hostaddress,
+                source: SshError::Libssh2(e),
+            })),
         }
     }
 
@@ -64,78 +547,120 @@ impl Ssh2HostHandler {
 
     pub fn init(&mut self) -> Result<(), Error> {
         if self.authmode == Ssh2AuthMode::Unset {
-            return Err(Error::MissingInitialization(
+            return Err(Error::Connection(ConnectionError::Unset(
                 "SSH2 authentication mode is unset".to_string(),
-            ));
-        } else {
-            // TODO : add SSH custom port handling
-            match TcpStream::connect(format!("{}:22", self.hostaddress)) {
-                Ok(tcp) => {
-                    self.sshsession.set_tcp_stream(tcp);
-                    self.sshsession.handshake().unwrap();
-
-                    match &self.authmode {
-                        Ssh2AuthMode::UsernamePassword(credentials) => {
-                            self.sshsession
-                                .userauth_password(&credentials.username, &credentials.password)
-                                .unwrap();
-                            if self.sshsession.authenticated() {
-                                return Ok(());
-                            } else {
-                                return Err(Error::FailedInitialization(String::from(
-                                    "PLACEHOLDER",
-                                )));
-                            }
-                        }
-                        Ssh2AuthMode::KeyFile((username, privatekeypath)) => {
-                            self.sshsession
-                                .userauth_pubkey_file(
-                                    username.as_str(),
-                                    None,
-                                    &privatekeypath,
-                                    None,
-                                )
-                                .unwrap(); // TODO : add pubkey and passphrase support
-                            if self.sshsession.authenticated() {
-                                return Ok(());
-                            } else {
-                                return Err(Error::FailedInitialization(String::from(
-                                    "PLACEHOLDER",
-                                )));
-                            }
-                        }
-                        Ssh2AuthMode::KeyMemory((username, pem)) => {
-                            self.sshsession
-                                .userauth_pubkey_memory(
-                                    username.as_str(),
-                                    None,
-                                    pem.to_string().as_str(), // Pem struct doesn't implement directly '.as_str()' but accepts '.to_string()'
-                                    None,
-                                )
-                                .unwrap(); // TODO : add pubkey and passphrase support
-                            if self.sshsession.authenticated() {
-                                return Ok(());
-                            } else {
-                                return Err(Error::FailedInitialization(String::from(
-                                    "PLACEHOLDER",
-                                )));
-                            }
-                        }
-                        Ssh2AuthMode::Agent(_agent) => {
-                            return Ok(());
-                        } // TODO
-                        _ => return Err(Error::FailedInitialization(String::from("Other error"))),
-                    }
-                }
+            )));
+        }
+
+        // Each jump host is reached through the previous one, the target through the last one
+        self.forwarders = Arc::new(Vec::new());
+        let mut forwarders: Vec<JumpForwarder> = Vec::new();
+        let mut previous_hop: Option<(Session, String)> = None;
+        for jump_host in self.jump_hosts.iter() {
+            let mut jump_session = match Session::new() {
+                Ok(jump_session) => jump_session,
                 Err(e) => {
-                    return Err(Error::FailedTcpBinding(format!("{:?}", e)));
+                    return Err(Error::Connection(ConnectionError::SessionFailed {
+                        address: jump_host.address.clone(),
+                        source: SshError::Libssh2(e),
+                    }));
                 }
+            };
+            let timeouts = &jump_host.settings.timeouts;
+            jump_session.set_timeout(timeout_as_millis(timeouts.handshake));
+            forwarders.extend(attach_stream(
+                &mut jump_session,
+                previous_hop.as_ref(),
+                &jump_host.address,
+                jump_host.settings.port_or_default(),
+                timeouts.connect,
+            )?);
+            handshake(
+                &mut jump_session,
+                &jump_host.address,
+                jump_host.settings.port_or_default(),
+                &jump_host.settings.host_key,
+            )
+            .map_err(|e| timed_out(e, &jump_host.address, "SSH handshake", timeouts.handshake))?;
+            if let Some(interval) = jump_host.settings.keepalive_interval {
+                jump_session.set_keepalive(true, interval);
             }
+            authenticate(
+                &jump_session,
+                &jump_host.address,
+                &jump_host.settings.authmode,
+                &jump_host.settings.key_options,
+            )
+            .map_err(|e| timed_out(e, &jump_host.address, "authentication", timeouts.handshake))?;
+            previous_hop = Some((jump_session, jump_host.address.clone()));
         }
+
+        self.sshsession
+            .set_timeout(timeout_as_millis(self.timeouts.handshake));
+        forwarders.extend(attach_stream(
+            &mut self.sshsession,
+            previous_hop.as_ref(),
+            &self.hostaddress,
+            self.port,
+            self.timeouts.connect,
+        )?);
+        self.forwarders = Arc::new(forwarders);
+        let fingerprint = handshake(
+            &mut self.sshsession,
+            &self.hostaddress,
+            self.port,
+            &self.host_key,
+        )
+        .map_err(|e| {
+            timed_out(
+                e,
+                &self.hostaddress,
+                "SSH handshake",
+                self.timeouts.handshake,
+            )
+        })?;
+        self.report.host_key_fingerprint = Some(fingerprint);
+        if let Some(interval) = self.keepalive_interval {
+            self.sshsession.set_keepalive(true, interval);
+        }
+        self.report.authenticated_with = authenticate(
+            &self.sshsession,
+            &self.hostaddress,
+            &self.authmode,
+            &self.key_options,
+        )
+        .map_err(|e| {
+            timed_out(
+                e,
+                &self.hostaddress,
+                "authentication",
+                self.timeouts.handshake,
+            )
+        })?;
+
+        // From now on, the remaining blocking calls (opening and closing channels) are limited by the read timeout
+        self.sshsession
+            .set_timeout(timeout_as_millis(self.timeouts.read));
+
+        return Ok(());
+    }
+
+    /// A new session replaces the lost one : same settings, same checks (host key...)
+    pub fn reconnect(&mut self) -> Result<(), Error> {
+        self.sshsession = match Session::new() {
+            Ok(sshsession) => sshsession,
+            Err(e) => {
+                return Err(Error::Connection(ConnectionError::SessionFailed {
+                    address: self.hostaddress.clone(),
+                    source: SshError::Libssh2(e),
+                }));
+            }
+        };
+        self.init()
     }
 
     pub fn is_this_cmd_available(&self, cmd: &str) -> Result<bool, Error> {
-        let check_cmd_content = format!("command -v {}", cmd);
+        let check_cmd_content = Argv::new("command").arg("-v").arg(cmd).to_shell();
         let check_cmd_result = self.run_cmd(check_cmd_content.as_str());
 
         match check_cmd_result {
@@ -147,44 +672,843 @@ impl Ssh2HostHandler {
                 }
             }
             Err(e) => {
-                return Err(Error::FailureToRunCommand(format!("{:?}", e)));
+                return Err(e);
             }
         }
     }
 
     pub fn run_cmd(&self, cmd: &str) -> Result<CmdResult, Error> {
+        self.run_cmd_with_input(cmd, None, None)
+    }
+
+    /// stdin is written to the command as is, followed by EOF. Each line of output is given to on_line as soon as it is received.
+    pub fn run_cmd_with_input(
+        &self,
+        cmd: &str,
+        stdin: Option<&[u8]>,
+        on_line: Option<&OnLine<'_>>,
+    ) -> Result<CmdResult, Error> {
         if let Ssh2AuthMode::Unset = self.authmode {
-            return Err(Error::MissingInitialization(
+            return Err(Error::Connection(ConnectionError::Unset(
                 "Can't run command on remote host : authentication unset".to_string(),
-            ));
+            )));
         }
 
-        match self.sshsession.channel_session() {
-            Ok(mut channel) => {
-                channel.exec(cmd).unwrap();
-                let mut s = String::new();
-                channel.read_to_string(&mut s).unwrap();
-                channel.wait_close().unwrap();
+        let session_failed = |e: ssh2::Error| {
+            Error::Connection(ConnectionError::SessionFailed {
+                address: self.hostaddress.clone(),
+                source: SshError::Libssh2(e),
+            })
+        };
 
-                return Ok(CmdResult {
-                    rc: channel.exit_status().unwrap(),
-                    stdout: s,
-                });
+        // Only sent if the keepalive interval is set and elapsed
+        let _ = self.sshsession.keepalive_send();
+        let mut channel = self.sshsession.channel_session().map_err(session_failed)?;
+        channel.exec(cmd).map_err(session_failed)?;
+
+        let (stdout, stderr) = self.exchange(&mut channel, stdin, on_line)?;
+        channel.wait_close().map_err(session_failed)?;
+
+        // exit_status() is 0 when none was received : only trusted without exit signal
+        let exit_signal = channel.exit_signal().map_err(session_failed)?.exit_signal;
+        let exit_status = match exit_signal {
+            Some(_) => None,
+            None => Some(channel.exit_status().map_err(session_failed)? as u32),
+        };
+
+        return Ok(CmdResult {
+            rc: remote_exit_code(exit_status, exit_signal.as_deref()),
+            stdout: String::from_utf8_lossy(&stdout).to_string(),
+            stderr: String::from_utf8_lossy(&stderr).to_string(),
+        });
+    }
+
+    /// Written through SFTP to a temporary file next to the destination, then renamed. Without a mode, the permissions of the replaced file are kept.
+    pub fn upload(
+        &self,
+        content: &[u8],
+        remote_path: &str,
+        mode: Option<u32>,
+    ) -> Result<(), Error> {
+        let transfer_failed = |e: std::io::Error| {
+            Error::Connection(ConnectionError::TransferFailed {
+                address: self.hostaddress.clone(),
+                path: remote_path.to_string(),
+                source: e,
+            })
+        };
+        let sftp = self
+            .sshsession
+            .sftp()
+            .map_err(|e| transfer_failed(e.into()))?;
+        let path = Path::new(remote_path);
+        let temp = temp_path(path);
+
+        let perm = match mode {
+            Some(mode) => mode,
+            None => match sftp.stat(path) {
+                Ok(stat) => stat.perm.map_or(DEFAULT_FILE_MODE, |perm| perm & 0o7777),
+                Err(_) => DEFAULT_FILE_MODE,
+            },
+        };
+
+        let write_temp = || -> Result<(), std::io::Error> {
+            let mut file = sftp.open_mode(
+                &temp,
+                OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE,
+                0o600,
+                OpenType::File,
+            )?;
+            file.write_all(content)?;
+            // Not subject to the server's umask, unlike the mode given when opening
+            file.setstat(FileStat {
+                size: None,
+                uid: None,
+                gid: None,
+                perm: Some(perm),
+                atime: None,
+                mtime: None,
+            })?;
+            Ok(())
+        };
+        if let Err(e) = write_temp() {
+            let _ = sftp.unlink(&temp);
+            return Err(transfer_failed(e));
+        }
+
+        // SFTP renames (v3) refuse to replace an existing file
+        let rename = self.run_cmd(
+            Argv::new("mv")
+                .args(["-f", "--"])
+                .arg(temp.display().to_string())
+                .arg(remote_path)
+                .to_shell()
+                .as_str(),
+        )?;
+        if rename.rc != 0 {
+            let _ = sftp.unlink(&temp);
+            return Err(transfer_failed(std::io::Error::other(
+                rename.stderr.trim().to_string(),
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn download(&self, remote_path: &str) -> Result<Vec<u8>, Error> {
+        let transfer_failed = |e: std::io::Error| {
+            Error::Connection(ConnectionError::TransferFailed {
+                address: self.hostaddress.clone(),
+                path: remote_path.to_string(),
+                source: e,
+            })
+        };
+        let sftp = self
+            .sshsession
+            .sftp()
+            .map_err(|e| transfer_failed(e.into()))?;
+        let mut file = sftp
+            .open(Path::new(remote_path))
+            .map_err(|e| transfer_failed(e.into()))?;
+        let mut content: Vec<u8> = Vec::new();
+        file.read_to_end(&mut content).map_err(transfer_failed)?;
+        Ok(content)
+    }
+
+    // stdin is written while stdout and stderr are read alternately : the server stops sending when
+    // the unread one fills the channel's window, and the command may not read more input until its output is read.
+    fn exchange(
+        &self,
+        channel: &mut Channel,
+        mut stdin: Option<&[u8]>,
+        on_line: Option<&OnLine<'_>>,
+    ) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        let mut stdout: Vec<u8> = Vec::new();
+        let mut stderr: Vec<u8> = Vec::new();
+        let mut stdout_lines = LineBuffer::new();
+        let mut stderr_lines = LineBuffer::new();
+        let mut buffer = [0u8; 32768];
+        let mut last_received = Instant::now();
+
+        self.sshsession.set_blocking(false);
+        let result = loop {
+            let mut idle = true;
+            let mut read_failed = false;
+            let mut write_failed = false;
+
+            if let Some(pending) = stdin {
+                if pending.is_empty() {
+                    match channel.send_eof() {
+                        Ok(()) => stdin = None,
+                        Err(e) if e.code() == ssh2::ErrorCode::Session(-37) => {} // LIBSSH2_ERROR_EAGAIN
+                        Err(_) => write_failed = true,
+                    }
+                } else {
+                    match channel.write(pending) {
+                        Ok(size) => {
+                            stdin = Some(&pending[size..]);
+                            idle = false;
+                        }
+                        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                        Err(_) => write_failed = true,
+                    }
+                }
             }
-            Err(e) => {
-                return Err(Error::FailureToEstablishConnection(format!("{e}")));
+            if write_failed {
+                // LIBSSH2_ERROR_SOCKET_SEND
+                break Err(Error::Connection(ConnectionError::SessionFailed {
+                    address: self.hostaddress.clone(),
+                    source: ssh2::Error::new(
+                        ssh2::ErrorCode::Session(-7),
+                        "Unable to send command input",
+                    )
+                    .into(),
+                }));
+            }
+
+            for (stream_id, output, lines, kind) in [
+                (0, &mut stdout, &mut stdout_lines, OutputStream::Stdout),
+                (
+                    ssh2::EXTENDED_DATA_STDERR,
+                    &mut stderr,
+                    &mut stderr_lines,
+                    OutputStream::Stderr,
+                ),
+            ] {
+                match channel.stream(stream_id).read(&mut buffer) {
+                    Ok(0) => {}
+                    Ok(size) => {
+                        output.extend_from_slice(&buffer[..size]);
+                        if let Some(on_line) = on_line {
+                            lines.push(&buffer[..size], &mut |line| on_line(kind, line));
+                        }
+                        idle = false;
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(_) => read_failed = true,
+                }
+            }
+
+            if read_failed {
+                // LIBSSH2_ERROR_SOCKET_RECV
+                break Err(Error::Connection(ConnectionError::SessionFailed {
+                    address: self.hostaddress.clone(),
+                    source: ssh2::Error::new(
+                        ssh2::ErrorCode::Session(-43),
+                        "Unable to read command output",
+                    )
+                    .into(),
+                }));
+            }
+            if !idle {
+                last_received = Instant::now();
+                continue;
+            }
+            // Nothing left to read once the server sent EOF
+            if channel.eof() {
+                if let Some(on_line) = on_line {
+                    stdout_lines.flush(&mut |line| on_line(OutputStream::Stdout, line));
+                    stderr_lines.flush(&mut |line| on_line(OutputStream::Stderr, line));
+                }
+                break Ok((stdout, stderr));
+            }
+            let mut wait = SOCKET_WAIT_LIMIT;
+            if let Some(read_timeout) = self.timeouts.read {
+                match read_timeout.checked_sub(last_received.elapsed()) {
+                    Some(remaining) if !remaining.is_zero() => wait = wait.min(remaining),
+                    _ => {
+                        break Err(Error::Connection(ConnectionError::TimedOut {
+                            address: self.hostaddress.clone(),
+                            operation: "waiting for command output".to_string(),
+                            after: read_timeout,
+                        }));
+                    }
+                }
+            }
+            // Only sent if the keepalive interval is set and elapsed
+            let _ = self.sshsession.keepalive_send();
+            wait_for_sockets(
+                &[(
+                    self.sshsession.as_raw_fd(),
+                    self.sshsession.block_directions(),
+                )],
+                wait,
+            );
+        };
+        self.sshsession.set_blocking(true);
+
+        result
+    }
+}
+
+// The session goes either straight to the host or through a direct-tcpip channel opened on the previous hop's session
+#[cfg(feature = "ssh2")]
+fn attach_stream(
+    session: &mut Session,
+    previous_hop: Option<&(Session, String)>,
+    address: &str,
+    port: u16,
+    connect_timeout: Option<Duration>,
+) -> Result<Option<JumpForwarder>, Error> {
+    match previous_hop {
+        None => {
+            let tcp = connect(address, port, connect_timeout)?;
+            session.set_tcp_stream(tcp);
+            Ok(None)
+        }
+        Some((jump_session, jump_address)) => {
+            let channel = match jump_session.channel_direct_tcpip(address, port, None) {
+                Ok(channel) => channel,
+                Err(e) => {
+                    return Err(Error::Connection(ConnectionError::JumpHostFailed {
+                        jump_host: jump_address.clone(),
+                        target: address.to_string(),
+                        source: SshError::Libssh2(e),
+                    }));
+                }
+            };
+            // libssh2 needs a real socket : one end is given to the session, the other one is bridged with the channel
+            let (session_end, channel_end) = match UnixStream::pair() {
+                Ok(pair) => pair,
+                Err(e) => {
+                    return Err(Error::Connection(ConnectionError::HostUnreachable {
+                        address: address.to_string(),
+                        source: e,
+                    }));
+                }
+            };
+            let jump_session = jump_session.clone();
+            let stop = Arc::new(AtomicBool::new(false));
+            let thread_stop = stop.clone();
+            let thread = std::thread::spawn(move || {
+                forward_through_jump_host(jump_session, channel, channel_end, &thread_stop)
+            });
+            session.set_tcp_stream(session_end);
+            Ok(Some(JumpForwarder {
+                stop,
+                thread: Some(thread),
+            }))
+        }
+    }
+}
+
+#[cfg(feature = "ssh2")]
+fn connect(address: &str, port: u16, timeout: Option<Duration>) -> Result<TcpStream, Error> {
+    let unreachable = |e: std::io::Error| {
+        Error::Connection(ConnectionError::HostUnreachable {
+            address: address.to_string(),
+            source: e,
+        })
+    };
+
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return TcpStream::connect((address, port)).map_err(unreachable),
+    };
+
+    // connect_timeout() only takes a resolved address : each one is tried in turn
+    let mut last_error =
+        std::io::Error::new(std::io::ErrorKind::NotFound, "address resolved to nothing");
+    for socket_address in (address, port).to_socket_addrs().map_err(unreachable)? {
+        match TcpStream::connect_timeout(&socket_address, timeout) {
+            Ok(tcp) => return Ok(tcp),
+            Err(e) => last_error = e,
+        }
+    }
+
+    if last_error.kind() == std::io::ErrorKind::TimedOut {
+        Err(Error::Connection(ConnectionError::TimedOut {
+            address: address.to_string(),
+            operation: "connection".to_string(),
+            after: timeout,
+        }))
+    } else {
+        Err(unreachable(last_error))
+    }
+}
+
+// 0 means no timeout for libssh2
+#[cfg(feature = "ssh2")]
+fn timeout_as_millis(timeout: Option<Duration>) -> u32 {
+    match timeout {
+        Some(timeout) => timeout.as_millis().clamp(1, u32::MAX as u128) as u32,
+        None => 0,
+    }
+}
+
+// libssh2 gives up with LIBSSH2_ERROR_TIMEOUT once the session's timeout expires
+#[cfg(feature = "ssh2")]
+fn timed_out(error: Error, address: &str, operation: &str, timeout: Option<Duration>) -> Error {
+    let source = match &error {
+        Error::Connection(ConnectionError::HandshakeFailed {
+            source: SshError::Libssh2(source),
+            ..
+        })
+        | Error::Connection(ConnectionError::AuthenticationFailed {
+            source: Some(SshError::Libssh2(source)),
+            ..
+        }) => source,
+        _ => return error,
+    };
+
+    match timeout {
+        Some(timeout) if source.code() == ssh2::ErrorCode::Session(-9) => {
+            Error::Connection(ConnectionError::TimedOut {
+                address: address.to_string(),
+                operation: operation.to_string(),
+                after: timeout,
+            })
+        }
+        _ => error,
+    }
+}
+
+// The thread bridging a jump host's channel with the next session's socket : stopped and joined when dropped
+#[cfg(feature = "ssh2")]
+struct JumpForwarder {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[cfg(feature = "ssh2")]
+impl Drop for JumpForwarder {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Runs until either side closes or it is stopped : the jump host's session lives as long as the session going through it.
+// In between, it waits for either socket and checks whether it was stopped each time it wakes up.
+#[cfg(feature = "ssh2")]
+fn forward_through_jump_host(
+    jump_session: Session,
+    mut channel: Channel,
+    mut socket: UnixStream,
+    stop: &AtomicBool,
+) {
+    jump_session.set_blocking(false);
+    if socket.set_nonblocking(true).is_err() {
+        return;
+    }
+
+    let session_fd = jump_session.as_raw_fd();
+    let socket_fd = socket.as_raw_fd();
+    let wait_for_session = || {
+        wait_for_sockets(
+            &[(session_fd, jump_session.block_directions())],
+            SOCKET_WAIT_LIMIT,
+        )
+    };
+    let wait_for_socket =
+        || wait_for_sockets(&[(socket_fd, BlockDirections::Outbound)], SOCKET_WAIT_LIMIT);
+
+    let mut buffer = [0u8; 32768];
+    while !stop.load(Ordering::SeqCst) {
+        let mut idle = true;
+
+        match socket.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(size) => {
+                if write_all_nonblocking(&mut channel, &buffer[..size], stop, &wait_for_session)
+                    .is_err()
+                {
+                    break;
+                }
+                idle = false;
             }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(_) => break,
         }
+
+        match channel.read(&mut buffer) {
+            Ok(0) => {
+                if channel.eof() {
+                    break;
+                }
+            }
+            Ok(size) => {
+                if write_all_nonblocking(&mut socket, &buffer[..size], stop, &wait_for_socket)
+                    .is_err()
+                {
+                    break;
+                }
+                idle = false;
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(_) => break,
+        }
+
+        if idle {
+            let _ = jump_session.keepalive_send();
+            wait_for_sockets(
+                &[
+                    (socket_fd, BlockDirections::Inbound),
+                    (session_fd, jump_session.block_directions()),
+                ],
+                SOCKET_WAIT_LIMIT,
+            );
+        }
+    }
+    let _ = channel.close();
+}
+
+// A wait for sockets lasts at most this long : a wakeup missed by poll() only delays the next attempt
+#[cfg(feature = "ssh2")]
+const SOCKET_WAIT_LIMIT: Duration = Duration::from_millis(100);
+
+// Returns once one of the sockets is ready in the directions libssh2 is blocked on (reading if none), or after the timeout
+#[cfg(feature = "ssh2")]
+fn wait_for_sockets(sockets: &[(RawFd, BlockDirections)], timeout: Duration) {
+    let mut poll_fds: Vec<libc::pollfd> = sockets
+        .iter()
+        .map(|(fd, directions)| libc::pollfd {
+            fd: *fd,
+            events: match directions {
+                BlockDirections::Outbound => libc::POLLOUT,
+                BlockDirections::Both => libc::POLLIN | libc::POLLOUT,
+                BlockDirections::Inbound | BlockDirections::None => libc::POLLIN,
+            },
+            revents: 0,
+        })
+        .collect();
+    let timeout_ms = timeout.min(SOCKET_WAIT_LIMIT).as_millis().max(1) as libc::c_int;
+    // SAFETY : poll_fds holds poll_fds.len() initialized entries. A failed or interrupted poll is
+    // handled like a wakeup : the caller tries again.
+    unsafe {
+        libc::poll(
+            poll_fds.as_mut_ptr(),
+            poll_fds.len() as libc::nfds_t,
+            timeout_ms,
+        );
     }
 }
 
+#[cfg(feature = "ssh2")]
+fn write_all_nonblocking<W: Write>(
+    writer: &mut W,
+    mut data: &[u8],
+    stop: &AtomicBool,
+    wait: &dyn Fn(),
+) -> Result<(), std::io::Error> {
+    while !data.is_empty() {
+        match writer.write(data) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(size) => data = &data[size..],
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                if stop.load(Ordering::SeqCst) {
+                    return Err(std::io::ErrorKind::Interrupted.into());
+                }
+                wait();
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Establishes the SSH session and verifies the host key. Returns the host key's fingerprint.
+#[cfg(feature = "ssh2")]
+fn handshake(
+    session: &mut Session,
+    address: &str,
+    port: u16,
+    host_key_verification: &HostKeyVerification,
+) -> Result<String, Error> {
+    if let Err(e) = session.handshake() {
+        return Err(Error::Connection(ConnectionError::HandshakeFailed {
+            address: address.to_string(),
+            source: SshError::Libssh2(e),
+        }));
+    }
+
+    match session.host_key() {
+        Some((host_key, _)) => verify_host_key(address, port, host_key, host_key_verification),
+        None => {
+            // LIBSSH2_ERROR_HOSTKEY_INIT
+            Err(Error::Connection(ConnectionError::HandshakeFailed {
+                address: address.to_string(),
+                source: ssh2::Error::new(
+                    ssh2::ErrorCode::Session(-10),
+                    "No host key presented by the server",
+                )
+                .into(),
+            }))
+        }
+    }
+}
+
+/// Returns the SSH agent identity used, if any
+#[cfg(feature = "ssh2")]
+fn authenticate(
+    session: &Session,
+    address: &str,
+    authmode: &Ssh2AuthMode,
+    key_options: &Ssh2KeyOptions,
+) -> Result<Option<String>, Error> {
+    let (username, auth_result) = match authmode {
+        Ssh2AuthMode::UsernamePassword(credentials) => (
+            credentials.username.clone(),
+            session.userauth_password(&credentials.username, &credentials.password),
+        ),
+        Ssh2AuthMode::KeyFile((username, privatekeypath)) => {
+            let passphrase = key_options.resolve_passphrase(&privatekeypath.display().to_string());
+            (
+                username.clone(),
+                session.userauth_pubkey_file(
+                    username.as_str(),
+                    key_options.public_key.as_deref(),
+                    privatekeypath,
+                    passphrase.as_deref(),
+                ),
+            )
+        }
+        Ssh2AuthMode::KeyMemory((username, pem)) => {
+            // libssh2 only takes the public key's content for in-memory keys
+            let public_key = match &key_options.public_key {
+                Some(path) => match std::fs::read_to_string(path) {
+                    Ok(content) => Some(content),
+                    Err(e) => {
+                        return Err(Error::Connection(ConnectionError::KeyUnreadable {
+                            path: path.clone(),
+                            source: e,
+                        }));
+                    }
+                },
+                None => None,
+            };
+            let passphrase =
+                key_options.resolve_passphrase(&format!("in-memory key of {}", username));
+            (
+                username.clone(),
+                session.userauth_pubkey_memory(
+                    username.as_str(),
+                    public_key.as_deref(),
+                    pem.to_string().as_str(), // Pem struct doesn't implement directly '.as_str()' but accepts '.to_string()'
+                    passphrase.as_deref(),
+                ),
+            )
+        }
+        Ssh2AuthMode::Agent((username, socket_path)) => {
+            let identity =
+                authenticate_with_agent(session, address, username, socket_path.as_deref())?;
+            return Ok(Some(identity));
+        }
+        Ssh2AuthMode::Unset => {
+            return Err(Error::Connection(ConnectionError::Unset(
+                "SSH2 authentication mode is unset".to_string(),
+            )));
+        }
+    };
+
+    match auth_result {
+        Ok(()) if session.authenticated() => Ok(None),
+        Ok(()) => Err(Error::Connection(ConnectionError::AuthenticationFailed {
+            address: address.to_string(),
+            username,
+            source: None,
+        })),
+        Err(e) => Err(Error::Connection(ConnectionError::AuthenticationFailed {
+            address: address.to_string(),
+            username,
+            source: Some(SshError::Libssh2(e)),
+        })),
+    }
+}
+
+/// Checks the host key (as sent by the server, in the SSH wire format) against the known hosts according to the policy and returns its fingerprint
+pub(crate) fn verify_host_key(
+    address: &str,
+    port: u16,
+    host_key: &[u8],
+    verification: &HostKeyVerification,
+) -> Result<String, Error> {
+    let fingerprint = host_key_fingerprint(host_key);
+    if verification.policy == HostKeyPolicy::Off {
+        return Ok(fingerprint);
+    }
+
+    let known_hosts_unavailable = |path: PathBuf, e: std::io::Error| {
+        Error::Connection(ConnectionError::KnownHostsUnavailable { path, source: e })
+    };
+
+    let (known_hosts_lines, known_hosts_path) = match &verification.known_hosts {
+        KnownHostsStore::Memory(lines) => (lines.clone(), None),
+        KnownHostsStore::File(path) => {
+            let path = match path {
+                Some(path) => path.clone(),
+                None => match std::env::var_os("HOME") {
+                    Some(home) => PathBuf::from(home).join(".ssh").join("known_hosts"),
+                    None => {
+                        return Err(known_hosts_unavailable(
+                            PathBuf::from("~/.ssh/known_hosts"),
+                            std::io::Error::new(std::io::ErrorKind::NotFound, "HOME is not set"),
+                        ));
+                    }
+                },
+            };
+            match std::fs::read_to_string(&path) {
+                Ok(content) => (content.lines().map(String::from).collect(), Some(path)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (Vec::new(), Some(path)),
+                Err(e) => return Err(known_hosts_unavailable(path, e)),
+            }
+        }
+    };
+
+    match check_known_host(&known_hosts_lines, address, port, host_key) {
+        KnownHostCheck::Match => Ok(fingerprint),
+        KnownHostCheck::Mismatch => Err(Error::Connection(ConnectionError::HostKeyMismatch {
+            address: address.to_string(),
+            fingerprint,
+        })),
+        KnownHostCheck::NotFound => match verification.policy {
+            HostKeyPolicy::AcceptNew => {
+                if let Some(path) = known_hosts_path {
+                    let line = known_hosts_line(address, port, host_key);
+                    if let Err(e) = append_known_host(&path, &line) {
+                        return Err(known_hosts_unavailable(path, e));
+                    }
+                }
+                Ok(fingerprint)
+            }
+            _ => Err(Error::Connection(ConnectionError::HostKeyUnknown {
+                address: address.to_string(),
+                fingerprint,
+            })),
+        },
+    }
+}
+
+/// Same format as OpenSSH (ex: SHA256:FtKS/g8SiFj2DDQAh06Ryb6fyd23c0CU/XYtNuaef5M)
+fn host_key_fingerprint(host_key: &[u8]) -> String {
+    format!(
+        "SHA256:{}",
+        base64::engine::general_purpose::STANDARD_NO_PAD.encode(Sha256::digest(host_key))
+    )
+}
+
+fn known_hosts_line(address: &str, port: u16, host_key: &[u8]) -> String {
+    format!(
+        "{} {} {}",
+        known_host_name(address, port),
+        key_type(host_key),
+        base64::engine::general_purpose::STANDARD.encode(host_key)
+    )
+}
+
+// Connections to the same new host run in parallel (other Jobs, other processes) : the file is locked and the line
+// is only written if none of them did it meanwhile.
+fn append_known_host(path: &Path, line: &str) -> Result<(), std::io::Error> {
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+    file.lock()?; // Released when the file is closed
+
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    if content.lines().any(|known| known == line) {
+        return Ok(());
+    }
+    if !content.is_empty() && !content.ends_with('\n') {
+        writeln!(file)?;
+    }
+    writeln!(file, "{}", line)
+}
+
+// Every identity of the agent is tried until one is accepted. Returns the comment of this identity (usually the key's file name).
+#[cfg(feature = "ssh2")]
+fn authenticate_with_agent(
+    session: &Session,
+    address: &str,
+    username: &str,
+    socket_path: Option<&Path>,
+) -> Result<String, Error> {
+    let agent_unavailable = |e: ssh2::Error| {
+        Error::Connection(ConnectionError::AgentUnavailable {
+            address: address.to_string(),
+            source: SshError::Libssh2(e),
+        })
+    };
+
+    let (mut agent, identities) =
+        agent_identities(session, socket_path).map_err(agent_unavailable)?;
+
+    let mut tried: Vec<String> = Vec::new();
+    for identity in identities.iter() {
+        if agent.userauth(username, identity).is_ok() && session.authenticated() {
+            let _ = agent.disconnect();
+            return Ok(identity.comment().to_string());
+        }
+        tried.push(identity.comment().to_string());
+    }
+    let _ = agent.disconnect();
+
+    Err(Error::Connection(ConnectionError::AgentIdentitiesRefused {
+        address: address.to_string(),
+        username: username.to_string(),
+        tried,
+    }))
+}
+
+#[cfg(feature = "ssh2")]
+fn agent_identities(
+    session: &Session,
+    socket_path: Option<&Path>,
+) -> Result<(Agent, Vec<PublicKey>), ssh2::Error> {
+    let mut agent = session.agent()?;
+    if let Some(path) = socket_path {
+        agent.set_identity_path(path)?;
+    }
+    agent.connect()?;
+    agent.list_identities()?;
+    let identities = agent.identities()?;
+    Ok((agent, identities))
+}
+
 #[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub enum Ssh2AuthMode {
     Unset,
     UsernamePassword(Credentials),
-    KeyFile((String, PathBuf)), // (username, private key's path)
-    KeyMemory((String, Pem)),   // (username, PEM encoded key from memory)
-    Agent(String),              // Name of SSH agent
+    KeyFile((String, PathBuf)),       // (username, private key's path)
+    KeyMemory((String, Pem)),         // (username, PEM encoded key from memory)
+    Agent((String, Option<PathBuf>)), // (username, agent's socket path, SSH_AUTH_SOCK if None)
+}
+
+impl Ssh2AuthMode {
+    /// Same authentication, as another user. Unset authentication has no username to change.
+    pub fn with_username(self, username: &str) -> Ssh2AuthMode {
+        match self {
+            Ssh2AuthMode::UsernamePassword(credentials) => Ssh2AuthMode::UsernamePassword(
+                Credentials::from(username.to_string(), credentials.password),
+            ),
+            Ssh2AuthMode::KeyFile((_, key_path)) => {
+                Ssh2AuthMode::KeyFile((username.to_string(), key_path))
+            }
+            Ssh2AuthMode::KeyMemory((_, key_content)) => {
+                Ssh2AuthMode::KeyMemory((username.to_string(), key_content))
+            }
+            Ssh2AuthMode::Agent((_, socket_path)) => {
+                Ssh2AuthMode::Agent((username.to_string(), socket_path))
+            }
+            other => other,
+        }
+    }
+
+    pub fn username(&self) -> Option<String> {
+        match self {
+            Ssh2AuthMode::UsernamePassword(credentials) => Some(credentials.username.clone()),
+            Ssh2AuthMode::KeyFile((username, _))
+            | Ssh2AuthMode::KeyMemory((username, _))
+            | Ssh2AuthMode::Agent((username, _)) => Some(username.clone()),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Debug for Ssh2AuthMode {
@@ -202,9 +1526,531 @@ impl std::fmt::Debug for Ssh2AuthMode {
             Ssh2AuthMode::KeyMemory((username, _key_content)) => {
                 write!(f, "KeyMemory(({:?}, \"HIDDEN KEY CONTENT\"))", username)
             }
-            Ssh2AuthMode::Agent(agent_name) => {
-                write!(f, "Agent({:?})", agent_name)
+            Ssh2AuthMode::Agent((username, socket_path)) => {
+                write!(f, "Agent(({:?}, {:?}))", username, socket_path)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "ssh2")]
+    use std::os::unix::net::UnixListener;
+
+    #[cfg(feature = "ssh2")]
+    // Answers the identities request of the SSH agent protocol with the given comments, refuses anything else
+    struct FakeAgent {
+        directory: PathBuf,
+        socket_path: PathBuf,
+    }
+
+    #[cfg(feature = "ssh2")]
+    impl FakeAgent {
+        const REQUEST_IDENTITIES: u8 = 11;
+        const IDENTITIES_ANSWER: u8 = 12;
+        const FAILURE: u8 = 5;
+
+        fn start(name: &str, comments: &[&str]) -> FakeAgent {
+            let directory =
+                std::env::temp_dir().join(format!("dux-agent-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&directory);
+            std::fs::create_dir_all(&directory).unwrap();
+            let socket_path = directory.join("agent.sock");
+            let listener = UnixListener::bind(&socket_path).unwrap();
+
+            let mut answer = vec![FakeAgent::IDENTITIES_ANSWER];
+            answer.extend_from_slice(&(comments.len() as u32).to_be_bytes());
+            for comment in comments {
+                // The key blob is not checked when listing identities
+                for field in [raw_host_key(FIRST_HOST_KEY), comment.as_bytes().to_vec()] {
+                    answer.extend_from_slice(&(field.len() as u32).to_be_bytes());
+                    answer.extend_from_slice(&field);
+                }
+            }
+            // Blocked on accept until the end of the tests
+            std::thread::spawn(move || {
+                for mut stream in listener.incoming().flatten() {
+                    while let Some(request) = FakeAgent::read_message(&mut stream) {
+                        let reply = match request.first() {
+                            Some(&FakeAgent::REQUEST_IDENTITIES) => answer.clone(),
+                            _ => vec![FakeAgent::FAILURE],
+                        };
+                        let mut message = (reply.len() as u32).to_be_bytes().to_vec();
+                        message.extend_from_slice(&reply);
+                        if stream.write_all(&message).is_err() {
+                            break;
+                        }
+                    }
+                }
+            });
+
+            FakeAgent {
+                directory,
+                socket_path,
+            }
+        }
+
+        // Messages are a 4 bytes length followed by the message type and its content
+        fn read_message(stream: &mut UnixStream) -> Option<Vec<u8>> {
+            let mut length = [0u8; 4];
+            stream.read_exact(&mut length).ok()?;
+            let mut message = vec![0u8; u32::from_be_bytes(length) as usize];
+            stream.read_exact(&mut message).ok()?;
+            Some(message)
+        }
+    }
+
+    #[cfg(feature = "ssh2")]
+    impl Drop for FakeAgent {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.directory);
+        }
+    }
+
+    #[test]
+    fn remote_exit_codes_follow_localhost() {
+        assert_eq!(remote_exit_code(Some(3), None), 3);
+        assert_eq!(remote_exit_code(None, Some("TERM")), 143);
+        assert_eq!(remote_exit_code(None, Some("KILL")), 137);
+        assert_eq!(remote_exit_code(None, Some("dux-unknown")), 128);
+        assert_eq!(remote_exit_code(None, None), 0);
+    }
+
+    #[cfg(feature = "ssh2")]
+    #[test]
+    fn agent_identities_are_listed() {
+        let agent = FakeAgent::start("identities", &["dux-first-key", "dux-second-key"]);
+
+        let session = Session::new().unwrap();
+        let (_agent, identities) = agent_identities(&session, Some(&agent.socket_path)).unwrap();
+        let comments: Vec<&str> = identities
+            .iter()
+            .map(|identity| identity.comment())
+            .collect();
+        assert_eq!(comments, vec!["dux-first-key", "dux-second-key"]);
+    }
+
+    #[cfg(feature = "ssh2")]
+    #[test]
+    fn empty_agent_is_reported() {
+        let agent = FakeAgent::start("empty", &[]);
+        let session = Session::new().unwrap();
+
+        match authenticate_with_agent(&session, "10.20.30.51", "deploy", Some(&agent.socket_path)) {
+            Err(error) => {
+                assert!(matches!(
+                    error,
+                    Error::Connection(ConnectionError::AgentIdentitiesRefused { .. })
+                ));
+                assert!(error.to_string().contains("no identity"));
+            }
+            other => panic!("unexpected result : {:?}", other),
+        }
+    }
+
+    #[cfg(feature = "ssh2")]
+    #[test]
+    fn missing_agent_is_reported() {
+        let session = Session::new().unwrap();
+        let socket_path = std::env::temp_dir().join("dux-no-agent-here.sock");
+
+        let result = authenticate_with_agent(&session, "10.20.30.51", "deploy", Some(&socket_path));
+        assert!(matches!(
+            result,
+            Err(Error::Connection(ConnectionError::AgentUnavailable { .. }))
+        ));
+    }
+
+    #[test]
+    fn given_passphrase_takes_precedence_over_callback() {
+        let mut key_options = Ssh2KeyOptions::new();
+        assert_eq!(
+            key_options.resolve_passphrase("/home/deploy/.ssh/id_ed25519"),
+            None
+        );
+
+        key_options.passphrase_callback = Some(PassphraseCallback::from(|key: &str| {
+            Some(format!("asked for {}", key))
+        }));
+        assert_eq!(
+            key_options.resolve_passphrase("/home/deploy/.ssh/id_ed25519"),
+            Some("asked for /home/deploy/.ssh/id_ed25519".into())
+        );
+
+        key_options.passphrase = Some("given".into());
+        assert_eq!(
+            key_options.resolve_passphrase("/home/deploy/.ssh/id_ed25519"),
+            Some("given".into())
+        );
+    }
+
+    #[test]
+    fn passphrase_is_hidden_and_callback_is_not_serialized() {
+        let mut settings = Ssh2Settings::from(Ssh2AuthMode::KeyFile((
+            "deploy".into(),
+            PathBuf::from("/home/deploy/.ssh/id_ed25519"),
+        )));
+        settings.key_options.passphrase = Some("s3cr3t".into());
+        settings.key_options.passphrase_callback =
+            Some(PassphraseCallback::from(|_: &str| Some("s3cr3t".into())));
+
+        assert!(!format!("{:?}", settings).contains("s3cr3t"));
+
+        let serialized = serde_json::to_string(&settings).unwrap();
+        assert!(!serialized.contains("passphrase_callback"));
+        let deserialized: Ssh2Settings = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.key_options.passphrase_callback, None);
+        assert_eq!(deserialized.key_options.passphrase, Some("s3cr3t".into()));
+    }
+
+    #[test]
+    fn settings_saved_by_older_versions_are_read() {
+        let settings = Ssh2Settings::from(Ssh2AuthMode::Agent(("deploy".into(), None)));
+        let mut serialized = serde_json::to_value(&settings).unwrap();
+        for added_later in ["keepalive_interval", "timeouts", "reconnect", "backend"] {
+            serialized.as_object_mut().unwrap().remove(added_later);
+        }
+
+        let deserialized: Ssh2Settings = serde_json::from_value(serialized).unwrap();
+        assert_eq!(deserialized, settings);
+    }
+
+    const FIRST_HOST_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIECMrMQDkWPuuHtggZ03LqxAoKmuZ7tgU9Y3wuMbkWL5";
+    const SECOND_HOST_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIHcOwQ7j1NCw5Mf5ROEyyuAQ9PVk9HZTtEeyOG2BUScm";
+
+    fn raw_host_key(encoded: &str) -> Vec<u8> {
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .unwrap()
+    }
+
+    fn verification(policy: HostKeyPolicy, known_hosts: KnownHostsStore) -> HostKeyVerification {
+        HostKeyVerification {
+            policy,
+            known_hosts,
+        }
+    }
+
+    #[test]
+    fn host_key_fingerprint_matches_openssh() {
+        // ssh-keygen -lf gives SHA256:FtKS/g8SiFj2DDQAh06Ryb6fyd23c0CU/XYtNuaef5M for this key
+        assert_eq!(
+            host_key_fingerprint(&raw_host_key(FIRST_HOST_KEY)),
+            "SHA256:FtKS/g8SiFj2DDQAh06Ryb6fyd23c0CU/XYtNuaef5M"
+        );
+    }
+
+    #[test]
+    fn new_host_is_added_to_known_hosts_file() {
+        let directory =
+            std::env::temp_dir().join(format!("dux-known-hosts-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let known_hosts_path = directory.join("known_hosts");
+        let host_key_verification = verification(
+            HostKeyPolicy::AcceptNew,
+            KnownHostsStore::File(Some(known_hosts_path.clone())),
+        );
+
+        for _ in 0..2 {
+            verify_host_key(
+                "10.20.30.51",
+                2222,
+                &raw_host_key(FIRST_HOST_KEY),
+                &host_key_verification,
+            )
+            .unwrap();
+        }
+        let content = std::fs::read_to_string(&known_hosts_path).unwrap();
+        assert_eq!(
+            content,
+            format!("[10.20.30.51]:2222 ssh-ed25519 {}\n", FIRST_HOST_KEY)
+        );
+
+        // Same host, another key : possible MITM
+        let result = verify_host_key(
+            "10.20.30.51",
+            2222,
+            &raw_host_key(SECOND_HOST_KEY),
+            &host_key_verification,
+        );
+        std::fs::remove_dir_all(&directory).unwrap();
+        match result {
+            Err(Error::Connection(ConnectionError::HostKeyMismatch { fingerprint, .. })) => {
+                assert_eq!(
+                    fingerprint,
+                    "SHA256:mokBnPSlzdaiX0L8NWwJ80vUAjWtdi3DL9ddrBKLuOo"
+                );
+            }
+            other => panic!("unexpected result : {:?}", other),
+        }
+    }
+
+    #[test]
+    fn new_host_is_added_once_by_parallel_connections() {
+        let directory =
+            std::env::temp_dir().join(format!("dux-known-hosts-parallel-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let known_hosts_path = directory.join("known_hosts");
+        let host_key_verification = verification(
+            HostKeyPolicy::AcceptNew,
+            KnownHostsStore::File(Some(known_hosts_path.clone())),
+        );
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| {
+                    verify_host_key(
+                        "10.20.30.51",
+                        DEFAULT_SSH2_PORT,
+                        &raw_host_key(FIRST_HOST_KEY),
+                        &host_key_verification,
+                    )
+                    .unwrap();
+                });
+            }
+        });
+        let content = std::fs::read_to_string(&known_hosts_path).unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(
+            content,
+            format!("10.20.30.51 ssh-ed25519 {}\n", FIRST_HOST_KEY)
+        );
+    }
+
+    #[test]
+    fn strict_policy_refuses_unknown_hosts() {
+        let known_hosts =
+            KnownHostsStore::Memory(vec![format!("10.20.30.51 ssh-ed25519 {}", FIRST_HOST_KEY)]);
+
+        assert!(verify_host_key(
+            "10.20.30.51",
+            DEFAULT_SSH2_PORT,
+            &raw_host_key(FIRST_HOST_KEY),
+            &verification(HostKeyPolicy::Strict, known_hosts.clone()),
+        )
+        .is_ok());
+        assert!(matches!(
+            verify_host_key(
+                "10.20.30.52",
+                DEFAULT_SSH2_PORT,
+                &raw_host_key(FIRST_HOST_KEY),
+                &verification(HostKeyPolicy::Strict, known_hosts.clone()),
+            ),
+            Err(Error::Connection(ConnectionError::HostKeyUnknown { .. }))
+        ));
+        // Nothing is checked, the fingerprint is still given
+        assert_eq!(
+            verify_host_key(
+                "10.20.30.51",
+                DEFAULT_SSH2_PORT,
+                &raw_host_key(SECOND_HOST_KEY),
+                &verification(HostKeyPolicy::Off, known_hosts),
+            )
+            .unwrap(),
+            "SHA256:mokBnPSlzdaiX0L8NWwJ80vUAjWtdi3DL9ddrBKLuOo"
+        );
+    }
+
+    #[test]
+    fn jump_hosts_are_chained_in_order() {
+        use crate::connection::host_connection::HostConnectionInfo;
+
+        let connection = HostConnectionInfo::ssh2_with_key_file("deploy", "/home/deploy/.ssh/id")
+            .with_jump_host(
+                "bastion.internal",
+                HostConnectionInfo::ssh2_with_key_file("jump", "/home/deploy/.ssh/jump")
+                    .with_port(2222)
+                    .with_jump_host(
+                        "bastion.example.com",
+                        HostConnectionInfo::ssh2_with_agent_user("jump"),
+                    ),
+            );
+
+        let HostConnectionInfo::Ssh2(settings) = connection else {
+            panic!("SSH2 connection expected");
+        };
+        let hops: Vec<(&str, u16)> = settings
+            .jump_hosts
+            .iter()
+            .map(|jump_host| {
+                (
+                    jump_host.address.as_str(),
+                    jump_host.settings.port_or_default(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            hops,
+            vec![("bastion.example.com", 22), ("bastion.internal", 2222)]
+        );
+        assert!(settings.jump_hosts[1].settings.jump_hosts.is_empty());
+    }
+
+    #[cfg(feature = "ssh2")]
+    #[test]
+    fn unreachable_jump_host_is_reported() {
+        let mut settings = Ssh2Settings::from(Ssh2AuthMode::KeyFile((
+            "deploy".into(),
+            PathBuf::from("/home/deploy/.ssh/id"),
+        )));
+        let mut jump_settings = Ssh2Settings::from(Ssh2AuthMode::Agent(("jump".into(), None)));
+        jump_settings.port = Some(1); // Nothing listens there
+        settings.jump_hosts.push(JumpHost {
+            address: "127.0.0.1".into(),
+            settings: jump_settings,
+        });
+
+        let mut handler = Ssh2HostHandler::from("10.20.30.51".into(), settings).unwrap();
+        match handler.init() {
+            Err(Error::Connection(ConnectionError::HostUnreachable { address, .. })) => {
+                assert_eq!(address, "127.0.0.1");
+            }
+            other => panic!("unexpected result : {:?}", other),
+        }
+    }
+
+    #[test]
+    fn settings_are_resolved_from_ssh_config() {
+        let directory = std::env::temp_dir().join(format!("dux-ssh-config-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        let identity_file = directory.join("db_key");
+        std::fs::write(&identity_file, "").unwrap();
+        let config_path = directory.join("config");
+        std::fs::write(
+            &config_path,
+            format!(
+                "Host db1
+    HostName db1.prod.internal
+    User deploy
+    IdentityFile {}/missing_key
+    IdentityFile {}
+    Port 2222
+    ProxyJump admin@bastion:2200,gw
+    ServerAliveInterval 15
+
+Host bastion
+    HostName bastion.example.com
+    Port 22
+",
+                directory.display(),
+                identity_file.display()
+            ),
+        )
+        .unwrap();
+
+        let mut explicit_settings = Ssh2Settings::from(Ssh2AuthMode::KeyFile((
+            "someone".into(),
+            PathBuf::from("/home/someone/.ssh/id_ed25519"),
+        )));
+        explicit_settings.port = Some(2022);
+        explicit_settings.keepalive_interval = Some(30);
+        explicit_settings.jump_hosts = vec![JumpHost {
+            address: "gw".into(),
+            settings: Ssh2Settings::from(Ssh2AuthMode::Agent(("someone".into(), None))),
+        }];
+        explicit_settings.ssh_config = Some(SshConfigSource::File(config_path.clone()));
+        let mut settings = Ssh2Settings::from(Ssh2AuthMode::Unset);
+        settings.ssh_config = Some(SshConfigSource::File(config_path.clone()));
+
+        // Explicit values win
+        let (hostaddress, resolved) = explicit_settings.clone().resolve_ssh_config("db1").unwrap();
+        assert_eq!(hostaddress, "db1.prod.internal");
+        assert_eq!(
+            Ssh2Settings {
+                ssh_config: None,
+                ..resolved
+            },
+            Ssh2Settings {
+                ssh_config: None,
+                ..explicit_settings
+            }
+        );
+
+        // Even when they are the same as the defaults
+        let mut default_values = Ssh2Settings::from(Ssh2AuthMode::Agent((local_username(), None)));
+        default_values.port = Some(22);
+        default_values.ssh_config = Some(SshConfigSource::File(config_path));
+        let (_, resolved) = default_values.resolve_ssh_config("db1").unwrap();
+        assert_eq!(resolved.port, Some(22));
+        assert_eq!(
+            resolved.authmode,
+            Ssh2AuthMode::Agent((local_username(), None))
+        );
+
+        // Unset values are filled by the configuration
+        let (hostaddress, settings) = settings.resolve_ssh_config("db1").unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert_eq!(hostaddress, "db1.prod.internal");
+        assert_eq!(settings.port, Some(2222));
+        assert_eq!(
+            settings.authmode,
+            Ssh2AuthMode::KeyFile(("deploy".into(), identity_file))
+        );
+        assert_eq!(settings.keepalive_interval, Some(15));
+        let hops: Vec<(&str, u16, Option<String>)> = settings
+            .jump_hosts
+            .iter()
+            .map(|jump_host| {
+                (
+                    jump_host.address.as_str(),
+                    jump_host.settings.port_or_default(),
+                    jump_host.settings.authmode.username(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            hops,
+            vec![
+                ("bastion.example.com", 2200, Some("admin".into())),
+                ("gw", 22, Some(local_username()))
+            ]
+        );
+    }
+
+    #[cfg(feature = "ssh2")]
+    #[test]
+    fn silent_server_expires_handshake_timeout() {
+        use crate::connection::host_connection::HostConnectionInfo;
+        use crate::job::job::Job;
+        use crate::workflow::hostworkflow::HostWorkFlowStatus;
+
+        // The connection is accepted by the OS but nothing ever answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut job = Job::new();
+        job.set_address("127.0.0.1")
+            .set_connection(
+                HostConnectionInfo::ssh2_with_agent_user("deploy")
+                    .with_port(port)
+                    .with_handshake_timeout(Some(Duration::from_millis(300))),
+            )
+            .unwrap()
+            .set_tasklist_from_str(
+                "---
+- name: Never reached
+  steps:
+    - ping:
+",
+                crate::task::tasklist::TaskListFileType::Yaml,
+            )
+            .unwrap();
+
+        let started = Instant::now();
+        job.apply();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        match &job.final_status {
+            HostWorkFlowStatus::ConnectionInitFailed(reason) => {
+                assert!(reason.starts_with("timeout : SSH handshake"), "{}", reason);
             }
+            other => panic!("unexpected status : {:?}", other),
         }
+        drop(listener);
     }
 }
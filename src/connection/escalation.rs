@@ -0,0 +1,328 @@
+//! Privilege escalation : how a command is run as another user (root by default) for steps using with_sudo or run_as
+//!
+//! Each method builds its own command and recognizes its own failures. Only sudo can be given a password (on stdin) :
+//! the other tools read it from a terminal only, so they need passwordless rules (ex: `permit nopass` in doas.conf).
+
+use crate::connection::argv::Argv;
+use crate::result::cmd::CmdResult;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BecomeMethod {
+    Sudo,
+    Su,
+    Doas,
+    Run0,
+    Pbrun,
+}
+
+impl BecomeMethod {
+    pub fn strategy(&self) -> &'static dyn Escalation {
+        match self {
+            BecomeMethod::Sudo => &Sudo,
+            BecomeMethod::Su => &Su,
+            BecomeMethod::Doas => &Doas,
+            BecomeMethod::Run0 => &Run0,
+            BecomeMethod::Pbrun => &Pbrun,
+        }
+    }
+}
+
+pub trait Escalation {
+    /// With a password, the command reads it from the first line of its stdin
+    fn command(&self, cmd: &str, username: &str, with_password: bool) -> String;
+    fn reads_password_from_stdin(&self) -> bool {
+        false
+    }
+    /// What the tool said when the command couldn't be run as the user (wrong password, user not allowed...)
+    fn failure(&self, cmd_result: &CmdResult) -> Option<String>;
+    /// Output without what the tool printed itself (password prompt...)
+    fn clean_output(&self, output: &str) -> String {
+        output.to_string()
+    }
+}
+
+// Printed by sudo when it reads the password : easy to find and remove from stderr
+const SUDO_PROMPT: &str = "[dux-become-password]";
+
+pub struct Sudo;
+
+impl Escalation for Sudo {
+    // Without a password, sudo fails right away instead of waiting for one (-n).
+    // With a password, it is only given to sudo if sudo needs it to become this user (no NOPASSWD rule or cached credentials),
+    // so that it never reaches the command itself. It never appears in the command line.
+    // The password is checked alone first : a wrong one can't make sudo read the command's input as further attempts.
+    // Cached credentials are ignored (-k) so that sudo reads the password line in both calls.
+    fn command(&self, cmd: &str, username: &str, with_password: bool) -> String {
+        if !with_password {
+            return format!("{} {}", Argv::new("sudo").args(["-n", "-u", username]), cmd);
+        }
+
+        let script = format!(
+            "IFS= read -r password; \
+             if sudo -n -u \"$1\" true 2>/dev/null; then eval \"sudo -n -u \\\"\\$1\\\" $2\"; \
+             elif printf '%s\\n' \"$password\" | sudo -k -S -p '{prompt}' -u \"$1\" true; then \
+             {{ printf '%s\\n' \"$password\"; cat; }} | eval \"sudo -k -S -p '{prompt}' -u \\\"\\$1\\\" $2\"; \
+             else exit 1; fi",
+            prompt = SUDO_PROMPT
+        );
+        return Argv::new("sh")
+            .arg("-c")
+            .arg(script)
+            .args(["dux-become", username, cmd])
+            .to_shell();
+    }
+
+    fn reads_password_from_stdin(&self) -> bool {
+        true
+    }
+
+    fn failure(&self, cmd_result: &CmdResult) -> Option<String> {
+        tool_message(cmd_result, |line| {
+            line.starts_with("sudo: ") && (line.contains("password") || line.contains("sudoers"))
+        })
+        .map(|line| line.trim_start_matches("sudo: ").to_string())
+    }
+
+    fn clean_output(&self, output: &str) -> String {
+        output.replace(SUDO_PROMPT, "")
+    }
+}
+
+pub struct Su;
+
+impl Escalation for Su {
+    fn command(&self, cmd: &str, username: &str, _with_password: bool) -> String {
+        Argv::new("su").arg(username).arg("-c").arg(cmd).to_shell()
+    }
+
+    // Ex: "su: Authentication failure", "su: must be run from a terminal"
+    fn failure(&self, cmd_result: &CmdResult) -> Option<String> {
+        tool_message(cmd_result, |line| line.starts_with("su: "))
+            .map(|line| line.trim_start_matches("su: ").to_string())
+    }
+}
+
+pub struct Doas;
+
+impl Escalation for Doas {
+    fn command(&self, cmd: &str, username: &str, _with_password: bool) -> String {
+        format!("{} {}", Argv::new("doas").args(["-n", "-u", username]), cmd)
+    }
+
+    // Ex: "doas: Authorization required", "doas: Operation not permitted"
+    fn failure(&self, cmd_result: &CmdResult) -> Option<String> {
+        tool_message(cmd_result, |line| line.starts_with("doas: "))
+            .map(|line| line.trim_start_matches("doas: ").to_string())
+    }
+}
+
+pub struct Run0;
+
+impl Escalation for Run0 {
+    fn command(&self, cmd: &str, username: &str, _with_password: bool) -> String {
+        format!(
+            "{} {}",
+            Argv::new("run0").arg(format!("--user={}", username)),
+            cmd
+        )
+    }
+
+    // Authorization goes through polkit. Ex: "Failed to start transient service unit: Interactive authentication required."
+    fn failure(&self, cmd_result: &CmdResult) -> Option<String> {
+        tool_message(cmd_result, |line| {
+            line.starts_with("Failed to start transient service unit")
+        })
+        .map(|line| line.to_string())
+    }
+}
+
+pub struct Pbrun;
+
+impl Escalation for Pbrun {
+    fn command(&self, cmd: &str, username: &str, _with_password: bool) -> String {
+        format!("{} {}", Argv::new("pbrun").args(["-u", username]), cmd)
+    }
+
+    // Ex: "Request rejected by pbmasterd on server1."
+    fn failure(&self, cmd_result: &CmdResult) -> Option<String> {
+        tool_message(cmd_result, |line| {
+            line.starts_with("pbrun") || line.contains("rejected by pbmasterd")
+        })
+        .map(|line| line.to_string())
+    }
+}
+
+// Only failed commands are considered : a successful command may print anything
+fn tool_message(cmd_result: &CmdResult, is_tool_message: impl Fn(&str) -> bool) -> Option<&str> {
+    if cmd_result.rc == 0 {
+        return None;
+    }
+    cmd_result
+        .stderr
+        .lines()
+        .map(|line| line.trim())
+        .find(|line| is_tool_message(line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::connectionmode::localhost::LocalHostHandler;
+    use std::os::unix::fs::PermissionsExt;
+
+    // Stands for sudo : the password is "secret", no password is needed to become the user in FAKE_SUDO_NOPASSWD
+    const FAKE_SUDO: &str = r#"#!/bin/sh
+stdin_password=false
+while [ $# -gt 0 ]; do
+    case "$1" in
+        -n|-k) shift ;;
+        -S) stdin_password=true; shift ;;
+        -p) prompt="$2"; shift 2 ;;
+        -u) user="$2"; shift 2 ;;
+        *) break ;;
+    esac
+done
+if [ "$user" = "$FAKE_SUDO_NOPASSWD" ]; then exec "$@"; fi
+if [ "$stdin_password" = false ]; then echo "sudo: a password is required" >&2; exit 1; fi
+printf '%s' "$prompt" >&2
+IFS= read -r password
+if [ "$password" = secret ]; then exec "$@"; fi
+echo "Sorry, try again." >&2
+echo "sudo: 1 incorrect password attempt" >&2
+exit 1
+"#;
+
+    #[test]
+    fn become_password_only_reaches_sudo() {
+        let directory = std::env::temp_dir().join(format!("dux-sudo-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let sudo = directory.join("sudo");
+        std::fs::write(&sudo, FAKE_SUDO).unwrap();
+        std::fs::set_permissions(&sudo, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let handler = LocalHostHandler::new();
+        let run = |environment: &str, username: &str, with_password: bool, stdin: &[u8]| {
+            let cmd = Sudo.command("cat", username, with_password);
+            let mut cmd_result = handler
+                .run_cmd_with_input(
+                    &format!(
+                        "PATH={}:\"$PATH\" {} {}",
+                        directory.display(),
+                        environment,
+                        Argv::new("sh").arg("-c").arg(&cmd)
+                    ),
+                    Some(stdin),
+                    None,
+                )
+                .unwrap();
+            cmd_result.stderr = Sudo.clean_output(&cmd_result.stderr);
+            cmd_result
+        };
+
+        let accepted = run("", "root", true, b"secret\ndata");
+        assert_eq!(accepted.stdout, "data");
+        assert_eq!(accepted.stderr, "");
+        assert_eq!(Sudo.failure(&accepted), None);
+
+        // sudo doesn't ask for the password : the command mustn't get it
+        let not_needed = run("FAKE_SUDO_NOPASSWD=root", "root", true, b"secret\ndata");
+        assert_eq!(not_needed.stdout, "data");
+
+        // Same with run_as a user needing no password, while root needs one
+        let run_as = run(
+            "FAKE_SUDO_NOPASSWD=app",
+            "app",
+            true,
+            b"secret\nfirst\nsecond",
+        );
+        assert_eq!(run_as.stdout, "first\nsecond");
+        assert_eq!(Sudo.failure(&run_as), None);
+
+        // The command's input is never taken for further password attempts
+        let refused = run("", "root", true, b"wrong\nsecret\ndata");
+        assert_eq!(refused.stdout, "");
+        assert_eq!(
+            Sudo.failure(&refused),
+            Some("1 incorrect password attempt".to_string())
+        );
+
+        let missing = run("", "root", false, b"data");
+        assert_eq!(
+            Sudo.failure(&missing),
+            Some("a password is required".to_string())
+        );
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn each_method_builds_its_command_and_recognizes_its_failures() {
+        let failed = |stderr: &str| CmdResult {
+            rc: 1,
+            stdout: String::new(),
+            stderr: stderr.to_string(),
+        };
+
+        assert_eq!(
+            BecomeMethod::Su
+                .strategy()
+                .command("systemctl restart 'my app'", "root", true),
+            "su root -c 'systemctl restart '\\''my app'\\'''"
+        );
+        assert_eq!(
+            BecomeMethod::Su
+                .strategy()
+                .failure(&failed("su: Authentication failure")),
+            Some("Authentication failure".to_string())
+        );
+
+        assert_eq!(
+            BecomeMethod::Doas
+                .strategy()
+                .command("apk update", "root", false),
+            "doas -n -u root apk update"
+        );
+        assert_eq!(
+            BecomeMethod::Doas
+                .strategy()
+                .failure(&failed("doas: Authorization required")),
+            Some("Authorization required".to_string())
+        );
+
+        assert_eq!(
+            BecomeMethod::Run0
+                .strategy()
+                .command("dnf check-update", "root", false),
+            "run0 --user=root dnf check-update"
+        );
+        assert!(BecomeMethod::Run0
+            .strategy()
+            .failure(&failed(
+                "Failed to start transient service unit: Interactive authentication required."
+            ))
+            .is_some());
+
+        assert_eq!(
+            BecomeMethod::Pbrun
+                .strategy()
+                .command("id", "oracle", false),
+            "pbrun -u oracle id"
+        );
+        assert!(BecomeMethod::Pbrun
+            .strategy()
+            .failure(&failed("Request rejected by pbmasterd on server1."))
+            .is_some());
+
+        // The command's own errors are not escalation failures
+        assert_eq!(
+            BecomeMethod::Doas
+                .strategy()
+                .failure(&failed("apk: unable to lock database")),
+            None
+        );
+        assert!(!BecomeMethod::Doas.strategy().reads_password_from_stdin());
+    }
+}
@@ -1,16 +1,27 @@
+#[cfg(test)]
+use crate::connection::connectionmode::fake::FakeTransport;
 use crate::connection::connectionmode::localhost::WhichUser;
-use crate::connection::connectionmode::ssh2mode::Ssh2AuthMode;
+use crate::connection::connectionmode::ssh2mode::{
+    HostKeyPolicy, JumpHost, KnownHostsStore, PassphraseCallback, Ssh2AuthMode, Ssh2Settings,
+    SshBackend, SshConfigSource,
+};
 use crate::connection::specification::Credentials;
+use crate::connection::sshconfig::local_username;
+use crate::host::hosts::ConnectionParameters;
 use pem::Pem;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[allow(clippy::large_enum_variant)] // One per Job, boxing Ssh2Settings would only complicate its handling
 pub enum HostConnectionInfo {
     Unset,
     LocalHost(WhichUser),
-    Ssh2(Ssh2AuthMode),
+    Ssh2(Ssh2Settings),
     // Ssh3
+    #[cfg(test)]
+    Fake(FakeTransport),
 }
 
 impl HostConnectionInfo {
@@ -31,14 +42,14 @@ impl HostConnectionInfo {
 
     /// Commands will be run on a remote host through SSH2, with username/password authentication
     pub fn ssh2_with_username_password(username: String, password: String) -> HostConnectionInfo {
-        HostConnectionInfo::Ssh2(Ssh2AuthMode::UsernamePassword(Credentials::from(
+        HostConnectionInfo::ssh2(Ssh2AuthMode::UsernamePassword(Credentials::from(
             username, password,
         )))
     }
 
     /// Commands will be run on a remote host through SSH2, using a key
     pub fn ssh2_with_key_file(username: &str, key_file_path: &str) -> HostConnectionInfo {
-        HostConnectionInfo::Ssh2(Ssh2AuthMode::KeyFile((
+        HostConnectionInfo::ssh2(Ssh2AuthMode::KeyFile((
             username.to_string(),
             PathBuf::from(key_file_path),
         )))
@@ -46,11 +57,291 @@ impl HostConnectionInfo {
 
     /// Commands will be run on a remote host through SSH2, using an in-memory pem key
     pub fn ssh2_with_key_in_memory(username: String, key_content: Pem) -> HostConnectionInfo {
-        HostConnectionInfo::Ssh2(Ssh2AuthMode::KeyMemory((username, key_content)))
+        HostConnectionInfo::ssh2(Ssh2AuthMode::KeyMemory((username, key_content)))
     }
 
-    /// Commands will be run on a remote host through SSH2, using SSH agent
-    pub fn ssh2_with_agent(agent_name: String) -> HostConnectionInfo {
-        HostConnectionInfo::Ssh2(Ssh2AuthMode::Agent(agent_name))
+    /// Commands will be run on a remote host through SSH2, using the SSH agent pointed by SSH_AUTH_SOCK
+    pub fn ssh2_with_agent_user(username: &str) -> HostConnectionInfo {
+        HostConnectionInfo::ssh2(Ssh2AuthMode::Agent((username.to_string(), None)))
+    }
+
+    #[deprecated(
+        since = "0.1.9",
+        note = "use ssh2_with_agent_user, which takes the username as a &str"
+    )]
+    pub fn ssh2_with_agent(username: String) -> HostConnectionInfo {
+        HostConnectionInfo::ssh2_with_agent_user(&username)
+    }
+
+    /// Commands will be run on a remote host through SSH2, using the SSH agent listening on the given socket
+    pub fn ssh2_with_agent_socket(username: String, socket_path: &str) -> HostConnectionInfo {
+        HostConnectionInfo::ssh2(Ssh2AuthMode::Agent((
+            username,
+            Some(PathBuf::from(socket_path)),
+        )))
+    }
+
+    /// Commands will be run on a remote host through SSH2, connecting like `ssh` would with ~/.ssh/config (SSH agent and current username
+    /// unless the configuration says otherwise)
+    pub fn ssh2_with_ssh_config() -> HostConnectionInfo {
+        HostConnectionInfo::ssh2(Ssh2AuthMode::Unset).with_ssh_config()
+    }
+
+    /// SSH2 connection on the default port
+    pub fn ssh2(authmode: Ssh2AuthMode) -> HostConnectionInfo {
+        HostConnectionInfo::Ssh2(Ssh2Settings::from(authmode))
+    }
+
+    /// SSH2 connection on a custom port (no effect on other connection modes)
+    pub fn with_port(self, port: u16) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(settings) => HostConnectionInfo::Ssh2(Ssh2Settings {
+                port: Some(port),
+                ..settings
+            }),
+            other => other,
+        }
+    }
+
+    /// Implementation of SSHv2 used to reach the host (SSH2 only). Default is SshBackend::Libssh2,
+    /// SshBackend::Russh needs the "russh" cargo feature.
+    pub fn with_ssh_backend(self, backend: SshBackend) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.backend = backend;
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// Public key or certificate matching the private key (SSH2 key authentications only)
+    pub fn with_public_key(self, public_key_path: &str) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.key_options.public_key = Some(PathBuf::from(public_key_path));
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// Passphrase of an encrypted private key (SSH2 key authentications only). Be aware it is serialized with the Job.
+    pub fn with_passphrase(self, passphrase: &str) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.key_options.passphrase = Some(passphrase.to_string());
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// The passphrase of an encrypted private key is asked when connecting and is never serialized with the Job.
+    /// The callback gets the key's description (path or username for in-memory keys).
+    pub fn with_passphrase_callback<F>(self, callback: F) -> HostConnectionInfo
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.key_options.passphrase_callback = Some(PassphraseCallback::from(callback));
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// How the host key presented by the server is checked (SSH2 only). Default is HostKeyPolicy::AcceptNew.
+    pub fn with_host_key_policy(self, policy: HostKeyPolicy) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.host_key.policy = policy;
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// Known hosts are read from (and added to, with HostKeyPolicy::AcceptNew) this file instead of ~/.ssh/known_hosts (SSH2 only)
+    pub fn with_known_hosts_file(self, known_hosts_path: &str) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.host_key.known_hosts =
+                    KnownHostsStore::File(Some(PathBuf::from(known_hosts_path)));
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// Known hosts are given as lines in the OpenSSH known_hosts format instead of being read from a file (SSH2 only)
+    pub fn with_known_hosts(self, known_hosts_lines: &[&str]) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.host_key.known_hosts = KnownHostsStore::Memory(
+                    known_hosts_lines
+                        .iter()
+                        .map(|line| line.to_string())
+                        .collect(),
+                );
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// Time limit to establish the TCP connection, 30 seconds by default (SSH2 only). None waits as long as the OS does.
+    pub fn with_connect_timeout(self, timeout: Option<Duration>) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.timeouts.connect = timeout;
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// Time limit for each step of the SSH handshake and authentication, 60 seconds by default (SSH2 only).
+    /// libssh2 applies it to each exchange with the server, russh to the handshake and then to the authentication.
+    pub fn with_handshake_timeout(self, timeout: Option<Duration>) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.timeouts.handshake = timeout;
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// Time limit for a command to give some output, no limit by default (SSH2 only)
+    pub fn with_read_timeout(self, timeout: Option<Duration>) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.timeouts.read = timeout;
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// When the session is lost during a run, up to max_attempts connections are tried, waiting initial_delay before the first one
+    /// and twice as long before each next one (30 seconds at most). 6 attempts from 1 second by default, 0 disables it (SSH2 only).
+    pub fn with_reconnect(self, max_attempts: u32, initial_delay: Duration) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.reconnect.max_attempts = max_attempts;
+                settings.reconnect.initial_delay = initial_delay;
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// A keepalive message asking for a reply is sent after this many seconds of inactivity, to keep the connection open through firewalls (SSH2 only).
+    /// russh gives up on a host after 3 unanswered ones, libssh2 only once TCP does.
+    pub fn with_keepalive(self, interval_seconds: u32) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.keepalive_interval =
+                    Some(interval_seconds).filter(|interval| *interval > 0);
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// HostName, Port, User, IdentityFile, ProxyJump and ServerAliveInterval are read from ~/.ssh/config for the Job's address, when connecting (SSH2 only).
+    /// Like with OpenSSH, values set explicitly take precedence : the configuration only fills the ones left unset.
+    pub fn with_ssh_config(self) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.ssh_config = Some(SshConfigSource::UserConfig);
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// Same as with_ssh_config() with another OpenSSH configuration file
+    pub fn with_ssh_config_file(self, ssh_config_path: &str) -> HostConnectionInfo {
+        match self {
+            HostConnectionInfo::Ssh2(mut settings) => {
+                settings.ssh_config = Some(SshConfigSource::File(PathBuf::from(ssh_config_path)));
+                HostConnectionInfo::Ssh2(settings)
+            }
+            other => other,
+        }
+    }
+
+    /// Goes through this jump host (bastion) before reaching the target (SSH2 only). Call it once per hop, in order.
+    /// The jump host's connection is built like any other SSH2 one and its own jump hosts are gone through first.
+    pub fn with_jump_host(
+        self,
+        jump_host_address: &str,
+        jump_host_connection: HostConnectionInfo,
+    ) -> HostConnectionInfo {
+        match (self, jump_host_connection) {
+            (
+                HostConnectionInfo::Ssh2(mut settings),
+                HostConnectionInfo::Ssh2(mut jump_settings),
+            ) => {
+                settings.jump_hosts.append(&mut jump_settings.jump_hosts);
+                settings.jump_hosts.push(JumpHost {
+                    address: jump_host_address.to_string(),
+                    settings: jump_settings,
+                });
+                HostConnectionInfo::Ssh2(settings)
+            }
+            (other, _) => other,
+        }
+    }
+
+    /// Applies connection parameters defined in a HostList on top of this connection info : the HostList has the final word.
+    /// A user and a key are enough to build an SSH2 connection from scratch (unset connection), otherwise parameters only refine an existing SSH2 connection.
+    pub fn with_parameters(self, parameters: &ConnectionParameters) -> HostConnectionInfo {
+        let host_connection_info = match (&parameters.user, &parameters.key) {
+            (Some(username), Some(key_path)) => match self {
+                HostConnectionInfo::Ssh2(settings) => {
+                    HostConnectionInfo::Ssh2(settings.with_key_file(username, key_path))
+                }
+                HostConnectionInfo::Unset => {
+                    HostConnectionInfo::ssh2_with_key_file(username, key_path)
+                }
+                other => other,
+            },
+            // Authentication left to the OpenSSH configuration : with this user, it becomes the SSH agent's
+            (Some(username), None) => match self {
+                HostConnectionInfo::Ssh2(settings) if settings.authmode == Ssh2AuthMode::Unset => {
+                    HostConnectionInfo::Ssh2(Ssh2Settings {
+                        authmode: Ssh2AuthMode::Agent((username.clone(), None)),
+                        ..settings
+                    })
+                }
+                HostConnectionInfo::Ssh2(settings) => HostConnectionInfo::Ssh2(Ssh2Settings {
+                    authmode: settings.authmode.with_username(username),
+                    ..settings
+                }),
+                other => other,
+            },
+            (None, Some(key_path)) => match self {
+                HostConnectionInfo::Ssh2(settings) => match settings.authmode.username() {
+                    Some(username) => {
+                        HostConnectionInfo::Ssh2(settings.with_key_file(&username, key_path))
+                    }
+                    None if settings.authmode == Ssh2AuthMode::Unset => HostConnectionInfo::Ssh2(
+                        settings.with_key_file(&local_username(), key_path),
+                    ),
+                    None => HostConnectionInfo::Ssh2(settings),
+                },
+                other => other,
+            },
+            (None, None) => self,
+        };
+
+        match parameters.port {
+            Some(port) => host_connection_info.with_port(port),
+            None => host_connection_info,
+        }
     }
 }
@@ -1,7 +1,22 @@
-use crate::connection::connectionmode::localhost::{LocalHostConnectionDetails, LocalHostHandler};
-use crate::connection::connectionmode::ssh2mode::{Ssh2ConnectionDetails, Ssh2HostHandler};
-use crate::connection::specification::{ConnectionMode, Privilege};
+use crate::connection::argv::Argv;
+#[cfg(test)]
+use crate::connection::connectionmode::fake::FakeHostHandler;
+use crate::connection::connectionmode::localhost::{
+    LocalHostConnectionDetails, LocalHostHandler, WhichUser,
+};
+#[cfg(feature = "russh")]
+use crate::connection::connectionmode::russhmode::RusshHostHandler;
+#[cfg(feature = "ssh2")]
+use crate::connection::connectionmode::ssh2mode::Ssh2HostHandler;
+use crate::connection::connectionmode::ssh2mode::{Ssh2ConnectionDetails, SshBackend};
+use crate::connection::escalation::BecomeMethod;
+use crate::connection::specification::{
+    ConnectionMode, ConnectionReport, Privilege, ReconnectPolicy, Reconnection,
+};
+use crate::connection::transfer::{decode_download, download_cmd, upload_cmd, UploadContent};
+use crate::error::connection::ConnectionError;
 use crate::error::Error;
+use crate::output::streaming::{OnLine, OutputLine, OutputSink, OutputStream};
 use crate::result::cmd::CmdResult;
 use serde::{Deserialize, Serialize};
 
@@ -38,17 +53,47 @@ impl HostHandlingInfo {
 
 #[derive(Clone)]
 pub struct HostHandler {
+    pub hostaddress: String,
     pub connectionmode: ConnectionMode,
     pub localhost: Option<LocalHostHandler>,
+    #[cfg(feature = "ssh2")]
     pub ssh2: Option<Ssh2HostHandler>,
+    #[cfg(feature = "russh")]
+    pub russh: Option<RusshHostHandler>,
+    #[cfg(test)]
+    pub fake: Option<FakeHostHandler>,
+    /// Receives the output of commands line by line, while a step is applied
+    pub output_sink: Option<OutputSink>,
+    pub current_step: Option<String>,
+    /// How commands needing privileges are run, unless the current step says otherwise
+    pub become_method: BecomeMethod,
+    pub step_become_method: Option<BecomeMethod>,
+    /// Given on stdin to the escalation method (sudo only) when a command needs privileges
+    pub become_password: Option<String>,
+    /// Commands run while this is true only read the host's state : they are run again after a reconnection
+    pub checking: bool,
+    pub reconnections: Vec<Reconnection>,
 }
 
 impl HostHandler {
     pub fn new() -> HostHandler {
         HostHandler {
+            hostaddress: String::new(),
             connectionmode: ConnectionMode::Unset,
             localhost: None,
+            #[cfg(feature = "ssh2")]
             ssh2: None,
+            #[cfg(feature = "russh")]
+            russh: None,
+            #[cfg(test)]
+            fake: None,
+            output_sink: None,
+            current_step: None,
+            become_method: BecomeMethod::Sudo,
+            step_become_method: None,
+            become_password: None,
+            checking: false,
+            reconnections: Vec::new(),
         }
     }
 
@@ -57,18 +102,82 @@ impl HostHandler {
         host_connection_info: HostConnectionInfo,
     ) -> Result<HostHandler, Error> {
         match host_connection_info {
-            HostConnectionInfo::Unset => Err(Error::MissingInitialization(
+            HostConnectionInfo::Unset => Err(Error::Connection(ConnectionError::Unset(
                 "Host connection info is still unset. Unable to build a HostHandler.".into(),
-            )),
+            ))),
             HostConnectionInfo::LocalHost(which_user) => Ok(HostHandler {
+                hostaddress: address,
                 connectionmode: ConnectionMode::LocalHost,
                 localhost: Some(LocalHostHandler::from(which_user)),
+                #[cfg(feature = "ssh2")]
                 ssh2: None,
+                #[cfg(feature = "russh")]
+                russh: None,
+                #[cfg(test)]
+                fake: None,
+                output_sink: None,
+                current_step: None,
+                become_method: BecomeMethod::Sudo,
+                step_become_method: None,
+                become_password: None,
+                checking: false,
+                reconnections: Vec::new(),
             }),
-            HostConnectionInfo::Ssh2(ssh2_auth_mode) => Ok(HostHandler {
-                connectionmode: ConnectionMode::Ssh2,
+            HostConnectionInfo::Ssh2(ssh2_settings) => match ssh2_settings.backend {
+                #[cfg(feature = "ssh2")]
+                SshBackend::Libssh2 => Ok(HostHandler {
+                    hostaddress: address.clone(),
+                    connectionmode: ConnectionMode::Ssh2,
+                    localhost: None,
+                    ssh2: Some(Ssh2HostHandler::from(address, ssh2_settings)?),
+                    #[cfg(feature = "russh")]
+                    russh: None,
+                    #[cfg(test)]
+                    fake: None,
+                    output_sink: None,
+                    current_step: None,
+                    become_method: BecomeMethod::Sudo,
+                    step_become_method: None,
+                    become_password: None,
+                    checking: false,
+                    reconnections: Vec::new(),
+                }),
+                #[cfg(feature = "russh")]
+                SshBackend::Russh => Ok(HostHandler {
+                    hostaddress: address.clone(),
+                    connectionmode: ConnectionMode::Russh,
+                    localhost: None,
+                    #[cfg(feature = "ssh2")]
+                    ssh2: None,
+                    russh: Some(RusshHostHandler::from(address, ssh2_settings)?),
+                    #[cfg(test)]
+                    fake: None,
+                    output_sink: None,
+                    current_step: None,
+                    become_method: BecomeMethod::Sudo,
+                    step_become_method: None,
+                    become_password: None,
+                    checking: false,
+                    reconnections: Vec::new(),
+                }),
+            },
+            #[cfg(test)]
+            HostConnectionInfo::Fake(transport) => Ok(HostHandler {
+                hostaddress: address.clone(),
+                connectionmode: ConnectionMode::Fake,
                 localhost: None,
-                ssh2: Some(Ssh2HostHandler::from(address, ssh2_auth_mode)),
+                #[cfg(feature = "ssh2")]
+                ssh2: None,
+                #[cfg(feature = "russh")]
+                russh: None,
+                fake: Some(FakeHostHandler::from(address, transport)),
+                output_sink: None,
+                current_step: None,
+                become_method: BecomeMethod::Sudo,
+                step_become_method: None,
+                become_password: None,
+                checking: false,
+                reconnections: Vec::new(),
             }),
         }
     }
@@ -76,59 +185,418 @@ impl HostHandler {
     pub fn init(&mut self) -> Result<(), Error> {
         match self.connectionmode {
             ConnectionMode::Unset => {
-                return Err(Error::MissingInitialization(
+                return Err(Error::Connection(ConnectionError::Unset(
                     "ConnectionMode is unset".to_string(),
-                ));
+                )));
             }
             // Nothing to initialize when working on localhost
             ConnectionMode::LocalHost => {
                 return Ok(());
             }
-            ConnectionMode::Ssh2 => self.ssh2.as_mut().unwrap().init(),
+            #[cfg(feature = "ssh2")]
+            ConnectionMode::Ssh2 => match self.ssh2.as_mut() {
+                Some(handler) => handler.init(),
+                None => Err(self.missing_handler()),
+            },
+            #[cfg(feature = "russh")]
+            ConnectionMode::Russh => match self.russh.as_mut() {
+                Some(handler) => handler.init(),
+                None => Err(self.missing_handler()),
+            },
+            #[cfg(test)]
+            ConnectionMode::Fake => match self.fake.as_mut() {
+                Some(handler) => handler.init(),
+                None => Err(self.missing_handler()),
+            },
         }
     }
 
     // Use this to check if a command is available on target host
     pub fn is_this_cmd_available(&mut self, cmd: &str) -> Result<bool, Error> {
+        match self.is_this_cmd_available_on_host(cmd) {
+            Err(error) if self.can_reconnect(&error) => {
+                // Only reads the host's state : always safe to run again
+                self.reconnect(&error, true)?;
+                self.is_this_cmd_available_on_host(cmd)
+            }
+            result => result,
+        }
+    }
+
+    fn is_this_cmd_available_on_host(&mut self, cmd: &str) -> Result<bool, Error> {
         match self.connectionmode {
-            ConnectionMode::Unset => Err(Error::MissingInitialization(
+            ConnectionMode::Unset => Err(Error::Connection(ConnectionError::Unset(
                 "ConnectionMode is unset".to_string(),
-            )),
-            ConnectionMode::LocalHost => {
-                self.localhost.as_mut().unwrap().is_this_cmd_available(cmd)
-            }
-            ConnectionMode::Ssh2 => self.ssh2.as_mut().unwrap().is_this_cmd_available(cmd),
+            ))),
+            ConnectionMode::LocalHost => match self.localhost.as_mut() {
+                Some(handler) => handler.is_this_cmd_available(cmd),
+                None => Err(self.missing_handler()),
+            },
+            #[cfg(feature = "ssh2")]
+            ConnectionMode::Ssh2 => match self.ssh2.as_mut() {
+                Some(handler) => handler.is_this_cmd_available(cmd),
+                None => Err(self.missing_handler()),
+            },
+            #[cfg(feature = "russh")]
+            ConnectionMode::Russh => match self.russh.as_mut() {
+                Some(handler) => handler.is_this_cmd_available(cmd),
+                None => Err(self.missing_handler()),
+            },
+            #[cfg(test)]
+            ConnectionMode::Fake => match self.fake.as_mut() {
+                Some(handler) => handler.is_this_cmd_available(cmd),
+                None => Err(self.missing_handler()),
+            },
         }
     }
 
+    /// Output of the commands run until the next call is streamed (if an OutputSink is set) and tagged with this step
+    pub fn set_current_step(&mut self, step: Option<String>) {
+        self.current_step = step;
+    }
+
+    /// True while a step is checked (dry run) : its commands are run again if the session is lost and found again
+    pub fn set_checking(&mut self, checking: bool) {
+        self.checking = checking;
+    }
+
+    /// Runs `true` as it is : a lost session is reported, never established again
+    pub fn is_alive(&mut self) -> bool {
+        match self.run_on_host(&Argv::new("true").to_shell(), None, None) {
+            Ok(cmd_result) => cmd_result.rc == 0,
+            Err(_) => false,
+        }
+    }
+
+    /// Escalation method of the step about to run (None : the host's one)
+    pub fn set_step_become_method(&mut self, become_method: Option<BecomeMethod>) {
+        self.step_become_method = become_method;
+    }
+
+    /// Runs the program with its arguments : each one reaches the program as it is, see Argv
+    pub fn run(&mut self, argv: &Argv, privilege: Privilege) -> Result<CmdResult, Error> {
+        self.run_cmd_with_input(&argv.to_shell(), None, privilege)
+    }
+
+    /// Same as run, with these bytes as standard input (see run_cmd_with_stdin)
+    pub fn run_with_stdin(
+        &mut self,
+        argv: &Argv,
+        stdin: &[u8],
+        privilege: Privilege,
+    ) -> Result<CmdResult, Error> {
+        self.run_cmd_with_input(&argv.to_shell(), Some(stdin), privilege)
+    }
+
+    /// Runs a shell command line as it is : values put in it must be quoted first (see Argv and argv::quote)
     pub fn run_cmd(&mut self, cmd: &str, privilege: Privilege) -> Result<CmdResult, Error> {
-        let final_cmd = final_cmd(cmd.to_string(), privilege.clone());
+        self.run_cmd_with_input(cmd, None, privilege)
+    }
+
+    /// Runs the command with these bytes as its standard input (followed by EOF). Nothing needs to be quoted or escaped : use it to write arbitrary content.
+    pub fn run_cmd_with_stdin(
+        &mut self,
+        cmd: &str,
+        stdin: &[u8],
+        privilege: Privilege,
+    ) -> Result<CmdResult, Error> {
+        self.run_cmd_with_input(cmd, Some(stdin), privilege)
+    }
+
+    fn run_cmd_with_input(
+        &mut self,
+        cmd: &str,
+        stdin: Option<&[u8]>,
+        privilege: Privilege,
+    ) -> Result<CmdResult, Error> {
+        let escalation = self
+            .step_become_method
+            .unwrap_or(self.become_method)
+            .strategy();
+        let become_user = become_user(&privilege);
+        let with_password =
+            self.become_password.is_some() && escalation.reads_password_from_stdin();
+        let final_cmd = match &become_user {
+            Some(username) => escalation.command(cmd, username, with_password),
+            None => cmd.to_string(),
+        };
+        // The password is the first line of stdin, read by the command built by the escalation method
+        let become_input: Option<Vec<u8>> = match (&become_user, &self.become_password) {
+            (Some(_), Some(password)) if with_password => {
+                let mut input = format!("{}\n", password).into_bytes();
+                input.extend_from_slice(stdin.unwrap_or_default());
+                Some(input)
+            }
+            _ => None,
+        };
+        let stdin = match &become_input {
+            Some(input) => Some(input.as_slice()),
+            None => stdin,
+        };
+
+        let hostaddress = self.hostaddress.clone();
+        let streaming = match (&self.output_sink, &self.current_step) {
+            (Some(sink), Some(step)) => Some((sink.clone(), step.clone())),
+            _ => None,
+        };
+        let send_line = |stream: OutputStream, line: &str| {
+            if let Some((sink, step)) = &streaming {
+                sink.send(OutputLine {
+                    host: hostaddress.clone(),
+                    step: step.clone(),
+                    stream,
+                    line: escalation.clean_output(line),
+                });
+            }
+        };
+        let on_line: Option<&OnLine<'_>> = match streaming {
+            Some(_) => Some(&send_line),
+            None => None,
+        };
+
+        let mut cmd_result = match self.run_on_host(&final_cmd, stdin, on_line) {
+            Err(error) if self.can_reconnect(&error) => {
+                // A command changing the host may have been applied or not : it is never run twice
+                let retry = self.checking;
+                self.reconnect(&error, retry)?;
+                if !retry {
+                    return Err(error);
+                }
+                self.run_on_host(&final_cmd, stdin, on_line)?
+            }
+            result => result?,
+        };
+
+        let user = match become_user {
+            Some(username) => username,
+            None => return Ok(cmd_result),
+        };
+        cmd_result.stderr = escalation.clean_output(&cmd_result.stderr);
+        match escalation.failure(&cmd_result) {
+            Some(details) => Err(Error::Connection(ConnectionError::BecomeFailed {
+                address: self.hostaddress.clone(),
+                user,
+                details,
+            })),
+            None => Ok(cmd_result),
+        }
+    }
+
+    fn run_on_host(
+        &mut self,
+        cmd: &str,
+        stdin: Option<&[u8]>,
+        on_line: Option<&OnLine<'_>>,
+    ) -> Result<CmdResult, Error> {
         match self.connectionmode {
-            ConnectionMode::Unset => Err(Error::MissingInitialization(
+            ConnectionMode::Unset => Err(Error::Connection(ConnectionError::Unset(
                 "ConnectionMode is unset".to_string(),
-            )),
-            ConnectionMode::LocalHost => {
-                self.localhost.as_mut().unwrap().run_cmd(final_cmd.as_str())
+            ))),
+            ConnectionMode::LocalHost => match self.localhost.as_mut() {
+                Some(handler) => handler.run_cmd_with_input(cmd, stdin, on_line),
+                None => Err(self.missing_handler()),
+            },
+            #[cfg(feature = "ssh2")]
+            ConnectionMode::Ssh2 => match self.ssh2.as_mut() {
+                Some(handler) => handler.run_cmd_with_input(cmd, stdin, on_line),
+                None => Err(self.missing_handler()),
+            },
+            #[cfg(feature = "russh")]
+            ConnectionMode::Russh => match self.russh.as_mut() {
+                Some(handler) => handler.run_cmd_with_input(cmd, stdin, on_line),
+                None => Err(self.missing_handler()),
+            },
+            #[cfg(test)]
+            ConnectionMode::Fake => match self.fake.as_mut() {
+                Some(handler) => handler.run_cmd(cmd),
+                None => Err(self.missing_handler()),
+            },
+        }
+    }
+
+    // A session failure means the connection is gone. Once reconnecting failed, the host is considered lost for this run.
+    fn can_reconnect(&self, error: &Error) -> bool {
+        let session_lost = matches!(
+            error,
+            Error::Connection(ConnectionError::SessionFailed { .. })
+        );
+        let given_up = self
+            .reconnections
+            .last()
+            .is_some_and(|reconnection| !reconnection.reconnected);
+        session_lost && !given_up && self.reconnect_policy().max_attempts > 0
+    }
+
+    fn reconnect_policy(&self) -> ReconnectPolicy {
+        match self.connectionmode {
+            #[cfg(feature = "ssh2")]
+            ConnectionMode::Ssh2 => match &self.ssh2 {
+                Some(handler) => handler.reconnect_policy.clone(),
+                None => ReconnectPolicy::disabled(),
+            },
+            #[cfg(feature = "russh")]
+            ConnectionMode::Russh => match &self.russh {
+                Some(handler) => handler.reconnect_policy.clone(),
+                None => ReconnectPolicy::disabled(),
+            },
+            #[cfg(test)]
+            ConnectionMode::Fake => ReconnectPolicy {
+                max_attempts: 2,
+                ..ReconnectPolicy::disabled()
+            },
+            _ => ReconnectPolicy::disabled(),
+        }
+    }
+
+    // New sessions are tried with the policy's backoff. The outcome is kept for the connection report.
+    fn reconnect(&mut self, cause: &Error, command_retried: bool) -> Result<(), Error> {
+        let mut reconnection = Reconnection {
+            cause: cause.with_sources(),
+            attempts: 0,
+            reconnected: false,
+            command_retried: false,
+        };
+        let mut last_error: Option<Error> = None;
+
+        for delay in self.reconnect_policy().delays() {
+            std::thread::sleep(delay);
+            reconnection.attempts += 1;
+            let attempt = match self.connectionmode {
+                #[cfg(feature = "ssh2")]
+                ConnectionMode::Ssh2 => match self.ssh2.as_mut() {
+                    Some(handler) => handler.reconnect(),
+                    None => Err(self.missing_handler()),
+                },
+                #[cfg(feature = "russh")]
+                ConnectionMode::Russh => match self.russh.as_mut() {
+                    Some(handler) => handler.reconnect(),
+                    None => Err(self.missing_handler()),
+                },
+                #[cfg(test)]
+                ConnectionMode::Fake => match self.fake.as_mut() {
+                    Some(handler) => handler.reconnect(),
+                    None => Err(self.missing_handler()),
+                },
+                _ => Err(self.missing_handler()),
+            };
+            match attempt {
+                Ok(()) => {
+                    reconnection.reconnected = true;
+                    reconnection.command_retried = command_retried;
+                    break;
+                }
+                Err(error) => last_error = Some(error),
             }
-            ConnectionMode::Ssh2 => self.ssh2.as_mut().unwrap().run_cmd(final_cmd.as_str()),
+        }
+
+        let reconnected = reconnection.reconnected;
+        self.reconnections.push(reconnection);
+        match (reconnected, last_error) {
+            (false, Some(error)) => Err(error),
+            _ => Ok(()),
         }
     }
-}
 
-// TODO : add some syntax checks
-fn final_cmd(cmd: String, privilege: Privilege) -> String {
-    match privilege {
-        Privilege::Usual => {
-            return format!("{} 2>&1", cmd);
+    /// Writes the content to remote_path atomically : a temporary file is written next to it, then renamed.
+    /// mode sets the file's permissions (ex: 0o644). Without it, a replaced file keeps its permissions and a new one gets 0o644.
+    /// With a privilege other than Privilege::Usual, the file is written by the privileged user (ex: root-owned destinations).
+    pub fn upload(
+        &mut self,
+        content: UploadContent,
+        remote_path: &str,
+        mode: Option<u32>,
+        privilege: Privilege,
+    ) -> Result<(), Error> {
+        let bytes = content.bytes()?;
+
+        if let Privilege::Usual = privilege {
+            #[cfg(feature = "ssh2")]
+            if let (ConnectionMode::Ssh2, Some(handler)) = (&self.connectionmode, &self.ssh2) {
+                return handler.upload(&bytes, remote_path, mode);
+            }
+            if let (ConnectionMode::LocalHost, Some(handler)) =
+                (&self.connectionmode, &self.localhost)
+            {
+                if let WhichUser::CurrentUser = handler.user {
+                    return handler.upload(&bytes, remote_path, mode);
+                }
+            }
         }
-        Privilege::WithSudo => {
-            let final_cmd = format!("sudo -u root {} 2>&1", cmd);
-            return final_cmd;
+
+        // Otherwise the file is written by a command, running with the right privileges
+        let cmd_result = self.run_with_stdin(&upload_cmd(remote_path, mode), &bytes, privilege)?;
+        if cmd_result.rc != 0 {
+            return Err(self.transfer_failed(remote_path, cmd_result.stderr.trim()));
         }
-        Privilege::AsUser(username) => {
-            let final_cmd = format!("sudo -u {} {} 2>&1", username, cmd);
-            return final_cmd;
+        Ok(())
+    }
+
+    /// Content of remote_path. With a privilege other than Privilege::Usual, the file is read by the privileged user.
+    pub fn download(&mut self, remote_path: &str, privilege: Privilege) -> Result<Vec<u8>, Error> {
+        if let Privilege::Usual = privilege {
+            #[cfg(feature = "ssh2")]
+            if let (ConnectionMode::Ssh2, Some(handler)) = (&self.connectionmode, &self.ssh2) {
+                return handler.download(remote_path);
+            }
+            if let (ConnectionMode::LocalHost, Some(handler)) =
+                (&self.connectionmode, &self.localhost)
+            {
+                if let WhichUser::CurrentUser = handler.user {
+                    return handler.download(remote_path);
+                }
+            }
         }
+
+        let cmd_result = self.run(&download_cmd(remote_path), privilege)?;
+        if cmd_result.rc != 0 {
+            return Err(self.transfer_failed(remote_path, cmd_result.stderr.trim()));
+        }
+        decode_download(&cmd_result.stdout)
+            .map_err(|details| self.transfer_failed(remote_path, &details))
+    }
+
+    /// What was learned while initializing the connection (empty before init), and the reconnections since
+    pub fn connection_report(&self) -> ConnectionReport {
+        let mut report = match self.connectionmode {
+            #[cfg(feature = "ssh2")]
+            ConnectionMode::Ssh2 => match &self.ssh2 {
+                Some(ssh2_handler) => ssh2_handler.report.clone(),
+                None => ConnectionReport::new(),
+            },
+            #[cfg(feature = "russh")]
+            ConnectionMode::Russh => match &self.russh {
+                Some(russh_handler) => russh_handler.report.clone(),
+                None => ConnectionReport::new(),
+            },
+            _ => ConnectionReport::new(),
+        };
+        report.reconnections = self.reconnections.clone();
+        report
+    }
+
+    fn transfer_failed(&self, path: &str, details: &str) -> Error {
+        Error::Connection(ConnectionError::TransferFailed {
+            address: self.hostaddress.clone(),
+            path: path.to_string(),
+            source: std::io::Error::other(details.to_string()),
+        })
+    }
+
+    // The connection mode and its handler are public : they may have been set inconsistently
+    fn missing_handler(&self) -> Error {
+        Error::Connection(ConnectionError::Unset(format!(
+            "No handler initialized for ConnectionMode::{:?}",
+            self.connectionmode
+        )))
+    }
+}
+
+// The user to become, None when the command is run as the connected user
+fn become_user(privilege: &Privilege) -> Option<String> {
+    match privilege {
+        Privilege::Usual => None,
+        Privilege::WithSudo => Some("root".to_string()),
+        Privilege::AsUser(username) => Some(username.clone()),
     }
 }
 
@@ -0,0 +1,215 @@
+//! Checking a host key against OpenSSH known hosts (~/.ssh/known_hosts), whichever SSH backend received it
+//!
+//! Lines are `patterns key-type base64-key [comment]`, patterns being comma separated (wildcards and negation as in
+//! ssh_config) or hashed (`|1|salt|hash`, HashKnownHosts). Keys marked `@revoked` are refused, `@cert-authority` lines
+//! are ignored. Lines that can't be read are ignored, like OpenSSH does.
+
+use crate::connection::connectionmode::ssh2mode::DEFAULT_SSH2_PORT;
+use crate::connection::sshconfig::host_matches;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KnownHostCheck {
+    /// The host is known with this key
+    Match,
+    /// The host is known with another key of the same type, or this key is revoked
+    Mismatch,
+    /// The host is not known with a key of this type
+    NotFound,
+}
+
+/// host_key is the key as sent by the server (SSH wire format)
+pub fn check_known_host<S: AsRef<str>>(
+    lines: &[S],
+    address: &str,
+    port: u16,
+    host_key: &[u8],
+) -> KnownHostCheck {
+    let host = known_host_name(address, port);
+    let host_key_type = key_type(host_key);
+    let mut known = false;
+    let mut other_key_known = false;
+
+    for line in lines.iter() {
+        let mut fields = line.as_ref().split_whitespace();
+        let (marker, patterns) = match fields.next() {
+            None => continue,
+            Some(first) if first.starts_with('#') => continue,
+            Some(first) if first.starts_with('@') => (Some(first), fields.next()),
+            Some(first) => (None, Some(first)),
+        };
+        let known_key = fields
+            .nth(1)
+            .and_then(|key| base64::engine::general_purpose::STANDARD.decode(key).ok());
+        let (patterns, known_key) = match (patterns, known_key) {
+            (Some(patterns), Some(known_key)) => (patterns, known_key),
+            _ => continue,
+        };
+        if !patterns_match(patterns, &host) {
+            continue;
+        }
+
+        match marker {
+            None if known_key == host_key => known = true,
+            None if key_type(&known_key) == host_key_type => other_key_known = true,
+            // Wherever it is in the file
+            Some("@revoked") if known_key == host_key => return KnownHostCheck::Mismatch,
+            _ => {}
+        }
+    }
+
+    match (known, other_key_known) {
+        (true, _) => KnownHostCheck::Match,
+        (false, true) => KnownHostCheck::Mismatch,
+        (false, false) => KnownHostCheck::NotFound,
+    }
+}
+
+/// How the host is written in known_hosts : address alone on the default port, [address]:port otherwise
+pub fn known_host_name(address: &str, port: u16) -> String {
+    if port == DEFAULT_SSH2_PORT {
+        address.to_string()
+    } else {
+        format!("[{}]:{}", address, port)
+    }
+}
+
+/// The key blob starts with its type : 4 bytes length, then the name (ex: ssh-ed25519)
+pub fn key_type(key: &[u8]) -> String {
+    key.get(0..4)
+        .map(|length| u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize)
+        .and_then(|length| key.get(4..4 + length))
+        .map(|name| String::from_utf8_lossy(name).to_string())
+        .unwrap_or_default()
+}
+
+fn patterns_match(patterns: &str, host: &str) -> bool {
+    if let Some(hashed) = patterns.strip_prefix("|1|") {
+        return hashed_host_matches(hashed, host);
+    }
+    let patterns: Vec<String> = patterns
+        .split(',')
+        .map(|pattern| pattern.to_lowercase())
+        .collect();
+    host_matches(&host.to_lowercase(), &patterns)
+}
+
+// salt|hash, both in base64, hash being HMAC-SHA1(salt, host)
+fn hashed_host_matches(hashed: &str, host: &str) -> bool {
+    let engine = base64::engine::general_purpose::STANDARD;
+    let (salt, hash) = match hashed.split_once('|') {
+        Some((salt, hash)) => (engine.decode(salt), engine.decode(hash)),
+        None => return false,
+    };
+    let (salt, hash) = match (salt, hash) {
+        (Ok(salt), Ok(hash)) => (salt, hash),
+        _ => return false,
+    };
+    let mut mac = match Hmac::<Sha1>::new_from_slice(&salt) {
+        Ok(mac) => mac,
+        Err(_) => return false,
+    };
+    mac.update(host.as_bytes());
+    mac.verify_slice(&hash).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRST_HOST_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIECMrMQDkWPuuHtggZ03LqxAoKmuZ7tgU9Y3wuMbkWL5";
+    const SECOND_HOST_KEY: &str =
+        "AAAAC3NzaC1lZDI1NTE5AAAAIHcOwQ7j1NCw5Mf5ROEyyuAQ9PVk9HZTtEeyOG2BUScm";
+    const RSA_HOST_KEY: &str = "AAAAB3NzaC1yc2EAAAADAQABAAAAgQC7";
+
+    fn raw_key(encoded: &str) -> Vec<u8> {
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .unwrap()
+    }
+
+    #[test]
+    fn hosts_are_matched_like_openssh() {
+        let lines = vec![
+            "# comment".to_string(),
+            format!(
+                "web1,10.20.30.51 ssh-ed25519 {} deploy@laptop",
+                FIRST_HOST_KEY
+            ),
+            format!("[10.20.30.51]:2222 ssh-ed25519 {}", SECOND_HOST_KEY),
+            format!("*.internal,!db.internal ssh-ed25519 {}", FIRST_HOST_KEY),
+            // web2, hashed
+            format!(
+                "|1|CnaOpuo2sSWSS0ENDC6NoGdXCw4=|nQGZe1tdggxvriiPuoEWzoj1ByY= ssh-ed25519 {}",
+                FIRST_HOST_KEY
+            ),
+        ];
+        let first = raw_key(FIRST_HOST_KEY);
+        let second = raw_key(SECOND_HOST_KEY);
+
+        assert_eq!(
+            check_known_host(&lines, "10.20.30.51", 22, &first),
+            KnownHostCheck::Match
+        );
+        assert_eq!(
+            check_known_host(&lines, "WEB1", 22, &first),
+            KnownHostCheck::Match
+        );
+        assert_eq!(
+            check_known_host(&lines, "10.20.30.51", 22, &second),
+            KnownHostCheck::Mismatch
+        );
+        assert_eq!(
+            check_known_host(&lines, "10.20.30.51", 2222, &second),
+            KnownHostCheck::Match
+        );
+        assert_eq!(
+            check_known_host(&lines, "10.20.30.51", 2200, &first),
+            KnownHostCheck::NotFound
+        );
+        assert_eq!(
+            check_known_host(&lines, "app.internal", 22, &first),
+            KnownHostCheck::Match
+        );
+        assert_eq!(
+            check_known_host(&lines, "db.internal", 22, &first),
+            KnownHostCheck::NotFound
+        );
+        assert_eq!(
+            check_known_host(&lines, "web2", 22, &first),
+            KnownHostCheck::Match
+        );
+        assert_eq!(
+            check_known_host(&lines, "web2", 22, &second),
+            KnownHostCheck::Mismatch
+        );
+        assert_eq!(
+            check_known_host(&lines, "web3", 22, &first),
+            KnownHostCheck::NotFound
+        );
+    }
+
+    #[test]
+    fn revoked_keys_and_other_key_types() {
+        let lines = vec![
+            format!("@cert-authority * ssh-ed25519 {}", FIRST_HOST_KEY),
+            format!("web1 ssh-rsa {}", RSA_HOST_KEY),
+            "web1 ssh-ed25519 not-base64".to_string(),
+            format!("web1 ssh-ed25519 {}", SECOND_HOST_KEY),
+            format!("@revoked * ssh-ed25519 {}", SECOND_HOST_KEY),
+        ];
+
+        // Known with its RSA key only : an ed25519 key is new, not a mismatch
+        assert_eq!(
+            check_known_host(&lines[..3], "web1", 22, &raw_key(FIRST_HOST_KEY)),
+            KnownHostCheck::NotFound
+        );
+        assert_eq!(
+            check_known_host(&lines, "web1", 22, &raw_key(SECOND_HOST_KEY)),
+            KnownHostCheck::Mismatch
+        );
+    }
+}
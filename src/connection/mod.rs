@@ -1,6 +1,12 @@
 //! Where connections to targetted hosts are handled
 
+pub mod argv;
 pub mod connectionmode;
+pub mod escalation;
 pub mod host_connection;
 pub mod hosthandler;
+pub mod knownhosts;
+pub mod pool;
 pub mod specification;
+pub mod sshconfig;
+pub mod transfer;
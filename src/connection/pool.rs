@@ -0,0 +1,298 @@
+//! Reusing connections across Jobs
+//!
+//! Without a pool, each run of a Job opens its own connection (a full SSH handshake). With a pool, connections are given
+//! back once the run is over and the next run on the same host, with the same connection information, borrows one of them.
+//! The pool is cheap to clone (all clones share the same connections) : keep one for the whole life of a program and
+//! give it to every JobList.
+
+use crate::connection::escalation::BecomeMethod;
+use crate::connection::host_connection::HostConnectionInfo;
+use crate::connection::hosthandler::HostHandler;
+use crate::error::Error;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long an unused connection is kept by default
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Clone)]
+pub struct ConnectionPool {
+    idle_timeout: Duration,
+    idle: Arc<Mutex<HashMap<String, Vec<IdleConnection>>>>, // key -> connections not used at the moment
+}
+
+struct IdleConnection {
+    host_handler: HostHandler,
+    since: Instant,
+}
+
+impl Default for ConnectionPool {
+    fn default() -> ConnectionPool {
+        ConnectionPool::new()
+    }
+}
+
+impl ConnectionPool {
+    pub fn new() -> ConnectionPool {
+        ConnectionPool::with_idle_timeout(DEFAULT_IDLE_TIMEOUT)
+    }
+
+    /// Connections unused for longer than this are closed instead of being reused
+    pub fn with_idle_timeout(idle_timeout: Duration) -> ConnectionPool {
+        ConnectionPool {
+            idle_timeout,
+            idle: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// An initialized HostHandler : a healthy idle connection to this host if there is one, a new one otherwise.
+    /// It goes back to the pool when dropped.
+    pub fn borrow(
+        &self,
+        address: &str,
+        host_connection_info: &HostConnectionInfo,
+    ) -> Result<PooledHostHandler, Error> {
+        let key = pool_key(address, host_connection_info);
+
+        // The host may have closed the connection since it was given back (timeout, reboot...) : a new one is
+        // cheaper than reconnecting with the policy's backoff
+        while let Some(mut host_handler) = self.take_idle(&key) {
+            if host_handler.is_alive() {
+                host_handler.reconnections.clear();
+                return Ok(PooledHostHandler {
+                    host_handler: Some(host_handler),
+                    pool: Some((self.clone(), key)),
+                });
+            }
+        }
+
+        let mut host_handler =
+            HostHandler::from(address.to_string(), host_connection_info.clone())?;
+        host_handler.init()?;
+        Ok(PooledHostHandler {
+            host_handler: Some(host_handler),
+            pool: Some((self.clone(), key)),
+        })
+    }
+
+    /// Number of connections waiting to be reused
+    pub fn idle_count(&self) -> usize {
+        let mut idle = self.lock();
+        self.expire(&mut idle);
+        idle.values().map(|connections| connections.len()).sum()
+    }
+
+    /// Closes all idle connections. Borrowed ones are still given back.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn take_idle(&self, key: &str) -> Option<HostHandler> {
+        let mut idle = self.lock();
+        self.expire(&mut idle);
+        // Most recently used first : the least likely to have been closed by the host
+        idle.get_mut(key)
+            .and_then(|connections| connections.pop())
+            .map(|connection| connection.host_handler)
+    }
+
+    fn give_back(&self, key: String, mut host_handler: HostHandler) {
+        // Nothing of the previous run is kept, only the connection
+        host_handler.output_sink = None;
+        host_handler.set_current_step(None);
+        host_handler.set_step_become_method(None);
+        host_handler.become_method = BecomeMethod::Sudo;
+        host_handler.become_password = None;
+        host_handler.set_checking(false);
+
+        // Expired connections are closed as soon as possible, not only when the pool is used to borrow
+        let mut idle = self.lock();
+        self.expire(&mut idle);
+        idle.entry(key).or_default().push(IdleConnection {
+            host_handler,
+            since: Instant::now(),
+        });
+    }
+
+    // Dropping a HostHandler closes its connection
+    fn expire(&self, idle: &mut HashMap<String, Vec<IdleConnection>>) {
+        for connections in idle.values_mut() {
+            connections.retain(|connection| connection.since.elapsed() < self.idle_timeout);
+        }
+        idle.retain(|_, connections| !connections.is_empty());
+    }
+
+    // A panic while the lock was held can't leave the map inconsistent : only whole entries are added or removed
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Vec<IdleConnection>>> {
+        self.idle
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl std::fmt::Debug for ConnectionPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "ConnectionPool {{ idle_timeout: {:?}, idle: {} }}",
+            self.idle_timeout,
+            self.idle_count()
+        )
+    }
+}
+
+/// A HostHandler borrowed from a ConnectionPool (or not pooled at all), used as a HostHandler
+pub struct PooledHostHandler {
+    host_handler: Option<HostHandler>, // Only None while being dropped
+    pool: Option<(ConnectionPool, String)>,
+}
+
+impl PooledHostHandler {
+    /// A HostHandler owned by nobody else, simply closed when dropped
+    pub fn unpooled(host_handler: HostHandler) -> PooledHostHandler {
+        PooledHostHandler {
+            host_handler: Some(host_handler),
+            pool: None,
+        }
+    }
+}
+
+impl Deref for PooledHostHandler {
+    type Target = HostHandler;
+
+    fn deref(&self) -> &HostHandler {
+        self.host_handler.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for PooledHostHandler {
+    fn deref_mut(&mut self) -> &mut HostHandler {
+        self.host_handler.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledHostHandler {
+    fn drop(&mut self) {
+        if let (Some((pool, key)), Some(host_handler)) =
+            (self.pool.take(), self.host_handler.take())
+        {
+            pool.give_back(key, host_handler);
+        }
+    }
+}
+
+// Connections are only shared between identical connection information. Secrets are part of it : the key is a hash.
+fn pool_key(address: &str, host_connection_info: &HostConnectionInfo) -> String {
+    let connection_info = serde_json::to_string(host_connection_info).unwrap_or_default();
+    format!(
+        "{:X}",
+        Sha256::digest(format!("{}\n{}", address, connection_info))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::argv::Argv;
+    use crate::connection::connectionmode::fake::{FakeResponse, FakeTransport};
+    use crate::connection::specification::Privilege;
+
+    // Stands for the time passing, without waiting
+    fn age_idle_connections(pool: &ConnectionPool, by: Duration) {
+        for connections in pool.lock().values_mut() {
+            for connection in connections.iter_mut() {
+                connection.since = connection.since.checked_sub(by).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn connections_are_reused_until_they_expire() {
+        let pool = ConnectionPool::with_idle_timeout(Duration::from_secs(60));
+        let localhost = HostConnectionInfo::localhost_current_user();
+
+        let first = pool.borrow("localhost", &localhost).unwrap();
+        let second = pool.borrow("localhost", &localhost).unwrap();
+        assert_eq!(pool.idle_count(), 0);
+        drop(first);
+        drop(second);
+        assert_eq!(pool.idle_count(), 2);
+
+        let mut borrowed = pool.borrow("localhost", &localhost).unwrap();
+        assert_eq!(pool.idle_count(), 1);
+        assert_eq!(
+            borrowed
+                .run(&Argv::new("echo").arg("reused"), Privilege::Usual)
+                .unwrap()
+                .stdout,
+            "reused\n"
+        );
+        drop(borrowed);
+
+        // Another address never gets these connections
+        let other = pool.borrow("127.0.0.1", &localhost).unwrap();
+        assert_eq!(pool.idle_count(), 2);
+        drop(other);
+        assert_eq!(pool.idle_count(), 3);
+
+        age_idle_connections(&pool, Duration::from_secs(61));
+        assert_eq!(pool.idle_count(), 0);
+    }
+
+    #[test]
+    fn expired_connections_are_closed_when_another_is_given_back() {
+        let pool = ConnectionPool::with_idle_timeout(Duration::from_secs(60));
+        let localhost = HostConnectionInfo::localhost_current_user();
+
+        drop(pool.borrow("localhost", &localhost).unwrap());
+        let other = pool.borrow("127.0.0.1", &localhost).unwrap();
+        age_idle_connections(&pool, Duration::from_secs(61));
+        drop(other);
+
+        let idle = pool.lock();
+        assert!(!idle.contains_key(&pool_key("localhost", &localhost)));
+        assert_eq!(idle[&pool_key("127.0.0.1", &localhost)].len(), 1);
+    }
+
+    #[test]
+    fn unhealthy_connections_are_replaced() {
+        let pool = ConnectionPool::new();
+        let localhost = HostConnectionInfo::localhost_current_user();
+
+        let mut borrowed = pool.borrow("localhost", &localhost).unwrap();
+        // Stands for a connection closed by the host in the meantime
+        borrowed.connectionmode = crate::connection::specification::ConnectionMode::Unset;
+        drop(borrowed);
+        assert_eq!(pool.idle_count(), 1);
+
+        let mut replacement = pool.borrow("localhost", &localhost).unwrap();
+        assert!(replacement
+            .run(&Argv::new("true"), Privilege::Usual)
+            .is_ok());
+        drop(replacement);
+        assert_eq!(pool.idle_count(), 1);
+    }
+
+    #[test]
+    fn lost_sessions_are_replaced_without_reconnecting() {
+        let pool = ConnectionPool::new();
+        let fake = HostConnectionInfo::Fake(
+            FakeTransport::new().on("true", FakeResponse::SessionDrop(0, String::new())),
+        );
+
+        drop(pool.borrow("10.20.30.51", &fake).unwrap());
+        let replacement = pool.borrow("10.20.30.51", &fake).unwrap();
+        assert!(replacement.reconnections.is_empty());
+        // A new connection : the lost one wasn't reconnected and reused
+        assert!(replacement
+            .fake
+            .as_ref()
+            .unwrap()
+            .dropped_patterns
+            .is_empty());
+        assert_eq!(pool.idle_count(), 0);
+    }
+}
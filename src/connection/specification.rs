@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 /// ConnectionMode is not directly withholding different host handlers like Ssh2(Ssh2HostHandler)
 /// because Serialize trait is not implemented for one of the Ssh2 structs. Instead, we pass the
@@ -8,8 +9,15 @@ use serde::{Deserialize, Serialize};
 pub enum ConnectionMode {
     Unset,
     LocalHost,
+    /// SSH2 through libssh2 (SshBackend::Libssh2)
+    #[cfg(feature = "ssh2")]
     Ssh2,
+    /// SSH2 through russh (SshBackend::Russh)
+    #[cfg(feature = "russh")]
+    Russh,
     // Ssh3
+    #[cfg(test)]
+    Fake,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -22,6 +30,88 @@ pub enum Privilege {
     AsUser(String),
 }
 
+/// What was learned while connecting to a host, to be shown in the Job output
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionReport {
+    /// Identity used to authenticate, when several could be tried (SSH agent)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authenticated_with: Option<String>,
+    /// SHA256 fingerprint of the host key presented by the server
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_key_fingerprint: Option<String>,
+    /// Sessions lost during the run and what happened next
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reconnections: Vec<Reconnection>,
+}
+
+impl ConnectionReport {
+    pub fn new() -> ConnectionReport {
+        ConnectionReport {
+            authenticated_with: None,
+            host_key_fingerprint: None,
+            reconnections: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.authenticated_with.is_none()
+            && self.host_key_fingerprint.is_none()
+            && self.reconnections.is_empty()
+    }
+}
+
+/// A session lost in the middle of a run (network blip, host rebooting...)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reconnection {
+    /// Why the session was considered lost
+    pub cause: String,
+    /// Connections tried before getting a new session, or before giving up
+    pub attempts: u32,
+    pub reconnected: bool,
+    /// The command running when the session was lost was run again (only for checks, which don't change the host)
+    pub command_retried: bool,
+}
+
+/// How a lost session is established again
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReconnectPolicy {
+    /// 0 disables reconnecting : all remaining steps fail
+    pub max_attempts: u32,
+    /// Wait before the first attempt, doubled after each failed one
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl ReconnectPolicy {
+    /// 6 attempts over about a minute (1, 2, 4, 8, 16 then 30 seconds of wait) : long enough for a host to reboot
+    pub fn new() -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: 6,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+
+    pub fn disabled() -> ReconnectPolicy {
+        ReconnectPolicy {
+            max_attempts: 0,
+            initial_delay: Duration::ZERO,
+            max_delay: Duration::ZERO,
+        }
+    }
+
+    /// Wait before each attempt
+    pub fn delays(&self) -> Vec<Duration> {
+        let mut delays = Vec::new();
+        let mut delay = self.initial_delay;
+        for _ in 0..self.max_attempts {
+            delays.push(delay.min(self.max_delay));
+            delay = delay.saturating_mul(2);
+        }
+        delays
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Credentials {
     pub username: String,
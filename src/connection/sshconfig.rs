@@ -0,0 +1,353 @@
+//! Reading OpenSSH client configuration files (~/.ssh/config)
+//!
+//! Only `Host` blocks and the options duxcore can use are considered : `HostName`, `Port`, `User`, `IdentityFile`, `ProxyJump` and `ServerAliveInterval`.
+//! Other options are ignored. `Match` blocks and `Include` are not supported : their content is ignored.
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SshConfig {
+    blocks: Vec<SshConfigBlock>,
+}
+
+// Options before the first Host line apply to all hosts (patterns = ["*"])
+#[derive(Debug, Clone, PartialEq)]
+struct SshConfigBlock {
+    patterns: Vec<String>,
+    options: Vec<SshConfigOption>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SshConfigOption {
+    HostName(String),
+    Port(u16),
+    User(String),
+    IdentityFile(String),
+    ProxyJump(String),
+    ServerAliveInterval(u32),
+}
+
+/// What the configuration says about one host. Tokens (%h, %d, %u, %r, ~) are not expanded yet.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SshConfigHost {
+    pub hostname: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub identity_files: Vec<String>,
+    pub proxy_jump: Option<String>,
+    pub server_alive_interval: Option<u32>,
+}
+
+impl SshConfig {
+    pub fn new() -> SshConfig {
+        SshConfig { blocks: Vec::new() }
+    }
+
+    /// Like OpenSSH, the first value obtained for each option is used, except for IdentityFile which accumulates
+    pub fn resolve(&self, host: &str) -> SshConfigHost {
+        let mut config_host = SshConfigHost {
+            hostname: None,
+            port: None,
+            user: None,
+            identity_files: Vec::new(),
+            proxy_jump: None,
+            server_alive_interval: None,
+        };
+
+        for block in self.blocks.iter() {
+            if !host_matches(host, &block.patterns) {
+                continue;
+            }
+            for option in block.options.iter() {
+                match option {
+                    SshConfigOption::HostName(hostname) => {
+                        config_host.hostname.get_or_insert(hostname.clone());
+                    }
+                    SshConfigOption::Port(port) => {
+                        config_host.port.get_or_insert(*port);
+                    }
+                    SshConfigOption::User(user) => {
+                        config_host.user.get_or_insert(user.clone());
+                    }
+                    SshConfigOption::IdentityFile(identity_file) => {
+                        config_host.identity_files.push(identity_file.clone());
+                    }
+                    SshConfigOption::ProxyJump(proxy_jump) => {
+                        config_host.proxy_jump.get_or_insert(proxy_jump.clone());
+                    }
+                    SshConfigOption::ServerAliveInterval(interval) => {
+                        config_host.server_alive_interval.get_or_insert(*interval);
+                    }
+                }
+            }
+        }
+
+        config_host
+    }
+}
+
+/// Errors tell the line number and what is wrong with it
+impl FromStr for SshConfig {
+    type Err = String;
+
+    fn from_str(content: &str) -> Result<SshConfig, String> {
+        let mut blocks: Vec<SshConfigBlock> = vec![SshConfigBlock {
+            patterns: vec!["*".to_string()],
+            options: Vec::new(),
+        }];
+
+        for (index, raw_line) in content.lines().enumerate() {
+            let line = raw_line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (keyword, arguments) = split_line(line);
+            let invalid = |details: &str| format!("line {} : {}", index + 1, details);
+            let single_argument = || match arguments.first() {
+                Some(argument) => Ok(argument.clone()),
+                None => Err(invalid(format!("{} requires a value", keyword).as_str())),
+            };
+
+            let option = match keyword.to_lowercase().as_str() {
+                "host" => {
+                    if arguments.is_empty() {
+                        return Err(invalid("Host requires at least one pattern"));
+                    }
+                    blocks.push(SshConfigBlock {
+                        patterns: arguments.clone(),
+                        options: Vec::new(),
+                    });
+                    continue;
+                }
+                "match" => {
+                    // Never matches : its options are ignored until the next Host line
+                    blocks.push(SshConfigBlock {
+                        patterns: Vec::new(),
+                        options: Vec::new(),
+                    });
+                    continue;
+                }
+                "hostname" => SshConfigOption::HostName(single_argument()?),
+                "port" => match single_argument()?.parse::<u16>() {
+                    Ok(port) => SshConfigOption::Port(port),
+                    Err(_) => return Err(invalid("Port must be a number between 0 and 65535")),
+                },
+                "user" => SshConfigOption::User(single_argument()?),
+                "identityfile" => SshConfigOption::IdentityFile(single_argument()?),
+                "proxyjump" => SshConfigOption::ProxyJump(single_argument()?),
+                "serveraliveinterval" => match single_argument()?.parse::<u32>() {
+                    Ok(interval) => SshConfigOption::ServerAliveInterval(interval),
+                    Err(_) => {
+                        return Err(invalid("ServerAliveInterval must be a number of seconds"))
+                    }
+                },
+                _ => continue,
+            };
+
+            if let Some(block) = blocks.last_mut() {
+                block.options.push(option);
+            }
+        }
+
+        Ok(SshConfig { blocks })
+    }
+}
+
+/// Expands the tokens allowed in HostName (%h) and IdentityFile (~, %d, %u, %r, %h). Without home directory, ~ and %d are left as they are.
+pub fn expand_tokens(value: &str, host: &str, remote_user: &str) -> String {
+    let home = home_directory();
+    let value = match (value.strip_prefix("~/"), &home) {
+        (Some(rest), Some(home)) => home.join(rest).display().to_string(),
+        _ => value.to_string(),
+    };
+
+    let mut expanded = String::new();
+    let mut characters = value.chars();
+    while let Some(character) = characters.next() {
+        if character != '%' {
+            expanded.push(character);
+            continue;
+        }
+        match characters.next() {
+            Some('h') => expanded.push_str(host),
+            Some('d') => match &home {
+                Some(home) => expanded.push_str(home.display().to_string().as_str()),
+                None => expanded.push_str("%d"),
+            },
+            Some('u') => expanded.push_str(local_username().as_str()),
+            Some('r') => expanded.push_str(remote_user),
+            Some('%') => expanded.push('%'),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    expanded
+}
+
+/// None when HOME is unset or empty : paths under it can't be resolved
+pub fn home_directory() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Default remote user, like OpenSSH
+pub fn local_username() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .unwrap_or_default()
+}
+
+// "Keyword value", "Keyword=value" and "Keyword = value" are all valid. Values can be quoted.
+fn split_line(line: &str) -> (String, Vec<String>) {
+    let separator = line
+        .find(|character: char| character.is_whitespace() || character == '=')
+        .unwrap_or(line.len());
+    let keyword = line[..separator].to_string();
+    let rest = line[separator..].trim_start();
+    let rest = rest.strip_prefix('=').unwrap_or(rest).trim_start();
+
+    let mut arguments: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    for character in rest.chars() {
+        match character {
+            '"' => in_quotes = !in_quotes,
+            character if character.is_whitespace() && !in_quotes => {
+                if !current.is_empty() {
+                    arguments.push(std::mem::take(&mut current));
+                }
+            }
+            character => current.push(character),
+        }
+    }
+    if !current.is_empty() {
+        arguments.push(current);
+    }
+
+    (keyword, arguments)
+}
+
+pub(crate) fn host_matches(host: &str, patterns: &[String]) -> bool {
+    let mut matched = false;
+    for pattern in patterns.iter() {
+        match pattern.strip_prefix('!') {
+            Some(negated_pattern) => {
+                if wildcard_match(negated_pattern, host) {
+                    return false;
+                }
+            }
+            None => {
+                if wildcard_match(pattern, host) {
+                    matched = true;
+                }
+            }
+        }
+    }
+    matched
+}
+
+// '*' matches any sequence, '?' exactly one character
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut pattern_index, mut value_index) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+
+    while value_index < value.len() {
+        if pattern_index < pattern.len()
+            && (pattern[pattern_index] == '?' || pattern[pattern_index] == value[value_index])
+        {
+            pattern_index += 1;
+            value_index += 1;
+        } else if pattern_index < pattern.len() && pattern[pattern_index] == '*' {
+            last_star = Some((pattern_index, value_index));
+            pattern_index += 1;
+        } else if let Some((star_index, star_value_index)) = last_star {
+            pattern_index = star_index + 1;
+            value_index = star_value_index + 1;
+            last_star = Some((star_index, star_value_index + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[pattern_index..]
+        .iter()
+        .all(|character| *character == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_obtained_value_wins() {
+        let config = SshConfig::from_str(
+            "# Defaults for the whole fleet
+User admin
+
+Host db? !db9
+    HostName %h.prod.internal
+    Port=2222
+    IdentityFile ~/.ssh/db_key
+
+Host *.internal db*
+    Port 22
+    User \"deploy\"
+    IdentityFile ~/.ssh/id_ed25519
+    ProxyJump bastion
+    ServerAliveInterval 30
+
+Match exec \"true\"
+    Port 2022
+",
+        )
+        .unwrap();
+
+        let db1 = config.resolve("db1");
+        assert_eq!(db1.hostname, Some("%h.prod.internal".into()));
+        assert_eq!(db1.port, Some(2222));
+        assert_eq!(db1.user, Some("admin".into()));
+        assert_eq!(
+            db1.identity_files,
+            vec!["~/.ssh/db_key".to_string(), "~/.ssh/id_ed25519".to_string()]
+        );
+        assert_eq!(db1.proxy_jump, Some("bastion".into()));
+        assert_eq!(db1.server_alive_interval, Some(30));
+
+        let db9 = config.resolve("db9");
+        assert_eq!(db9.hostname, None);
+        assert_eq!(db9.port, Some(22));
+
+        let other = config.resolve("web1");
+        assert_eq!(other.port, None);
+        assert_eq!(other.user, Some("admin".into()));
+        assert!(other.identity_files.is_empty());
+    }
+
+    #[test]
+    fn invalid_values_are_refused() {
+        assert!(SshConfig::from_str("Host db1\n  Port twenty-two\n")
+            .unwrap_err()
+            .starts_with("line 2"));
+        assert!(SshConfig::from_str("Host\n").is_err());
+    }
+
+    #[test]
+    fn tokens_are_expanded() {
+        assert_eq!(
+            expand_tokens("%h.prod.internal", "db1", "deploy"),
+            "db1.prod.internal"
+        );
+        assert_eq!(
+            expand_tokens("%d/.ssh/%r@%h", "db1", "deploy"),
+            format!("{}/.ssh/deploy@db1", home_directory().unwrap().display())
+        );
+        assert_eq!(expand_tokens("100%%", "db1", "deploy"), "100%");
+    }
+}
@@ -0,0 +1,206 @@
+//! Moving files to and from hosts
+//!
+//! Files are always written to a temporary file next to the destination, then renamed : the destination is either
+//! the old file or the complete new one, never a partial write.
+//! When the transfer can't be done directly by the connection (privilege escalation, another local user...), it goes
+//! through shell commands instead : content is given on stdin for uploads and read in base64 for downloads.
+
+use crate::connection::argv::Argv;
+use crate::error::connection::ConnectionError;
+use crate::error::Error;
+use base64::Engine;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Permissions given to new files when no mode is specified
+pub const DEFAULT_FILE_MODE: u32 = 0o644;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UploadContent {
+    /// Path of a file on the machine running duxcore
+    LocalFile(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+impl UploadContent {
+    pub fn bytes(&self) -> Result<Vec<u8>, Error> {
+        match self {
+            UploadContent::LocalFile(path) => match std::fs::read(path) {
+                Ok(bytes) => Ok(bytes),
+                Err(e) => Err(Error::Connection(ConnectionError::TransferFailed {
+                    address: "localhost".to_string(),
+                    path: path.display().to_string(),
+                    source: e,
+                })),
+            },
+            UploadContent::Bytes(bytes) => Ok(bytes.clone()),
+        }
+    }
+}
+
+/// Hidden file in the same directory as the destination, so that renaming it is atomic
+pub fn temp_path(path: &Path) -> PathBuf {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.subsec_nanos())
+        .unwrap_or_default();
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    path.with_file_name(format!(
+        ".{}.dux-tmp-{}-{}",
+        file_name,
+        std::process::id(),
+        nanos
+    ))
+}
+
+/// Writes stdin to the destination. An existing destination keeps its permissions (and owner when run as root) unless a mode is given.
+pub fn upload_cmd(remote_path: &str, mode: Option<u32>) -> Argv {
+    let chmod = match mode {
+        Some(mode) => format!("chmod {:o} \"$tmp\" && ", mode),
+        None => String::new(),
+    };
+    let script = format!(
+        "tmp=\"$(dirname -- \"$1\")/.$(basename -- \"$1\").dux-tmp-$$\"; \
+         {{ if [ -e \"$1\" ]; then cp -p -- \"$1\" \"$tmp\"; else : > \"$tmp\" && chmod {:o} \"$tmp\"; fi; }} \
+         && cat > \"$tmp\" && {}mv -f -- \"$tmp\" \"$1\" || {{ rm -f -- \"$tmp\"; exit 1; }}",
+        DEFAULT_FILE_MODE, chmod
+    );
+    Argv::new("sh")
+        .arg("-c")
+        .arg(script)
+        .arg("dux-upload")
+        .arg(remote_path)
+}
+
+pub fn download_cmd(remote_path: &str) -> Argv {
+    Argv::new("base64").arg("--").arg(remote_path)
+}
+
+/// Output of download_cmd back to the file's content
+pub fn decode_download(stdout: &str) -> Result<Vec<u8>, String> {
+    let encoded: String = stdout
+        .chars()
+        .filter(|character| !character.is_whitespace())
+        .collect();
+    base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::connectionmode::localhost::LocalHostHandler;
+    use crate::connection::host_connection::HostConnectionInfo;
+    use crate::connection::hosthandler::HostHandler;
+    use crate::connection::specification::Privilege;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn commands_transfer_any_content() {
+        let directory = std::env::temp_dir().join(format!("dux-transfer-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("it's a file");
+        let path = file.display().to_string();
+        let content: Vec<u8> = vec![0, 255, b'\'', b'"', b'$', b'\n', 13, 200];
+        let handler = LocalHostHandler::new();
+
+        let upload = handler
+            .run_cmd_with_input(&upload_cmd(&path, None).to_shell(), Some(&content), None)
+            .unwrap();
+        assert_eq!(upload.rc, 0, "{}", upload.stderr);
+        assert_eq!(std::fs::read(&file).unwrap(), content);
+        assert_eq!(
+            std::fs::metadata(&file).unwrap().permissions().mode() & 0o7777,
+            DEFAULT_FILE_MODE
+        );
+
+        // Replacing the file keeps its permissions unless a mode is given
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o600)).unwrap();
+        handler
+            .run_cmd_with_input(&upload_cmd(&path, None).to_shell(), Some(b"new"), None)
+            .unwrap();
+        assert_eq!(
+            std::fs::metadata(&file).unwrap().permissions().mode() & 0o7777,
+            0o600
+        );
+        handler
+            .run_cmd_with_input(
+                &upload_cmd(&path, Some(0o640)).to_shell(),
+                Some(&content),
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            std::fs::metadata(&file).unwrap().permissions().mode() & 0o7777,
+            0o640
+        );
+
+        let download = handler.run_cmd(&download_cmd(&path).to_shell()).unwrap();
+        assert_eq!(decode_download(&download.stdout).unwrap(), content);
+
+        // Only the destination is left
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn files_are_uploaded_and_downloaded_locally() {
+        let directory = std::env::temp_dir().join(format!("dux-upload-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let source = directory.join("source");
+        std::fs::write(&source, [1u8, 2, 3, 255]).unwrap();
+        let destination = directory.join("destination").display().to_string();
+        let mut hosthandler = HostHandler::from(
+            "localhost".into(),
+            HostConnectionInfo::localhost_current_user(),
+        )
+        .unwrap();
+
+        hosthandler
+            .upload(
+                UploadContent::LocalFile(source.clone()),
+                &destination,
+                Some(0o600),
+                Privilege::Usual,
+            )
+            .unwrap();
+        assert_eq!(
+            hosthandler
+                .download(&destination, Privilege::Usual)
+                .unwrap(),
+            vec![1u8, 2, 3, 255]
+        );
+
+        hosthandler
+            .upload(
+                UploadContent::Bytes(b"replaced".to_vec()),
+                &destination,
+                None,
+                Privilege::Usual,
+            )
+            .unwrap();
+        assert_eq!(std::fs::read(&destination).unwrap(), b"replaced");
+        assert_eq!(
+            std::fs::metadata(&destination)
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o7777,
+            0o600
+        );
+
+        assert!(hosthandler
+            .download(
+                &directory.join("missing").display().to_string(),
+                Privilege::Usual
+            )
+            .is_err());
+        // Source and destination only
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 2);
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
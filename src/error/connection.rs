@@ -0,0 +1,269 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Problems reaching a host or talking to it
+#[derive(Debug)]
+pub enum ConnectionError {
+    /// Connection information or mode not set before being used
+    Unset(String),
+    /// TCP connection to the host impossible
+    HostUnreachable {
+        address: String,
+        source: std::io::Error,
+    },
+    /// An operation didn't complete within its configured time limit
+    TimedOut {
+        address: String,
+        operation: String,
+        after: Duration,
+    },
+    /// Host reached but the SSH session couldn't be established
+    HandshakeFailed { address: String, source: SshError },
+    /// Host reached but credentials were refused
+    AuthenticationFailed {
+        address: String,
+        username: String,
+        source: Option<SshError>,
+    },
+    /// A key file given to authenticate couldn't be read
+    KeyUnreadable {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The server's host key differs from the known one
+    HostKeyMismatch {
+        address: String,
+        fingerprint: String,
+    },
+    /// The server's host key is not known and the policy requires it to be
+    HostKeyUnknown {
+        address: String,
+        fingerprint: String,
+    },
+    /// The known_hosts file couldn't be read or updated
+    KnownHostsUnavailable {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The OpenSSH configuration file couldn't be read
+    SshConfigUnreadable {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The OpenSSH configuration has a wrong value for an option duxcore uses
+    SshConfigInvalid { source: String, details: String },
+    /// The SSH agent couldn't be reached or queried
+    AgentUnavailable { address: String, source: SshError },
+    /// None of the SSH agent's identities was accepted
+    AgentIdentitiesRefused {
+        address: String,
+        username: String,
+        tried: Vec<String>,
+    },
+    /// The jump host couldn't open a channel to the next hop
+    JumpHostFailed {
+        jump_host: String,
+        target: String,
+        source: SshError,
+    },
+    /// An established session couldn't be used to run a command (channel, I/O...)
+    SessionFailed { address: String, source: SshError },
+    /// A command couldn't be started on localhost
+    LocalCommandFailed(std::io::Error),
+    /// The command couldn't be run with privileges (wrong or missing password, user not allowed...)
+    BecomeFailed {
+        address: String,
+        user: String,
+        details: String,
+    },
+    /// A file couldn't be uploaded to or downloaded from the host
+    TransferFailed {
+        address: String,
+        path: String,
+        source: std::io::Error,
+    },
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectionError::Unset(details) => {
+                write!(f, "connection not initialized : {}", details)
+            }
+            ConnectionError::HostUnreachable { address, .. } => {
+                write!(f, "host {} unreachable", address)
+            }
+            ConnectionError::TimedOut {
+                address,
+                operation,
+                after,
+            } => write!(
+                f,
+                "timeout : {} with {} took more than {:?}",
+                operation, address, after
+            ),
+            ConnectionError::HandshakeFailed { address, .. } => {
+                write!(f, "SSH handshake with {} failed", address)
+            }
+            ConnectionError::AuthenticationFailed {
+                address, username, ..
+            } => write!(f, "authentication failed on {} as {}", address, username),
+            ConnectionError::KeyUnreadable { path, .. } => {
+                write!(f, "unable to read key {}", path.display())
+            }
+            ConnectionError::HostKeyMismatch {
+                address,
+                fingerprint,
+            } => write!(
+                f,
+                "host key of {} doesn't match the known one (got {}) : possible man-in-the-middle attack",
+                address, fingerprint
+            ),
+            ConnectionError::HostKeyUnknown {
+                address,
+                fingerprint,
+            } => write!(
+                f,
+                "host key of {} is unknown ({}) and strict verification is required",
+                address, fingerprint
+            ),
+            ConnectionError::KnownHostsUnavailable { path, .. } => {
+                write!(f, "unable to use known hosts file {}", path.display())
+            }
+            ConnectionError::SshConfigUnreadable { path, .. } => {
+                write!(f, "unable to read SSH configuration {}", path.display())
+            }
+            ConnectionError::SshConfigInvalid { source, details } => {
+                write!(f, "invalid SSH configuration {} : {}", source, details)
+            }
+            ConnectionError::AgentUnavailable { address, .. } => {
+                write!(
+                    f,
+                    "unable to use the SSH agent to authenticate on {}",
+                    address
+                )
+            }
+            ConnectionError::AgentIdentitiesRefused {
+                address,
+                username,
+                tried,
+            } => {
+                if tried.is_empty() {
+                    write!(
+                        f,
+                        "authentication failed on {} as {} : the SSH agent has no identity",
+                        address, username
+                    )
+                } else {
+                    write!(
+                        f,
+                        "authentication failed on {} as {} : no SSH agent identity accepted (tried {})",
+                        address,
+                        username,
+                        tried.join(", ")
+                    )
+                }
+            }
+            ConnectionError::JumpHostFailed {
+                jump_host, target, ..
+            } => write!(
+                f,
+                "unable to reach {} through jump host {}",
+                target, jump_host
+            ),
+            ConnectionError::SessionFailed { address, .. } => {
+                write!(f, "SSH session with {} failed", address)
+            }
+            ConnectionError::LocalCommandFailed(_) => {
+                write!(f, "unable to run command on localhost")
+            }
+            ConnectionError::BecomeFailed {
+                address,
+                user,
+                details,
+            } => write!(f, "unable to become {} on {} : {}", user, address, details),
+            ConnectionError::TransferFailed { address, path, .. } => {
+                write!(f, "unable to transfer {} on {}", path, address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConnectionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConnectionError::Unset(_) => None,
+            ConnectionError::HostUnreachable { source, .. } => Some(source),
+            ConnectionError::TimedOut { .. } => None,
+            ConnectionError::HandshakeFailed { source, .. } => Some(source),
+            ConnectionError::AuthenticationFailed { source, .. } => match source {
+                Some(source) => Some(source),
+                None => None,
+            },
+            ConnectionError::KeyUnreadable { source, .. } => Some(source),
+            ConnectionError::HostKeyMismatch { .. } => None,
+            ConnectionError::HostKeyUnknown { .. } => None,
+            ConnectionError::KnownHostsUnavailable { source, .. } => Some(source),
+            ConnectionError::SshConfigUnreadable { source, .. } => Some(source),
+            ConnectionError::SshConfigInvalid { .. } => None,
+            ConnectionError::AgentUnavailable { source, .. } => Some(source),
+            ConnectionError::AgentIdentitiesRefused { .. } => None,
+            ConnectionError::JumpHostFailed { source, .. } => Some(source),
+            ConnectionError::SessionFailed { source, .. } => Some(source),
+            ConnectionError::LocalCommandFailed(source) => Some(source),
+            ConnectionError::BecomeFailed { .. } => None,
+            ConnectionError::TransferFailed { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Error reported by the SSH implementation talking to the host (see SshBackend), whichever it is
+#[derive(Debug)]
+pub enum SshError {
+    #[cfg(feature = "ssh2")]
+    Libssh2(ssh2::Error),
+    #[cfg(feature = "russh")]
+    Russh(Box<russh::Error>), // Much larger than the other errors
+    /// Found by duxcore itself (unexpected answer from the server...)
+    Protocol(String),
+}
+
+impl fmt::Display for SshError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            #[cfg(feature = "ssh2")]
+            SshError::Libssh2(error) => write!(f, "{}", error),
+            #[cfg(feature = "russh")]
+            SshError::Russh(error) => write!(f, "{}", error),
+            SshError::Protocol(details) => write!(f, "{}", details),
+        }
+    }
+}
+
+// The library's error is the source itself : it is displayed as is, not as a cause of this one
+impl std::error::Error for SshError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "ssh2")]
+            SshError::Libssh2(error) => error.source(),
+            #[cfg(feature = "russh")]
+            SshError::Russh(error) => error.source(),
+            SshError::Protocol(_) => None,
+        }
+    }
+}
+
+#[cfg(feature = "ssh2")]
+impl From<ssh2::Error> for SshError {
+    fn from(error: ssh2::Error) -> Self {
+        SshError::Libssh2(error)
+    }
+}
+
+#[cfg(feature = "russh")]
+impl From<russh::Error> for SshError {
+    fn from(error: russh::Error) -> Self {
+        SshError::Russh(Box::new(error))
+    }
+}
@@ -1,22 +1,121 @@
 //! Dux specific errors
 
-// Definition of all possible errors for the whole crate
-// FIXME : relevant to subdivide this into multiple enums ?
+pub mod connection;
+pub mod module;
+pub mod parse;
+pub mod workflow;
 
+use crate::error::connection::ConnectionError;
+use crate::error::module::ModuleError;
+use crate::error::parse::{ParseError, ParsingError};
+use crate::error::workflow::WorkflowError;
+use std::error::Error as StdError;
+use std::fmt;
+
+/// Definition of all possible errors for the whole crate, one variant per subsystem.
+/// Display and source() are those of the subsystem error.
 #[derive(Debug)]
 pub enum Error {
-    FailureToFindGroupContent,
-    FailureToParseContent(String),
-    FailureToRunCommand(String),
-    FailureToEstablishConnection(String),
-    FailedInitialization(String),
-    FailedTcpBinding(String),
-    FailedTaskDryRun(String),
-    FailedDryRunEvaluation(String),
-    MissingInitialization(String),
-    GroupNotFound,
-    MissingGroupsList,
-    WorkFlowNotFollowed(String),
-    WrongInitialization(String),
-    AnyOtherError(String),
+    Connection(ConnectionError),
+    Parsing(ParsingError),
+    Module(ModuleError),
+    Workflow(WorkflowError),
+}
+
+impl Error {
+    /// The message followed by those of its sources, the deepest last (ex: why a session was lost)
+    pub fn with_sources(&self) -> String {
+        let mut message = self.to_string();
+        let mut source = StdError::source(self);
+        while let Some(error) = source {
+            message.push_str(&format!(" : {}", error));
+            source = error.source();
+        }
+        return message;
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Connection(error) => write!(f, "{}", error),
+            Error::Parsing(error) => write!(f, "{}", error),
+            Error::Module(error) => write!(f, "{}", error),
+            Error::Workflow(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Connection(error) => error.source(),
+            Error::Parsing(error) => error.source(),
+            Error::Module(error) => error.source(),
+            Error::Workflow(error) => error.source(),
+        }
+    }
+}
+
+impl From<ConnectionError> for Error {
+    fn from(error: ConnectionError) -> Self {
+        Error::Connection(error)
+    }
+}
+
+impl From<ParsingError> for Error {
+    fn from(error: ParsingError) -> Self {
+        Error::Parsing(error)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(error: ParseError) -> Self {
+        Error::Parsing(ParsingError::Invalid(Box::new(error)))
+    }
+}
+
+impl From<ModuleError> for Error {
+    fn from(error: ModuleError) -> Self {
+        Error::Module(error)
+    }
+}
+
+impl From<WorkflowError> for Error {
+    fn from(error: WorkflowError) -> Self {
+        Error::Workflow(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::tasklist::{TaskList, TaskListFileType};
+
+    #[test]
+    fn errors_can_be_matched_and_chained() {
+        let error = TaskList::from_file("/nonexistent/tasklist.yml", TaskListFileType::Unknown)
+            .unwrap_err();
+
+        assert!(matches!(
+            error,
+            Error::Parsing(ParsingError::UnreadableFile { .. })
+        ));
+        assert_eq!(
+            error.to_string(),
+            "unable to read /nonexistent/tasklist.yml"
+        );
+        assert_eq!(
+            error.with_sources(),
+            format!(
+                "unable to read /nonexistent/tasklist.yml : {}",
+                std::io::Error::from_raw_os_error(2) // ENOENT
+            )
+        );
+        let source = StdError::source(&error).unwrap();
+        assert_eq!(
+            source.downcast_ref::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
 }
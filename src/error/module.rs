@@ -0,0 +1,26 @@
+use std::fmt;
+
+/// Problems of a module evaluating or applying a step
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModuleError {
+    /// Arguments of the module are missing, wrong or inconsistent
+    InvalidArguments(String),
+    /// A tool required by the module is missing on the host (ex: apt-get, systemctl)
+    Unavailable(String),
+    /// The module couldn't assess the current state of the host
+    CheckFailed(String),
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModuleError::InvalidArguments(details) => {
+                write!(f, "invalid module arguments : {}", details)
+            }
+            ModuleError::Unavailable(details) => write!(f, "module unavailable : {}", details),
+            ModuleError::CheckFailed(details) => write!(f, "module check failed : {}", details),
+        }
+    }
+}
+
+impl std::error::Error for ModuleError {}
@@ -0,0 +1,360 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// Where and why a TaskList or a HostList couldn't be parsed. Every location information is optional as it depends on how far the parsing went.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,     // Starts at 1
+    pub column: Option<usize>,   // Starts at 1
+    pub snippet: Option<String>, // Content of the offending line
+    pub task_index: Option<usize>,
+    pub task_name: Option<String>,
+    pub step_index: Option<usize>,
+    pub step_name: Option<String>,
+}
+
+impl ParseError {
+    pub fn new(message: &str) -> ParseError {
+        ParseError {
+            message: message.to_string(),
+            file: None,
+            line: None,
+            column: None,
+            snippet: None,
+            task_index: None,
+            task_name: None,
+            step_index: None,
+            step_name: None,
+        }
+    }
+
+    pub fn from_yaml_error(error: &serde_yaml::Error, content: &str) -> ParseError {
+        let mut message = strip_location_suffix(&format!("{}", error));
+
+        // serde_yaml prefixes messages with the path of the faulty value (ex: ".[0].steps[2].apt: ...")
+        let mut task_index = None;
+        let mut step_index = None;
+        if let Some((path, path_free_message)) = message.clone().split_once(": ") {
+            if path.starts_with(".[") || path.starts_with('[') {
+                task_index = index_after(path, "[");
+                step_index = index_after(path, "steps[");
+                message = path_free_message.to_string();
+            }
+        }
+
+        let mut parse_error = ParseError::new(&message);
+        if let Some(location) = error.location() {
+            parse_error = parse_error.at(location.line(), location.column(), content);
+        }
+        parse_error.task_index = task_index;
+        parse_error.step_index = step_index;
+        parse_error
+    }
+
+    pub fn from_json_error(error: &serde_json::Error, content: &str) -> ParseError {
+        let message = strip_location_suffix(&format!("{}", error));
+        if error.line() == 0 {
+            ParseError::new(&message)
+        } else {
+            ParseError::new(&message).at(error.line(), error.column(), content)
+        }
+    }
+
+    pub fn from_toml_error(error: &toml::de::Error, content: &str) -> ParseError {
+        let parse_error = ParseError::new(error.message().trim());
+        match error.span() {
+            Some(span) => {
+                // toml only gives a byte range : turn its start into line and column
+                let before = &content[..span.start.min(content.len())];
+                let line = before.matches('\n').count() + 1;
+                let column = match before.rfind('\n') {
+                    Some(line_start) => before[line_start + 1..].chars().count() + 1,
+                    None => before.chars().count() + 1,
+                };
+                parse_error.at(line, column, content)
+            }
+            None => parse_error,
+        }
+    }
+
+    /// Sets line and column and keeps the matching line of the content as a snippet
+    pub fn at(mut self, line: usize, column: usize, content: &str) -> ParseError {
+        self.line = Some(line);
+        self.column = Some(column);
+        self.snippet = content
+            .lines()
+            .nth(line.saturating_sub(1))
+            .map(|line_content| line_content.to_string());
+        self
+    }
+
+    pub fn in_file(mut self, file: &str) -> ParseError {
+        self.file = Some(PathBuf::from(file));
+        self
+    }
+
+    pub fn in_task(mut self, task_index: usize, task_name: &Option<String>) -> ParseError {
+        self.task_index = Some(task_index);
+        if task_name.is_some() {
+            self.task_name = task_name.clone();
+        }
+        self
+    }
+
+    pub fn in_step(mut self, step_index: usize, step_name: &Option<String>) -> ParseError {
+        self.step_index = Some(step_index);
+        if step_name.is_some() {
+            self.step_name = step_name.clone();
+        }
+        self
+    }
+
+    /// Best effort to find a location when the content was syntactically valid but semantically wrong (ex: two modules in one step) :
+    /// the line defining the name of the step, after the line defining the name of its task.
+    pub fn locate_names(self, content: &str) -> ParseError {
+        if self.line.is_some() {
+            return self;
+        }
+
+        let mut lines = content.lines().enumerate();
+        if let Some(task_name) = &self.task_name {
+            match lines.find(|(_, line)| is_name_definition(line, task_name)) {
+                Some(task_line) => {
+                    if self.step_name.is_none() {
+                        return self.at_name(task_line, content);
+                    }
+                }
+                None => return self,
+            }
+        }
+        if let Some(step_name) = &self.step_name {
+            if let Some(step_line) = lines.find(|(_, line)| is_name_definition(line, step_name)) {
+                return self.at_name(step_line, content);
+            }
+        }
+
+        self
+    }
+
+    /// Locates the first occurrence of a text in the content
+    pub fn locate_text(self, content: &str, text: &str) -> ParseError {
+        match content
+            .lines()
+            .enumerate()
+            .find_map(|(index, line)| line.find(text).map(|column| (index, column)))
+        {
+            Some((index, column)) => self.at(index + 1, column + 1, content),
+            None => self,
+        }
+    }
+
+    fn at_name(self, (index, line): (usize, &str), content: &str) -> ParseError {
+        let column = line.find("name").unwrap_or(0) + 1;
+        self.at(index + 1, column, content)
+    }
+}
+
+/// Renders the error the way compilers do :
+/// ```text
+/// invalid type: string "yes", expected a boolean
+///  --> tasklist.yml:6:18
+///   |
+/// 6 |         upgrade: yes
+///   |                  ^
+///   = task 0 "Install", step 1 "Upgrade everything"
+/// ```
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        let file = self
+            .file
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .unwrap_or("<content>".into());
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, "\n --> {}:{}:{}", file, line, column)?;
+            if let Some(snippet) = &self.snippet {
+                let margin = " ".repeat(line.to_string().len());
+                write!(f, "\n{} |", margin)?;
+                write!(f, "\n{} | {}", line, snippet)?;
+                write!(
+                    f,
+                    "\n{} | {}^",
+                    margin,
+                    " ".repeat(column.saturating_sub(1))
+                )?;
+            }
+        } else if self.file.is_some() {
+            write!(f, "\n --> {}", file)?;
+        }
+
+        let mut context: Vec<String> = Vec::new();
+        if let Some(task_index) = self.task_index {
+            match &self.task_name {
+                Some(name) => context.push(format!("task {} {:?}", task_index, name)),
+                None => context.push(format!("task {}", task_index)),
+            }
+        }
+        if let Some(step_index) = self.step_index {
+            match &self.step_name {
+                Some(name) => context.push(format!("step {} {:?}", step_index, name)),
+                None => context.push(format!("step {}", step_index)),
+            }
+        }
+        if !context.is_empty() {
+            write!(f, "\n  = {}", context.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Problems turning a TaskList or a HostList into Rust objects
+#[derive(Debug)]
+pub enum ParsingError {
+    /// The content is not valid (syntax, types, module definitions...)
+    Invalid(Box<ParseError>),
+    /// The format wasn't given and none of the supported ones matches the content : how each of them failed
+    UnknownFormat(Vec<(String, ParseError)>),
+    /// The file couldn't be read
+    UnreadableFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+impl ParsingError {
+    /// Attaches the file to the location(s) of the error
+    pub fn in_file(self, file: &str) -> ParsingError {
+        match self {
+            ParsingError::Invalid(parse_error) => {
+                ParsingError::Invalid(Box::new((*parse_error).in_file(file)))
+            }
+            ParsingError::UnknownFormat(attempts) => ParsingError::UnknownFormat(
+                attempts
+                    .into_iter()
+                    .map(|(format, parse_error)| (format, parse_error.in_file(file)))
+                    .collect(),
+            ),
+            unreadable_file => unreadable_file,
+        }
+    }
+}
+
+impl fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParsingError::Invalid(parse_error) => write!(f, "{}", parse_error),
+            ParsingError::UnknownFormat(attempts) => {
+                write!(f, "unable to detect the format of the content")?;
+                for (format, parse_error) in attempts {
+                    write!(f, "\n{} : {}", format, parse_error)?;
+                }
+                Ok(())
+            }
+            ParsingError::UnreadableFile { path, .. } => {
+                write!(f, "unable to read {}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParsingError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParsingError::UnreadableFile { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// serde errors end with " at line X column Y", which is redundant with the structured location
+fn strip_location_suffix(message: &str) -> String {
+    match message.rfind(" at line ") {
+        Some(position) => message[..position].to_string(),
+        None => message.to_string(),
+    }
+}
+
+// "[0].steps[2]" with "steps[" -> Some(2)
+fn index_after(path: &str, prefix: &str) -> Option<usize> {
+    let start = path.find(prefix)? + prefix.len();
+    let end = path[start..].find(']')? + start;
+    path[start..end].parse::<usize>().ok()
+}
+
+// Matches "- name: x", "name: 'x'", "\"name\": \"x\"", "name = \"x\"" and so on
+fn is_name_definition(line: &str, name: &str) -> bool {
+    let line = line.trim().trim_start_matches("- ").trim_start_matches('{');
+    let line = line.trim_start();
+    let value = match line
+        .strip_prefix("name:")
+        .or_else(|| line.strip_prefix("\"name\":"))
+        .or_else(|| line.strip_prefix("name ="))
+    {
+        Some(value) => value,
+        None => return false,
+    };
+    let value = value.trim().trim_end_matches(',').trim();
+    value.trim_matches(|c| c == '"' || c == '\'') == name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yaml_errors_carry_location_and_step() {
+        let content = "---
+- name: Install
+  steps:
+    - name: Upgrade everything
+      apt:
+        upgrade: notabool
+";
+        let error = serde_yaml::from_str::<Vec<crate::task::taskblock::ParsingTaskBlock>>(content)
+            .unwrap_err();
+        let parse_error = ParseError::from_yaml_error(&error, content).in_file("tasklist.yml");
+
+        assert_eq!(parse_error.line, Some(6));
+        assert_eq!(parse_error.task_index, Some(0));
+        assert_eq!(parse_error.step_index, Some(0));
+        assert_eq!(
+            format!("{}", parse_error),
+            "invalid type: string \"notabool\", expected a boolean
+ --> tasklist.yml:6:18
+  |
+6 |         upgrade: notabool
+  |                  ^
+  = task 0, step 0"
+        );
+    }
+
+    #[test]
+    fn semantic_errors_are_located_by_names() {
+        let content = "---
+- name: First task
+  steps:
+    - name: Same name
+      ping:
+- name: Second task
+  steps:
+    - name: Same name
+      ping:
+      debug:
+        msg: two modules
+";
+        let parse_error = ParseError::new("Too much modules defined in this step")
+            .in_task(1, &Some("Second task".into()))
+            .in_step(0, &Some("Same name".into()))
+            .locate_names(content);
+
+        assert_eq!(parse_error.line, Some(8));
+        assert_eq!(parse_error.column, Some(7));
+    }
+}
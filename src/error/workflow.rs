@@ -0,0 +1,49 @@
+use std::fmt;
+
+/// Problems with the way a Job or a JobList is set up and run
+#[derive(Debug)]
+pub enum WorkflowError {
+    /// A Job (or JobList) lacks something to be run, or was given something meaningless
+    WrongInitialization(String),
+    /// Steps were run in an unexpected order (ex: apply without any change to apply)
+    NotFollowed(String),
+    /// A template (ex: delegate_to) couldn't be rendered
+    Template(tera::Error),
+    /// delegate_to points at a host whose connection information is unknown
+    UnknownDelegate(String),
+    /// A run_once step failed on the host that ran it for the others
+    RunOnceFailed(String),
+}
+
+impl fmt::Display for WorkflowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorkflowError::WrongInitialization(details) => {
+                write!(f, "wrong initialization : {}", details)
+            }
+            WorkflowError::NotFollowed(details) => write!(f, "workflow not followed : {}", details),
+            WorkflowError::Template(_) => write!(f, "unable to render template"),
+            WorkflowError::UnknownDelegate(address) => write!(
+                f,
+                "unable to delegate to {} : no connection information for this host in the JobList",
+                address
+            ),
+            WorkflowError::RunOnceFailed(details) => {
+                write!(
+                    f,
+                    "run_once step failed on the host running it : {}",
+                    details
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for WorkflowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WorkflowError::Template(source) => Some(source),
+            _ => None,
+        }
+    }
+}
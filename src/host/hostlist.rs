@@ -1,6 +1,7 @@
+use crate::error::parse::ParsingError;
 use crate::error::Error;
 use crate::host::hosts::Host;
-use crate::host::parser::hostlist_parser;
+use crate::host::parser::{hostlist_parser, toml_hostlist_parser, yaml_hostlist_parser};
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize, Clone)]
@@ -9,20 +10,40 @@ pub struct HostList {
 }
 
 impl HostList {
+    /// The format (YAML or TOML) is detected from the content
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(raw_content: &str) -> Result<HostList, Error> {
         hostlist_parser(raw_content)
     }
 
+    /// The format is deduced from the file extension ('.yml', '.yaml' or '.toml'), or detected from the content otherwise
     pub fn from_file(file_path: &str) -> Result<HostList, Error> {
-        match std::fs::read_to_string(file_path) {
-            Ok(file_content) => {
-                return HostList::from_str(&file_content);
-            }
+        let file_content = match std::fs::read_to_string(file_path) {
+            Ok(file_content) => file_content,
             Err(error) => {
-                return Err(Error::FailedInitialization(format!(
-                    "{} : {}",
-                    file_path, error
-                )));
+                return Err(Error::Parsing(ParsingError::UnreadableFile {
+                    path: file_path.into(),
+                    source: error,
+                }));
+            }
+        };
+
+        let extension = std::path::Path::new(file_path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        let parsing_result = match extension.as_deref() {
+            Some("yml") | Some("yaml") => yaml_hostlist_parser(&file_content),
+            Some("toml") => toml_hostlist_parser(&file_content),
+            _ => HostList::from_str(&file_content),
+        };
+
+        match parsing_result {
+            Err(Error::Parsing(parsing_error)) => {
+                return Err(Error::Parsing(parsing_error.in_file(file_path)));
+            }
+            _ => {
+                return parsing_result;
             }
         }
     }
@@ -53,7 +74,7 @@ pub fn hostlist_get_from_interactive_mode() -> String {
 }
 
 // If the host is already in the list, the index is returned. Otherwise, None is returned.
-pub fn find_host_in_list(hosts_list: &Vec<Host>, host_name: &String) -> Option<usize> {
+pub fn find_host_in_list(hosts_list: &[Host], host_name: &str) -> Option<usize> {
     for (index, host) in hosts_list.iter().enumerate() {
         if host.address.eq(host_name) {
             return Some(index);
@@ -75,7 +96,7 @@ mod tests {
             Host::from_string("10.20.30.53".into()),
         ];
 
-        assert!(find_host_in_list(&hosts_list, &"10.20.30.51".to_string()).is_some());
-        assert!(find_host_in_list(&hosts_list, &"192.168.10.25".to_string()).is_none());
+        assert!(find_host_in_list(&hosts_list, "10.20.30.51").is_some());
+        assert!(find_host_in_list(&hosts_list, "192.168.10.25").is_none());
     }
 }
@@ -1,12 +1,17 @@
+use crate::connection::escalation::BecomeMethod;
+use crate::connection::host_connection::HostConnectionInfo;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-// TODO : add a connection mode field
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Host {
     pub address: String,
     pub vars: Option<HashMap<String, String>>,
     pub groups: Option<Vec<String>>,
+    /// Connection parameters defined in the HostList, for this host or its groups
+    #[serde(default)]
+    pub connection: Option<ConnectionParameters>,
 }
 
 impl Host {
@@ -15,6 +20,7 @@ impl Host {
             address: String::new(),
             vars: None,
             groups: None,
+            connection: None,
         }
     }
 
@@ -23,18 +29,19 @@ impl Host {
             address,
             vars: None,
             groups: None,
+            connection: None,
         }
     }
 
-    pub fn add_to_group(&mut self, groupname: &String) {
+    pub fn add_to_group(&mut self, groupname: &str) {
         match &self.groups {
             Some(group_list) => {
                 let mut new_group_list = group_list.clone();
-                new_group_list.push(groupname.clone());
+                new_group_list.push(groupname.to_string());
                 self.groups = Some(new_group_list);
             }
             None => {
-                self.groups = Some(vec![groupname.clone()]);
+                self.groups = Some(vec![groupname.to_string()]);
             }
         }
     }
@@ -52,6 +59,36 @@ impl Host {
         }
     }
 
+    /// Given parameters override the ones already known for this host
+    pub fn add_connection_parameters(&mut self, parameters: &ConnectionParameters) {
+        if parameters.is_empty() {
+            return;
+        }
+        match &self.connection {
+            Some(old_parameters) => {
+                self.connection = Some(old_parameters.overridden_by(parameters));
+            }
+            None => {
+                self.connection = Some(parameters.clone());
+            }
+        }
+    }
+
+    /// Connection info to use for this host : the given one, refined by this host's connection parameters
+    pub fn connection_info(&self, host_connection_info: HostConnectionInfo) -> HostConnectionInfo {
+        match &self.connection {
+            Some(parameters) => host_connection_info.with_parameters(parameters),
+            None => host_connection_info,
+        }
+    }
+
+    /// Escalation method defined in the HostList for this host or its groups
+    pub fn become_method(&self) -> Option<BecomeMethod> {
+        self.connection
+            .as_ref()
+            .and_then(|parameters| parameters.become_method)
+    }
+
     pub fn add_var(&mut self, key: &str, value: &str) {
         match &self.vars {
             Some(oldvars) => {
@@ -68,9 +105,47 @@ impl Host {
     }
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
 pub struct Group {
     pub name: String,
     pub vars: Option<HashMap<String, String>>,
     pub hosts: Option<Vec<String>>,
+    /// Default connection parameters for the hosts of this group
+    #[serde(flatten)]
+    pub connection: ConnectionParameters,
+}
+
+/// How to connect to a host, as written in a HostList (at HostList, group or host level)
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize, JsonSchema)]
+pub struct ConnectionParameters {
+    /// SSH port (22 by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Path to a private key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// How steps run with_sudo or run_as get their privileges (sudo by default)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub become_method: Option<BecomeMethod>,
+}
+
+impl ConnectionParameters {
+    pub fn is_empty(&self) -> bool {
+        self.port.is_none()
+            && self.user.is_none()
+            && self.key.is_none()
+            && self.become_method.is_none()
+    }
+
+    /// Parameters defined in 'other' take precedence
+    pub fn overridden_by(&self, other: &ConnectionParameters) -> ConnectionParameters {
+        ConnectionParameters {
+            port: other.port.or(self.port),
+            user: other.user.clone().or(self.user.clone()),
+            key: other.key.clone().or(self.key.clone()),
+            become_method: other.become_method.or(self.become_method),
+        }
+    }
 }
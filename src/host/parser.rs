@@ -1,23 +1,55 @@
+use crate::error::parse::{ParseError, ParsingError};
 use crate::error::Error;
 use crate::host::hostlist::{find_host_in_list, HostList};
-use crate::host::hosts::{Group, Host};
+use crate::host::hosts::{ConnectionParameters, Group, Host};
+use schemars::JsonSchema;
 use serde::Deserialize;
 use std::collections::HashMap;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
 pub struct HostListVarsUnparsed {
     pub vars: Option<HashMap<String, String>>,
-    pub hosts: Option<Vec<String>>,
+    pub hosts: Option<Vec<HostEntry>>,
     pub groups: Option<Vec<Group>>,
+    /// Default connection parameters for all hosts
+    #[serde(flatten)]
+    pub connection: ConnectionParameters,
+}
+
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum HostEntry {
+    /// "address" or "address[key1=value1,key2=value2]" to add host variables
+    Plain(String),
+    Detailed(HostDefinition),
+}
+
+/// A host written as a map : its address, variables and connection parameters
+#[derive(Debug, Deserialize, Clone, JsonSchema)]
+pub struct HostDefinition {
+    pub host: String,
+    pub vars: Option<HashMap<String, String>>,
+    #[serde(flatten)]
+    pub connection: ConnectionParameters,
 }
 
 impl HostListVarsUnparsed {
-    pub fn parse_host_vars(&self) -> HostListFile {
+    pub fn parse_host_vars(&self) -> Result<HostListFile, Error> {
         match &self.hosts {
             Some(hosts_list) => {
                 let mut parsed_hosts: Vec<Host> = Vec::new();
 
-                for host_string in hosts_list {
+                for host_entry in hosts_list {
+                    let host_string = match host_entry {
+                        HostEntry::Plain(host_string) => host_string,
+                        HostEntry::Detailed(host_definition) => {
+                            let mut host = Host::from_string(host_definition.host.clone());
+                            host.vars = host_definition.vars.clone();
+                            host.add_connection_parameters(&host_definition.connection);
+                            parsed_hosts.push(host);
+                            continue;
+                        }
+                    };
                     let mut line = host_string.split(['[', ']']);
                     let hostname = line.next().unwrap().trim();
 
@@ -25,36 +57,43 @@ impl HostListVarsUnparsed {
                         Some(vars_content) => {
                             let mut vars_list: HashMap<String, String> = HashMap::new();
                             for vardef in vars_content.split(',') {
-                                let mut vardef_parsed = vardef.split('=');
-                                let key = vardef_parsed.next().unwrap().trim();
-                                let value = vardef_parsed.next().unwrap().trim();
-                                vars_list.insert(key.to_string(), value.to_string());
+                                match vardef.split_once('=') {
+                                    Some((key, value)) => {
+                                        vars_list.insert(
+                                            key.trim().to_string(),
+                                            value.trim().to_string(),
+                                        );
+                                    }
+                                    None => {
+                                        return Err(Error::from(ParseError::new(&format!(
+                                            "Host variable '{}' of {} is not defined as key=value",
+                                            vardef.trim(),
+                                            hostname
+                                        ))));
+                                    }
+                                }
                             }
-                            parsed_hosts.push(Host {
-                                address: hostname.to_string(),
-                                vars: Some(vars_list),
-                                groups: None,
-                            })
+                            let mut host = Host::from_string(hostname.to_string());
+                            host.vars = Some(vars_list);
+                            parsed_hosts.push(host)
                         }
-                        None => parsed_hosts.push(Host {
-                            address: hostname.to_string(),
-                            vars: None,
-                            groups: None,
-                        }),
+                        None => parsed_hosts.push(Host::from_string(hostname.to_string())),
                     }
                 }
 
-                HostListFile {
+                Ok(HostListFile {
                     hosts: Some(parsed_hosts),
                     groups: self.groups.clone(),
                     vars: self.vars.clone(),
-                }
+                    connection: self.connection.clone(),
+                })
             }
-            None => HostListFile {
+            None => Ok(HostListFile {
                 hosts: None,
                 groups: self.groups.clone(),
                 vars: self.vars.clone(),
-            },
+                connection: self.connection.clone(),
+            }),
         }
     }
 }
@@ -64,6 +103,8 @@ pub struct HostListFile {
     pub vars: Option<HashMap<String, String>>,
     pub hosts: Option<Vec<Host>>,
     pub groups: Option<Vec<Group>>,
+    #[serde(flatten)]
+    pub connection: ConnectionParameters,
 }
 
 impl HostListFile {
@@ -72,6 +113,7 @@ impl HostListFile {
             vars: Some(HashMap::new()),
             hosts: Some(Vec::new()),
             groups: Some(Vec::new()),
+            connection: ConnectionParameters::default(),
         }
     }
 
@@ -81,6 +123,7 @@ impl HostListFile {
                 vars: None,
                 hosts: None,
                 groups: None,
+                connection: ConnectionParameters::default(),
             }
         } else {
             match vars {
@@ -88,11 +131,13 @@ impl HostListFile {
                     vars: Some(variables),
                     hosts: Some(hosts),
                     groups: None,
+                    connection: ConnectionParameters::default(),
                 },
                 None => HostListFile {
                     vars: None,
                     hosts: Some(hosts),
                     groups: None,
+                    connection: ConnectionParameters::default(),
                 },
             }
         }
@@ -109,24 +154,30 @@ impl HostListFile {
                     match &group.hosts {
                         Some(host_list) => {
                             for host_address in host_list {
-                                match find_host_in_list(&final_hostlist, &host_address) {
+                                match find_host_in_list(&final_hostlist, host_address) {
                                     Some(index) => {
                                         final_hostlist[index].add_to_group(&group.name);
                                         // Only add group level variables because the host is already in the list, meaning it already has HostList level variables
+                                        if let Some(vars_content) = group.vars.as_ref() {
+                                            final_hostlist[index].add_vars(vars_content);
+                                        }
                                         final_hostlist[index]
-                                            .add_vars(&group.vars.as_ref().unwrap());
+                                            .add_connection_parameters(&group.connection);
                                     }
                                     None => {
                                         let mut temp_host = Host::from_string(host_address.clone());
                                         temp_host.add_to_group(&group.name);
                                         // First, add HostList level variables
-                                        if let Some(vars_content) = &self.vars.as_ref() {
+                                        if let Some(vars_content) = self.vars.as_ref() {
                                             temp_host.add_vars(vars_content);
                                         }
                                         // Then add group level variables (surcharge)
-                                        if let Some(vars_content) = &group.vars.as_ref() {
+                                        if let Some(vars_content) = group.vars.as_ref() {
                                             temp_host.add_vars(vars_content);
                                         }
+                                        // Same for connection parameters
+                                        temp_host.add_connection_parameters(&self.connection);
+                                        temp_host.add_connection_parameters(&group.connection);
 
                                         final_hostlist.push(temp_host);
                                     }
@@ -146,18 +197,28 @@ impl HostListFile {
                     match find_host_in_list(&final_hostlist, &host.address) {
                         Some(index) => {
                             // Host is already part of a group, only host vars need to be added
-                            final_hostlist[index].add_vars(host.vars.as_ref().unwrap());
+                            if let Some(vars_content) = host.vars.as_ref() {
+                                final_hostlist[index].add_vars(vars_content);
+                            }
+                            if let Some(parameters) = &host.connection {
+                                final_hostlist[index].add_connection_parameters(parameters);
+                            }
                         }
                         None => {
                             let mut temp_host = Host::from_string(host.address.clone());
                             // First, add HostList level variables
-                            if let Some(vars_content) = &self.vars.as_ref() {
+                            if let Some(vars_content) = self.vars.as_ref() {
                                 temp_host.add_vars(vars_content);
                             }
                             // Then add host level variables (surcharge)
-                            if let Some(vars_content) = &host.vars.as_ref() {
+                            if let Some(vars_content) = host.vars.as_ref() {
                                 temp_host.add_vars(vars_content);
                             }
+                            // Same for connection parameters
+                            temp_host.add_connection_parameters(&self.connection);
+                            if let Some(parameters) = &host.connection {
+                                temp_host.add_connection_parameters(parameters);
+                            }
 
                             final_hostlist.push(temp_host);
                         }
@@ -177,25 +238,93 @@ impl HostListFile {
     }
 }
 
-// TODO : So far, we assume the hostlist file is in YAML format. More formats will come later.
+/// Parses a hostlist whose format is not known in advance : YAML first, then TOML
 pub fn hostlist_parser(hostlistfilecontent: &str) -> Result<HostList, Error> {
-    // First we parse the content as YAML, host vars not parsed yet (unproper YAML syntax)
-    match serde_yaml::from_str::<HostListVarsUnparsed>(&hostlistfilecontent) {
-        Ok(yaml_parsed_result) => {
-            // Second we parse the host vars
-            let host_vars_parsed_result = yaml_parsed_result.parse_host_vars();
-            // Finally, we generate a HostList out of the HostListFile
+    match serde_yaml::from_str::<HostListVarsUnparsed>(hostlistfilecontent) {
+        Ok(yaml_parsed_result) => hostlist_from_unparsed(yaml_parsed_result, hostlistfilecontent),
+        Err(yaml_try_error) => match toml::from_str::<HostListVarsUnparsed>(hostlistfilecontent) {
+            Ok(toml_parsed_result) => {
+                hostlist_from_unparsed(toml_parsed_result, hostlistfilecontent)
+            }
+            Err(toml_try_error) => Err(Error::Parsing(ParsingError::UnknownFormat(vec![
+                (
+                    "YAML".to_string(),
+                    ParseError::from_yaml_error(&yaml_try_error, hostlistfilecontent),
+                ),
+                (
+                    "TOML".to_string(),
+                    ParseError::from_toml_error(&toml_try_error, hostlistfilecontent),
+                ),
+            ]))),
+        },
+    }
+}
+
+pub fn yaml_hostlist_parser(hostlistfilecontent: &str) -> Result<HostList, Error> {
+    match serde_yaml::from_str::<HostListVarsUnparsed>(hostlistfilecontent) {
+        Ok(yaml_parsed_result) => hostlist_from_unparsed(yaml_parsed_result, hostlistfilecontent),
+        Err(error) => Err(Error::from(ParseError::from_yaml_error(
+            &error,
+            hostlistfilecontent,
+        ))),
+    }
+}
+
+pub fn toml_hostlist_parser(hostlistfilecontent: &str) -> Result<HostList, Error> {
+    match toml::from_str::<HostListVarsUnparsed>(hostlistfilecontent) {
+        Ok(toml_parsed_result) => hostlist_from_unparsed(toml_parsed_result, hostlistfilecontent),
+        Err(error) => Err(Error::from(ParseError::from_toml_error(
+            &error,
+            hostlistfilecontent,
+        ))),
+    }
+}
+
+// The content is already deserialized, host vars not parsed yet (they are written inside the host string)
+fn hostlist_from_unparsed(
+    hostlist_unparsed: HostListVarsUnparsed,
+    hostlistfilecontent: &str,
+) -> Result<HostList, Error> {
+    match hostlist_unparsed.parse_host_vars() {
+        // Finally, we generate a HostList out of the HostListFile
+        Ok(host_vars_parsed_result) => {
             return Ok(host_vars_parsed_result.generate_hostlist());
         }
+        Err(Error::Parsing(ParsingError::Invalid(parse_error))) => {
+            return Err(Error::from(locate_host_definition(
+                *parse_error,
+                &hostlist_unparsed,
+                hostlistfilecontent,
+            )));
+        }
         Err(error) => {
-            return Err(Error::FailedInitialization(format!("{}", error)));
+            return Err(error);
+        }
+    }
+}
+
+// Host definitions are plain strings for serde : the faulty one is searched back in the content
+fn locate_host_definition(
+    parse_error: ParseError,
+    hostlist_unparsed: &HostListVarsUnparsed,
+    hostlistfilecontent: &str,
+) -> ParseError {
+    for host_entry in hostlist_unparsed.hosts.iter().flatten() {
+        if let HostEntry::Plain(host_string) = host_entry {
+            if let Some(vars_content) = host_string.split(['[', ']']).nth(1) {
+                if vars_content.split(',').any(|vardef| !vardef.contains('=')) {
+                    return parse_error.locate_text(hostlistfilecontent, host_string);
+                }
+            }
         }
-    };
+    }
+    parse_error
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::connection::escalation::BecomeMethod;
 
     #[test]
     fn empty_hostlist_parsing() {
@@ -219,13 +348,105 @@ hosts:
         let vec_hosts = hostlist.hosts.unwrap();
         assert_eq!(vec_hosts.len(), 3);
 
-        let mut address_list: Vec<String> = Vec::new();
-        address_list.push(vec_hosts[0].address.clone());
-        address_list.push(vec_hosts[1].address.clone());
-        address_list.push(vec_hosts[2].address.clone());
+        let address_list: Vec<String> = vec![
+            vec_hosts[0].address.clone(),
+            vec_hosts[1].address.clone(),
+            vec_hosts[2].address.clone(),
+        ];
         assert!(address_list.binary_search(&"10.20.30.51".into()).is_ok());
         assert!(address_list.binary_search(&"10.20.30.52".into()).is_ok());
         assert!(address_list.binary_search(&"10.20.30.53".into()).is_ok());
         assert!(address_list.binary_search(&"192.168.10.25".into()).is_err());
     }
+
+    #[test]
+    fn malformed_host_vars_are_located() {
+        let content = "---
+hosts:
+- 10.20.30.51
+- 10.20.30.52[port=2222,forgotten]
+";
+        match hostlist_parser(content) {
+            Err(Error::Parsing(ParsingError::Invalid(parse_error))) => {
+                assert_eq!(parse_error.line, Some(4));
+                assert_eq!(parse_error.column, Some(3));
+                assert!(parse_error.message.contains("forgotten"));
+            }
+            other => panic!("unexpected result : {:?}", other),
+        }
+    }
+
+    #[test]
+    fn connection_parameters_are_inherited_and_overridden() {
+        let hostlist = hostlist_parser(
+            "---
+user: admin
+key: /home/admin/.ssh/id_ed25519
+groups:
+  - name: db
+    port: 2222
+    become_method: doas
+    hosts:
+      - db1
+      - db2
+hosts:
+  - web1
+  - host: db2
+    port: 2200
+    user: deploy
+    vars:
+      role: replica
+",
+        )
+        .unwrap();
+        let hosts = hostlist.hosts.unwrap();
+
+        let web1 = &hosts[find_host_in_list(&hosts, "web1").unwrap()];
+        let web1_connection = web1.connection.as_ref().unwrap();
+        assert_eq!(web1_connection.port, None);
+        assert_eq!(web1_connection.user, Some("admin".into()));
+        assert_eq!(web1.become_method(), None);
+
+        let db1 = &hosts[find_host_in_list(&hosts, "db1").unwrap()];
+        assert_eq!(db1.connection.as_ref().unwrap().port, Some(2222));
+        assert_eq!(db1.become_method(), Some(BecomeMethod::Doas));
+
+        let db2 = &hosts[find_host_in_list(&hosts, "db2").unwrap()];
+        let db2_connection = db2.connection.as_ref().unwrap();
+        assert_eq!(db2_connection.port, Some(2200));
+        assert_eq!(db2_connection.user, Some("deploy".into()));
+        assert_eq!(
+            db2_connection.key,
+            Some("/home/admin/.ssh/id_ed25519".into())
+        );
+        assert_eq!(
+            db2.vars.as_ref().unwrap().get("role"),
+            Some(&"replica".to_string())
+        );
+    }
+
+    #[test]
+    fn toml_hostlist_parsing() {
+        let hostlist = hostlist_parser(
+            r#"
+hosts = ["10.20.30.51", "10.20.30.52[port=2222,user=admin]"]
+
+[vars]
+env = "staging"
+
+[[groups]]
+name = "web"
+hosts = ["10.20.30.53"]
+"#,
+        )
+        .unwrap();
+
+        let hosts = hostlist.hosts.unwrap();
+        assert_eq!(hosts.len(), 3);
+        let second_host = find_host_in_list(&hosts, "10.20.30.52").unwrap();
+        assert_eq!(
+            hosts[second_host].vars.as_ref().unwrap().get("user"),
+            Some(&"admin".to_string())
+        );
+    }
 }
@@ -1,10 +1,16 @@
+use crate::connection::escalation::BecomeMethod;
 use crate::connection::host_connection::HostConnectionInfo;
 use crate::connection::hosthandler::HostHandler;
+use crate::connection::pool::{ConnectionPool, PooledHostHandler};
+use crate::connection::specification::ConnectionReport;
+use crate::error::workflow::WorkflowError;
 use crate::error::Error;
 use crate::host::hosts::Host;
 use crate::output::job_output::JobOutput;
+use crate::output::streaming::OutputSink;
 use crate::task::tasklist::TaskList;
 use crate::task::tasklist::TaskListFileType;
+use crate::workflow::coordination::JobListCoordinator;
 use crate::workflow::hostworkflow::HostWorkFlow;
 use crate::workflow::hostworkflow::HostWorkFlowStatus;
 use chrono::Utc;
@@ -25,6 +31,17 @@ pub struct Job {
     pub timestamp_end: Option<String>,
     pub hostworkflow: Option<HostWorkFlow>,
     pub final_status: HostWorkFlowStatus,
+    pub connection_report: Option<ConnectionReport>,
+    #[serde(skip)]
+    pub output_sink: Option<OutputSink>,
+    #[serde(skip)]
+    pub connection_pool: Option<ConnectionPool>,
+    /// How steps run with_sudo or run_as get their privileges (sudo by default)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub become_method: Option<BecomeMethod>,
+    /// Password given to sudo for steps run with_sudo or run_as. Never serialized.
+    #[serde(skip)]
+    pub become_password: Option<String>,
 }
 
 impl Job {
@@ -39,22 +56,28 @@ impl Job {
             timestamp_end: None,
             hostworkflow: None,
             final_status: HostWorkFlowStatus::NotRunYet,
+            connection_report: None,
+            output_sink: None,
+            connection_pool: None,
+            become_method: None,
+            become_password: None,
         }
     }
 
     pub fn from_host(host: Host) -> Job {
         let mut job = Job::new();
-        job.set_address(&host.address);
-
-        let temp_tera_context_value = match &host.vars {
-            Some(vars_list) => Some(
-                tera::Context::from_serialize(vars_list)
-                    .unwrap()
-                    .into_json(),
-            ),
-            None => None,
-        };
+
+        let temp_tera_context_value = host.vars.as_ref().map(|vars_list| {
+            tera::Context::from_serialize(vars_list)
+                .unwrap()
+                .into_json()
+        });
         job.set_vars(temp_tera_context_value);
+        // With a user and a key in the HostList, the Job can connect on its own
+        job.host_connection_info = host.connection_info(HostConnectionInfo::Unset);
+        job.become_method = host.become_method();
+        // Host vars and groups are kept as well : they are shared with other Jobs of a JobList through 'hostvars' and 'groups'
+        job.host = host;
         job
     }
 
@@ -87,7 +110,9 @@ impl Job {
                     Ok(self)
                 }
                 Err(e) => {
-                    return Err(Error::FailedInitialization(format!("{}", e)));
+                    return Err(Error::Workflow(WorkflowError::WrongInitialization(
+                        format!("unable to build a correlation id : {}", e),
+                    )));
                 }
             }
         } else {
@@ -96,21 +121,46 @@ impl Job {
         }
     }
 
-    /// How do we connect to the target host ?
+    /// How do we connect to the target host ? Connection parameters of the host (from a HostList) take precedence.
     pub fn set_connection(
         &mut self,
         host_connection_info: HostConnectionInfo,
     ) -> Result<&mut Self, Error> {
         if let HostConnectionInfo::Unset = host_connection_info {
-            Err(Error::WrongInitialization(format!(
-                "No point in initializing connection info to HostConnectionInfo::Unset"
+            Err(Error::Workflow(WorkflowError::WrongInitialization(
+                "No point in initializing connection info to HostConnectionInfo::Unset".to_string(),
             )))
         } else {
-            self.host_connection_info = host_connection_info;
+            self.host_connection_info = self.host.connection_info(host_connection_info);
             Ok(self)
         }
     }
 
+    /// While steps are applied, the output of their commands is sent line by line to this sink (tagged with host and step). The final result of each step is unchanged.
+    pub fn set_output_sink(&mut self, output_sink: OutputSink) -> &mut Self {
+        self.output_sink = Some(output_sink);
+        self
+    }
+
+    /// The connection is borrowed from this pool, and given back to it once the run is over
+    pub fn set_connection_pool(&mut self, connection_pool: ConnectionPool) -> &mut Self {
+        self.connection_pool = Some(connection_pool);
+        self
+    }
+
+    /// How steps run with_sudo or run_as get their privileges on this host (sudo by default). It replaces the HostList's become_method, a step's become_method overrides it.
+    pub fn set_become_method(&mut self, become_method: BecomeMethod) -> &mut Self {
+        self.become_method = Some(become_method);
+        self
+    }
+
+    /// Password asked by sudo on the host, for steps run with_sudo or run_as. It is given to sudo on stdin, never in the command line.
+    /// It is not serialized with the Job : set it again on a deserialized Job.
+    pub fn set_become_password(&mut self, password: &str) -> &mut Self {
+        self.become_password = Some(password.to_string());
+        self
+    }
+
     /// Define the task list from a TaskList
     pub fn set_tasklist(
         &mut self,
@@ -174,51 +224,60 @@ impl Job {
 
     /// "DRY_RUN" this job -> evaluate the difference between the expected state and the actual state of the given host
     pub fn dry_run(&mut self) {
-        // Build a HostHandler
-        let mut host_handler =
-            HostHandler::from(self.host.address.clone(), self.host_connection_info.clone())
-                .unwrap();
-        if let Err(error) = host_handler.init() {
-            self.final_status = HostWorkFlowStatus::ConnectionInitFailed(
-                format!("{:?}", error)
-            );
-            return;
-        }
+        // A Job on its own is handled like a JobList of one Job
+        let coordinator = JobListCoordinator::from_jobs(std::slice::from_ref(self));
+        self.dry_run_coordinated(&coordinator);
+    }
 
-        // Build a context
-        let mut temp_tera_context = match &self.vars {
-            Some(context_value) => tera::Context::from_value(context_value.clone()).unwrap(),
-            None => tera::Context::new(),
+    /// "APPLY" this job -> evaluate what needs to be done to reach the expected state, then do it
+    pub fn apply(&mut self) {
+        // A Job on its own is handled like a JobList of one Job
+        let coordinator = JobListCoordinator::from_jobs(std::slice::from_ref(self));
+        self.apply_coordinated(&coordinator);
+    }
+
+    pub(crate) fn dry_run_coordinated(&mut self, coordinator: &JobListCoordinator) {
+        let (mut host_handler, mut temp_tera_context) = match self.prepare_run() {
+            Some(prepared) => prepared,
+            None => return,
         };
 
-        self.timestamp_start = Some(format!("{}", Utc::now().format("%+").to_string()));
+        self.timestamp_start = Some(Utc::now().format("%+").to_string());
 
         match &mut self.hostworkflow {
             Some(host_work_flow) => {
-                match host_work_flow.dry_run(&mut host_handler, &mut temp_tera_context) {
+                match host_work_flow.dry_run(&mut host_handler, &mut temp_tera_context, coordinator) {
                     Ok(()) => {
                         self.final_status = host_work_flow.final_status.clone();
                     }
-                    Err(_error) => {
-                        self.final_status = HostWorkFlowStatus::DryRunFailed;
+                    Err(error) => {
+                        self.final_status = HostWorkFlowStatus::DryRunFailed(error.to_string());
                     }
                 }
             }
             None => {
-                let mut host_work_flow = HostWorkFlow::from(&self.tasklist.as_mut().unwrap());
-                match host_work_flow.dry_run(&mut host_handler, &mut temp_tera_context) {
+                // prepare_run() made sure a tasklist is there
+                let mut host_work_flow = match &self.tasklist {
+                    Some(task_list) => HostWorkFlow::from(task_list),
+                    None => return,
+                };
+                match host_work_flow.dry_run(&mut host_handler, &mut temp_tera_context, coordinator) {
                     Ok(()) => {
                         self.final_status = host_work_flow.final_status.clone();
                         self.hostworkflow = Some(host_work_flow);
                     }
-                    Err(_error) => {
-                        self.final_status = HostWorkFlowStatus::ApplyFailed;
+                    Err(error) => {
+                        self.final_status = HostWorkFlowStatus::DryRunFailed(error.to_string());
                     }
                 }
             }
         }
 
-        self.timestamp_end = Some(format!("{}", Utc::now().format("%+").to_string()));
+        self.timestamp_end = Some(Utc::now().format("%+").to_string());
+        // Sessions may have been lost and established again during the run
+        self.connection_report = Some(host_handler.connection_report());
+        // Every host's variables would otherwise be kept in each Job
+        coordinator.remove_hostvars_from_context(&mut temp_tera_context);
         match temp_tera_context.clone().into_json() {
             serde_json::Value::Null => {
                 self.vars = None;
@@ -227,30 +286,17 @@ impl Job {
         }
     }
 
-    /// "APPLY" this job -> evaluate what needs to be done to reach the expected state, then do it
-    pub fn apply(&mut self) {
-        // Build a HostHandler
-        let mut host_handler =
-            HostHandler::from(self.host.address.clone(), self.host_connection_info.clone())
-                .unwrap();
-        if let Err(error) = host_handler.init() {
-            self.final_status = HostWorkFlowStatus::ConnectionInitFailed(
-                format!("{:?}", error)
-            );
-            return;
-        }
-
-        // Build a context
-        let mut temp_tera_context = match &self.vars {
-            Some(context_value) => tera::Context::from_value(context_value.clone()).unwrap(),
-            None => tera::Context::new(),
+    pub(crate) fn apply_coordinated(&mut self, coordinator: &JobListCoordinator) {
+        let (mut host_handler, mut temp_tera_context) = match self.prepare_run() {
+            Some(prepared) => prepared,
+            None => return,
         };
 
-        self.timestamp_start = Some(format!("{}", Utc::now().format("%+").to_string()));
+        self.timestamp_start = Some(Utc::now().format("%+").to_string());
 
         match &mut self.hostworkflow {
             Some(host_work_flow) => {
-                match host_work_flow.apply(&mut host_handler, &mut temp_tera_context) {
+                match host_work_flow.apply(&mut host_handler, &mut temp_tera_context, coordinator) {
                     Ok(()) => {
                         self.final_status = host_work_flow.final_status.clone();
                     }
@@ -260,8 +306,12 @@ impl Job {
                 }
             }
             None => {
-                let mut host_work_flow = HostWorkFlow::from(&self.tasklist.as_mut().unwrap());
-                match host_work_flow.apply(&mut host_handler, &mut temp_tera_context) {
+                // prepare_run() made sure a tasklist is there
+                let mut host_work_flow = match &self.tasklist {
+                    Some(task_list) => HostWorkFlow::from(task_list),
+                    None => return,
+                };
+                match host_work_flow.apply(&mut host_handler, &mut temp_tera_context, coordinator) {
                     Ok(()) => {
                         self.final_status = host_work_flow.final_status.clone();
                         self.hostworkflow = Some(host_work_flow);
@@ -273,8 +323,12 @@ impl Job {
             }
         }
 
-        self.timestamp_end = Some(format!("{}", Utc::now().format("%+").to_string()));
+        self.timestamp_end = Some(Utc::now().format("%+").to_string());
+        // Sessions may have been lost and established again during the run
+        self.connection_report = Some(host_handler.connection_report());
 
+        // Every host's variables would otherwise be kept in each Job
+        coordinator.remove_hostvars_from_context(&mut temp_tera_context);
         match temp_tera_context.into_json() {
             serde_json::Value::Null => {
                 self.vars = None;
@@ -283,6 +337,55 @@ impl Job {
         }
     }
 
+    // Everything a run needs before reaching the host workflow. On failure, final_status tells why.
+    fn prepare_run(&mut self) -> Option<(PooledHostHandler, tera::Context)> {
+        if self.hostworkflow.is_none() && self.tasklist.is_none() {
+            self.final_status =
+                HostWorkFlowStatus::JobInitFailed("No tasklist defined for this job".into());
+            return None;
+        }
+
+        // Build a context
+        let temp_tera_context = match &self.vars {
+            Some(context_value) => match tera::Context::from_value(context_value.clone()) {
+                Ok(context) => context,
+                Err(error) => {
+                    self.final_status = HostWorkFlowStatus::JobInitFailed(format!(
+                        "Job variables can't be used as a context : {}",
+                        error
+                    ));
+                    return None;
+                }
+            },
+            None => tera::Context::new(),
+        };
+
+        // Get a HostHandler : borrowed from the pool or built for this run only
+        let host_handler = match &self.connection_pool {
+            Some(pool) => pool.borrow(&self.host.address, &self.host_connection_info),
+            None => {
+                HostHandler::from(self.host.address.clone(), self.host_connection_info.clone())
+                    .and_then(|mut host_handler| {
+                        host_handler.init()?;
+                        Ok(PooledHostHandler::unpooled(host_handler))
+                    })
+            }
+        };
+        let mut host_handler = match host_handler {
+            Ok(host_handler) => host_handler,
+            Err(error) => {
+                self.final_status = HostWorkFlowStatus::ConnectionInitFailed(error.with_sources());
+                return None;
+            }
+        };
+        self.connection_report = Some(host_handler.connection_report());
+        host_handler.output_sink = self.output_sink.clone();
+        host_handler.become_method = self.become_method.unwrap_or(BecomeMethod::Sudo);
+        host_handler.become_password = self.become_password.clone();
+
+        Some((host_handler, temp_tera_context))
+    }
+
     pub fn display(&mut self) -> String {
         let job_output = JobOutput::from_job(self);
         serde_json::to_string(&job_output).unwrap()
@@ -318,3 +421,48 @@ pub enum HostAddress {
     LocalHost,
     RemoteHost(String), // IP/hostname
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn become_password_is_not_serialized() {
+        let mut job = Job::new();
+        job.set_become_password("s3cr3t-become-password");
+
+        let serialized = serde_json::to_string(&job).unwrap();
+        assert!(!serialized.contains("s3cr3t-become-password"));
+        assert!(!serialized.contains("become_password"));
+
+        let deserialized: Job = serde_json::from_str(&serialized).unwrap();
+        assert!(deserialized.become_password.is_none());
+    }
+
+    #[test]
+    fn other_hosts_variables_are_not_kept_in_vars() {
+        let mut job = Job::from_host(Host::from_string("localhost".into()));
+        job.add_var("greeting", "hello");
+        job.set_connection(HostConnectionInfo::localhost_current_user())
+            .unwrap()
+            .set_tasklist_from_str(
+                "---
+- name: Rendering with hostvars
+  steps:
+    - name: Read variables and hostvars
+      register: greeted
+      command:
+        content: echo {{ greeting }} {{ hostvars | length }}
+",
+                TaskListFileType::Yaml,
+            )
+            .unwrap();
+
+        job.apply();
+
+        let vars = job.vars.unwrap();
+        assert_eq!(vars["greeted"]["output"].as_str().unwrap(), "hello 1\\n");
+        assert!(vars.get("hostvars").is_none());
+        assert!(vars.get("groups").is_none());
+    }
+}
@@ -1,24 +1,31 @@
 use rayon::iter::IntoParallelRefMutIterator;
 use rayon::iter::ParallelIterator;
 
+use crate::connection::escalation::BecomeMethod;
 use crate::connection::host_connection::HostConnectionInfo;
+use crate::connection::pool::ConnectionPool;
+use crate::error::workflow::WorkflowError;
 use crate::error::Error;
 use crate::host::hostlist::HostList;
 use crate::job::job::Job;
 use crate::output::joblist_output::JobListOutput;
+use crate::output::streaming::OutputSink;
 use crate::task::tasklist::TaskList;
 use crate::task::tasklist::TaskListFileType;
+use crate::workflow::coordination::JobListCoordinator;
 
 /// A JobList is just a Vec of Jobs on which convenient methods are defined. It simplifies the handling of multiple hosts.
 #[derive(Debug, Clone)]
 pub struct JobList {
     pub job_list: Option<Vec<Job>>,
+    pub share_registered_vars: bool,
 }
 
 impl JobList {
     pub fn new() -> JobList {
         JobList {
             job_list: Some(Vec::new()),
+            share_registered_vars: false,
         }
     }
 
@@ -34,9 +41,13 @@ impl JobList {
 
                 JobList {
                     job_list: Some(jobs),
+                    share_registered_vars: false,
                 }
             }
-            None => JobList { job_list: None },
+            None => JobList {
+                job_list: None,
+                share_registered_vars: false,
+            },
         }
     }
 
@@ -49,9 +60,9 @@ impl JobList {
 
     /// This method integrates to the JobList an "externally-defined" Job, meaning it can have a different connection method and so-on.
     pub fn add_job(&mut self, job: Job) {
-        match &self.job_list {
-            Some(_jobs) => {
-                self.job_list.as_mut().unwrap().push(job);
+        match &mut self.job_list {
+            Some(jobs) => {
+                jobs.push(job);
             }
             None => {
                 self.job_list = Some(vec![job]);
@@ -71,19 +82,20 @@ impl JobList {
         serde_json::to_string_pretty(&joblist_output).unwrap()
     }
 
-    /// Set the same connection information for all hosts of the JobList
+    /// Set the same connection information for all hosts of the JobList. Connection parameters defined in the HostList (port, user, key) take precedence.
     pub fn set_connection(
         &mut self,
         host_connection_info: HostConnectionInfo,
     ) -> Result<&mut Self, Error> {
         if let HostConnectionInfo::Unset = host_connection_info {
-            Err(Error::WrongInitialization(format!(
-                "No point in initializing connection info to HostConnectionInfo::Unset"
+            Err(Error::Workflow(WorkflowError::WrongInitialization(
+                "No point in initializing connection info to HostConnectionInfo::Unset".to_string(),
             )))
         } else {
             if let Some(jobs) = &mut self.job_list {
                 for job in jobs {
-                    job.host_connection_info = host_connection_info.clone();
+                    job.host_connection_info =
+                        job.host.connection_info(host_connection_info.clone());
                 }
             }
 
@@ -91,6 +103,53 @@ impl JobList {
         }
     }
 
+    /// Output of all Jobs is streamed to the same sink. Lines are tagged with their host since Jobs run in parallel.
+    pub fn set_output_sink(&mut self, output_sink: OutputSink) -> &mut Self {
+        if let Some(jobs) = &mut self.job_list {
+            for job in jobs {
+                job.set_output_sink(output_sink.clone());
+            }
+        }
+
+        self
+    }
+
+    /// All Jobs borrow their connection from this pool : keep it to reuse connections in the next runs (dry_run then apply, other JobLists...)
+    pub fn set_connection_pool(&mut self, connection_pool: ConnectionPool) -> &mut Self {
+        if let Some(jobs) = &mut self.job_list {
+            for job in jobs {
+                job.set_connection_pool(connection_pool.clone());
+            }
+        }
+
+        self
+    }
+
+    /// Same escalation method for all hosts of the JobList. The HostList's become_method takes precedence.
+    pub fn set_become_method(&mut self, become_method: BecomeMethod) -> &mut Self {
+        if let Some(jobs) = &mut self.job_list {
+            for job in jobs
+                .iter_mut()
+                .filter(|job| job.host.become_method().is_none())
+            {
+                job.set_become_method(become_method);
+            }
+        }
+
+        self
+    }
+
+    /// Same sudo password for all hosts of the JobList
+    pub fn set_become_password(&mut self, password: &str) -> &mut Self {
+        if let Some(jobs) = &mut self.job_list {
+            for job in jobs {
+                job.set_become_password(password);
+            }
+        }
+
+        self
+    }
+
     /// Add the same variable for each host of the JobList
     pub fn add_var(&mut self, key: &str, value: &str) -> &mut Self {
         if let Some(jobs) = &mut self.job_list {
@@ -102,6 +161,13 @@ impl JobList {
         self
     }
 
+    /// Each host's variables are always visible to all Jobs through `hostvars` (ex: `{{ hostvars['10.20.0.10'].db_port }}`).
+    /// With this enabled, values registered by a Job are added to its host entry as well, and become visible to other Jobs as soon as they are registered.
+    pub fn share_registered_vars(&mut self, share_registered_vars: bool) -> &mut Self {
+        self.share_registered_vars = share_registered_vars;
+        self
+    }
+
     /// Set the given tasklist for all hosts of the JobList
     pub fn set_tasklist_from_str(
         &mut self,
@@ -145,18 +211,425 @@ impl JobList {
     }
 
     /// "DRY_RUN" the task list on each host of this JobList. This is done in parallel (based on the Rayon crate).
+    /// Steps with `run_once` are only evaluated by the first Job reaching them and steps with `delegate_to` can target any host of the JobList.
     pub fn dry_run(&mut self) -> Result<(), Error> {
         if let Some(jobs) = &mut self.job_list {
-            jobs.par_iter_mut().for_each(|job| job.dry_run());
+            let coordinator = JobListCoordinator::from_jobs(jobs)
+                .with_registered_vars_sharing(self.share_registered_vars);
+            jobs.par_iter_mut()
+                .for_each(|job| job.dry_run_coordinated(&coordinator));
         }
 
         Ok(())
     }
 
     /// "APPLY" the task list on each host of this JobList. This is done in parallel (based on the Rayon crate).
+    /// Steps with `run_once` are only applied by the first Job reaching them, their registered result being shared with all other Jobs. Steps with `delegate_to` can target any host of the JobList.
     pub fn apply(&mut self) {
         if let Some(jobs) = &mut self.job_list {
-            jobs.par_iter_mut().for_each(|job| job.apply());
+            let coordinator = JobListCoordinator::from_jobs(jobs)
+                .with_registered_vars_sharing(self.share_registered_vars);
+            jobs.par_iter_mut()
+                .for_each(|job| job.apply_coordinated(&coordinator));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::connectionmode::ssh2mode::{HostKeyPolicy, Ssh2AuthMode};
+    use crate::host::hosts::Host;
+    use crate::output::streaming::{OutputLine, OutputStream};
+
+    #[test]
+    fn run_once_step_is_shared_across_jobs() {
+        let marker_file = std::env::temp_dir().join(format!("dux-run-once-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker_file);
+
+        let mut job_list = JobList::new();
+        job_list.add_job(Job::from_host(Host::from_string("first".into())));
+        job_list.add_job(Job::from_host(Host::from_string("second".into())));
+        job_list
+            .set_connection(HostConnectionInfo::localhost_current_user())
+            .unwrap()
+            .set_tasklist_from_str(
+                format!(
+                    "---
+- name: Run once
+  steps:
+    - name: Only one host writes in the file
+      run_once: true
+      register: once
+      command:
+        content: echo written >> {} && echo done
+    - name: Every host sees the shared result
+      register: seen
+      command:
+        content: echo '{{{{ once.output }}}}'
+",
+                    marker_file.display()
+                )
+                .as_str(),
+                TaskListFileType::Yaml,
+            )
+            .unwrap();
+
+        job_list.apply();
+
+        let file_content = std::fs::read_to_string(&marker_file).unwrap();
+        std::fs::remove_file(&marker_file).unwrap();
+        assert_eq!(file_content.lines().count(), 1);
+
+        for job in job_list.job_list.unwrap() {
+            let vars = job.vars.unwrap();
+            assert!(vars["seen"]["output"].as_str().unwrap().contains("done"));
+        }
+    }
+
+    #[test]
+    fn run_once_steps_of_different_tasklists_are_not_shared() {
+        let marker_file =
+            std::env::temp_dir().join(format!("dux-run-once-tasklists-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker_file);
+
+        let mut job_list = JobList::new();
+        for (address, task_name) in [("first", "Deploy the API"), ("second", "Deploy the UI")] {
+            let mut job = Job::from_host(Host::from_string(address.into()));
+            job.set_connection(HostConnectionInfo::localhost_current_user())
+                .unwrap()
+                .set_tasklist_from_str(
+                    format!(
+                        "---
+- name: {}
+  steps:
+    - run_once: true
+      command:
+        content: echo {} >> {}
+",
+                        task_name,
+                        address,
+                        marker_file.display()
+                    )
+                    .as_str(),
+                    TaskListFileType::Yaml,
+                )
+                .unwrap();
+            job_list.add_job(job);
+        }
+
+        job_list.apply();
+
+        // Same position, different steps : each one is run
+        let file_content = std::fs::read_to_string(&marker_file).unwrap();
+        std::fs::remove_file(&marker_file).unwrap();
+        let mut lines: Vec<&str> = file_content.lines().collect();
+        lines.sort();
+        assert_eq!(lines, vec!["first", "second"]);
+    }
+
+    #[test]
+    fn stdout_and_stderr_are_registered_separately() {
+        let mut job_list = JobList::new();
+        job_list.add_job(Job::from_host(Host::from_string("local".into())));
+        job_list
+            .set_connection(HostConnectionInfo::localhost_current_user())
+            .unwrap()
+            .set_tasklist_from_str(
+                "---
+- name: Outputs
+  steps:
+    - name: Data on stdout, warning on stderr
+      register: both
+      command:
+        content: echo data; echo warning >&2
+",
+                TaskListFileType::Yaml,
+            )
+            .unwrap();
+
+        job_list.apply();
+
+        for job in job_list.job_list.unwrap() {
+            let vars = job.vars.unwrap();
+            assert_eq!(vars["both"]["output"].as_str().unwrap(), "data\\n");
+            assert_eq!(vars["both"]["stderr"].as_str().unwrap(), "warning\\n");
+        }
+    }
+
+    #[test]
+    fn output_is_streamed_while_steps_are_applied() {
+        let (sender, receiver) = std::sync::mpsc::channel::<OutputLine>();
+        let mut job_list = JobList::new();
+        job_list.add_job(Job::from_host(Host::from_string("local".into())));
+        job_list
+            .set_connection(HostConnectionInfo::localhost_current_user())
+            .unwrap()
+            .set_tasklist_from_str(
+                "---
+- name: Upgrade
+  steps:
+    - name: Long running command
+      register: upgrade
+      command:
+        content: echo first; echo oops >&2; echo second
+    - command:
+        content: echo unnamed
+",
+                TaskListFileType::Yaml,
+            )
+            .unwrap();
+        job_list.set_output_sink(OutputSink::from_sender(sender));
+
+        job_list.apply();
+
+        let (lines, unnamed_step_lines): (Vec<OutputLine>, Vec<OutputLine>) = receiver
+            .try_iter()
+            .partition(|line| line.step == "Long running command");
+        assert!(lines.iter().all(|line| line.host == "local"));
+        assert_eq!(unnamed_step_lines.len(), 1);
+        assert_eq!(unnamed_step_lines[0].step, "task 1, step 2");
+        let lines_of = |stream: OutputStream| -> Vec<String> {
+            lines
+                .iter()
+                .filter(|line| line.stream == stream)
+                .map(|line| line.line.clone())
+                .collect()
+        };
+        assert_eq!(lines_of(OutputStream::Stdout), vec!["first", "second"]);
+        assert_eq!(lines_of(OutputStream::Stderr), vec!["oops"]);
+
+        // The final result is still assembled
+        for job in job_list.job_list.unwrap() {
+            let vars = job.vars.unwrap();
+            assert_eq!(
+                vars["upgrade"]["output"].as_str().unwrap(),
+                "first\\nsecond\\n"
+            );
+        }
+    }
+
+    #[test]
+    fn dry_run_and_apply_share_the_pooled_connection() {
+        let pool = ConnectionPool::new();
+        let mut job_list = JobList::new();
+        job_list.add_job(Job::from_host(Host::from_string("localhost".into())));
+        job_list
+            .set_connection(HostConnectionInfo::localhost_current_user())
+            .unwrap()
+            .set_connection_pool(pool.clone())
+            .set_tasklist_from_str(
+                "---
+- name: Pooled
+  steps:
+    - name: Some command
+      command:
+        content: echo pooled
+",
+                TaskListFileType::Yaml,
+            )
+            .unwrap();
+
+        job_list.dry_run().unwrap();
+        assert_eq!(pool.idle_count(), 1);
+        job_list.apply();
+        assert_eq!(pool.idle_count(), 1);
+    }
+
+    #[test]
+    fn lines_are_written_as_they_are() {
+        // The path has to be quoted as well
+        let file = std::env::temp_dir().join(format!("dux lineinfile it's {}", std::process::id()));
+        std::fs::write(&file, "first\nlast\n").unwrap();
+        let tasklist = format!(
+            r#"---
+- name: Lines with special characters
+  steps:
+    - name: At the top
+      lineinfile:
+        filepath: {path}
+        line: "it's \"quoted\" $HOME"
+        state: present
+        position: top
+    - name: At the bottom
+      lineinfile:
+        filepath: {path}
+        line: 'back\slash & `ticks`'
+        state: present
+        position: bottom
+"#,
+            path = file.display()
+        );
+
+        for _ in 0..2 {
+            let mut job_list = JobList::new();
+            job_list.add_job(Job::from_host(Host::from_string("local".into())));
+            job_list
+                .set_connection(HostConnectionInfo::localhost_current_user())
+                .unwrap()
+                .set_tasklist_from_str(&tasklist, TaskListFileType::Yaml)
+                .unwrap();
+            job_list.apply();
+        }
+
+        // Applied twice : the second run finds the lines already there
+        assert_eq!(
+            std::fs::read_to_string(&file).unwrap(),
+            "it's \"quoted\" $HOME\nfirst\nlast\nback\\slash & `ticks`\n"
+        );
+        let _ = std::fs::remove_file(&file);
+    }
+
+    #[test]
+    fn jobs_get_their_own_connection_from_the_hostlist() {
+        let mut job_list = JobList::from_hostlist_as_str(
+            "---
+hosts:
+  - host: db1
+    port: 2222
+    user: deploy
+    key: /home/deploy/.ssh/id_ed25519
+  - web1
+",
+        )
+        .unwrap();
+        job_list
+            .set_connection(HostConnectionInfo::ssh2_with_key_file(
+                "admin",
+                "/home/admin/.ssh/id_ed25519",
+            ))
+            .unwrap();
+
+        for job in job_list.job_list.unwrap() {
+            let HostConnectionInfo::Ssh2(settings) = job.host_connection_info else {
+                panic!("SSH2 connection expected");
+            };
+            match job.host.address.as_str() {
+                "db1" => {
+                    assert_eq!(settings.port, Some(2222));
+                    assert_eq!(settings.authmode.username(), Some("deploy".into()));
+                }
+                _ => {
+                    assert_eq!(settings.port, None);
+                    assert_eq!(settings.authmode.username(), Some("admin".into()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn explicit_become_method_of_a_job_wins() {
+        let mut job_list = JobList::from_hostlist_as_str(
+            "---
+hosts:
+  - host: db1
+    become_method: doas
+  - web1
+",
+        )
+        .unwrap();
+        job_list.set_become_method(BecomeMethod::Su);
+
+        let mut jobs = job_list.job_list.unwrap();
+        // The HostList's method is kept over the one of the JobList...
+        assert_eq!(jobs[0].become_method, Some(BecomeMethod::Doas));
+        assert_eq!(jobs[1].become_method, Some(BecomeMethod::Su));
+        // ... but not over the one given to the Job itself
+        jobs[0].set_become_method(BecomeMethod::Run0);
+        assert_eq!(jobs[0].become_method, Some(BecomeMethod::Run0));
+    }
+
+    #[test]
+    fn hostlist_keys_only_replace_ssh2_keys() {
+        let hostlist = "---
+hosts:
+  - host: db1
+    user: deploy
+    key: /home/deploy/.ssh/id_ed25519
+";
+        let mut job_list = JobList::from_hostlist_as_str(hostlist).unwrap();
+        job_list
+            .set_connection(
+                HostConnectionInfo::ssh2_with_key_file("admin", "/home/admin/.ssh/id_ed25519")
+                    .with_host_key_policy(HostKeyPolicy::Strict)
+                    .with_jump_host(
+                        "bastion",
+                        HostConnectionInfo::ssh2_with_agent_socket("admin".into(), "/tmp/agent"),
+                    ),
+            )
+            .unwrap();
+        let job = &job_list.job_list.as_ref().unwrap()[0];
+        let HostConnectionInfo::Ssh2(settings) = &job.host_connection_info else {
+            panic!("SSH2 connection expected");
+        };
+        // Only the key changes
+        assert_eq!(
+            settings.authmode,
+            Ssh2AuthMode::KeyFile(("deploy".into(), "/home/deploy/.ssh/id_ed25519".into()))
+        );
+        assert_eq!(settings.host_key.policy, HostKeyPolicy::Strict);
+        assert_eq!(settings.jump_hosts.len(), 1);
+
+        let mut job_list = JobList::from_hostlist_as_str(hostlist).unwrap();
+        job_list
+            .set_connection(HostConnectionInfo::localhost_current_user())
+            .unwrap();
+        let job = &job_list.job_list.as_ref().unwrap()[0];
+        assert!(matches!(
+            job.host_connection_info,
+            HostConnectionInfo::LocalHost(_)
+        ));
+    }
+
+    #[test]
+    fn hostvars_and_groups_are_visible_to_all_jobs() {
+        let mut job_list = JobList::from_hostlist_as_str(
+            "---
+groups:
+  - name: web
+    hosts:
+      - web1
+    vars:
+      me: web1
+  - name: db
+    hosts:
+      - db1
+    vars:
+      me: db1
+      db_port: '5432'
+",
+        )
+        .unwrap();
+        job_list
+            .share_registered_vars(true)
+            .set_connection(HostConnectionInfo::localhost_current_user())
+            .unwrap()
+            .set_tasklist_from_str(
+                "---
+- name: Cross-host variables
+  steps:
+    - name: Read the database host's variables
+      register: db_access
+      command:
+        content: echo {{ groups.db | join(sep=',') }}:{{ hostvars['db1'].db_port }}
+    - name: Registered values are now in hostvars as well
+      register: own_entry
+      command:
+        content: echo {{ hostvars[me].db_access.rc }}
+",
+                TaskListFileType::Yaml,
+            )
+            .unwrap();
+
+        job_list.apply();
+
+        for job in job_list.job_list.unwrap() {
+            let vars = job.vars.unwrap();
+            assert_eq!(vars["db_access"]["output"].as_str().unwrap(), "db1:5432\\n");
+            assert_eq!(vars["own_entry"]["output"].as_str().unwrap(), "0\\n");
+            // Only needed while steps are rendered : each Job keeps its own variables
+            assert!(vars.get("hostvars").is_none());
+            assert!(vars.get("groups").is_none());
         }
     }
 }
@@ -1,4 +1,5 @@
 //! Dux main interaction point
  
+#[allow(clippy::module_inception)]
 pub mod job;
 pub mod joblist;
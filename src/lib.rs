@@ -54,6 +54,17 @@
 //!}
 //! ```
 
+// Functions end with an explicit `return`, types are built with `new()` and single cases are matched with `match` :
+// all part of the codebase's style
+#![allow(
+    clippy::needless_return,
+    clippy::new_without_default,
+    clippy::single_match
+)]
+
+#[cfg(not(any(feature = "ssh2", feature = "russh")))]
+compile_error!("at least one SSH backend is needed : enable the \"ssh2\" or \"russh\" feature");
+
 pub mod connection;
 pub mod error;
 pub mod exitcode;
@@ -63,6 +74,7 @@ pub mod modules;
 pub mod output;
 pub mod prelude;
 pub mod result;
+pub mod schema;
 pub mod step;
 pub mod task;
 pub mod workflow;
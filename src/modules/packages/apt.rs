@@ -1,16 +1,20 @@
 // APT Module : handle packages in Debian-like distributions
 
+use crate::connection::argv::Argv;
 use crate::connection::hosthandler::HostHandler;
 use crate::connection::specification::Privilege;
+use crate::error::module::ModuleError;
 use crate::error::Error;
 use crate::result::apicallresult::{ApiCallResult, ApiCallStatus};
 use crate::step::stepchange::StepChange;
 use crate::task::moduleblock::ModuleApiCall;
-use crate::task::moduleblock::{Apply, DryRun};
+use crate::task::moduleblock::{check_allowed_value, check_not_an_option, Apply, DryRun, Validate};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct AptBlockExpectedState {
+    #[schemars(extend("anyOf" = [{ "enum": ["present", "absent"] }, { "pattern": "\\{[{%]" }]))]
     #[serde(skip_serializing_if = "Option::is_none")]
     state: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -25,12 +29,12 @@ impl DryRun for AptBlockExpectedState {
         hosthandler: &mut HostHandler,
         privilege: Privilege,
     ) -> Result<StepChange, Error> {
-        if !hosthandler.is_this_cmd_available("apt-get").unwrap()
-            || !hosthandler.is_this_cmd_available("dpkg").unwrap()
+        if !hosthandler.is_this_cmd_available("apt-get")?
+            || !hosthandler.is_this_cmd_available("dpkg")?
         {
-            return Err(Error::FailedDryRunEvaluation(
+            return Err(Error::Module(ModuleError::Unavailable(
                 "APT not working on this host".to_string(),
-            ));
+            )));
         }
 
         let mut changes: Vec<ModuleApiCall> = Vec::new();
@@ -38,36 +42,44 @@ impl DryRun for AptBlockExpectedState {
         match &self.state {
             None => {}
             Some(state) => {
+                let package = match &self.package {
+                    Some(package) => package.clone(),
+                    None => {
+                        return Err(Error::Module(ModuleError::InvalidArguments(
+                            "package is required when state is defined".to_string(),
+                        )));
+                    }
+                };
                 match state.as_str() {
                     "present" => {
                         // Check is package is already installed or needs to be
-                        if is_package_installed(hosthandler, self.package.clone().unwrap()) {
+                        if is_package_installed(hosthandler, package.clone())? {
                             changes.push(ModuleApiCall::None(format!(
                                 "{} already present",
-                                self.package.clone().unwrap()
+                                package.clone()
                             )));
                         } else {
                             // Package is absent and needs to be installed
                             changes.push(ModuleApiCall::Apt(AptApiCall::from(
                                 "install",
-                                Some(self.package.clone().unwrap()),
+                                Some(package.clone()),
                                 privilege.clone(),
                             )));
                         }
                     }
                     "absent" => {
                         // Check is package is already absent or needs to be removed
-                        if is_package_installed(hosthandler, self.package.clone().unwrap()) {
+                        if is_package_installed(hosthandler, package.clone())? {
                             // Package is present and needs to be removed
                             changes.push(ModuleApiCall::Apt(AptApiCall::from(
                                 "remove",
-                                Some(self.package.clone().unwrap()),
+                                Some(package.clone()),
                                 privilege.clone(),
                             )));
                         } else {
                             changes.push(ModuleApiCall::None(format!(
                                 "{} already absent",
-                                self.package.clone().unwrap()
+                                package.clone()
                             )));
                         }
                     }
@@ -102,6 +114,35 @@ impl DryRun for AptBlockExpectedState {
     }
 }
 
+impl Validate for AptBlockExpectedState {
+    fn validate_block(&self) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+
+        match &self.state {
+            Some(state) => {
+                if let Some(problem) = check_allowed_value("state", state, &["present", "absent"]) {
+                    problems.push(problem);
+                }
+                match &self.package {
+                    Some(package) => {
+                        if let Some(problem) = check_not_an_option("package", package) {
+                            problems.push(problem);
+                        }
+                    }
+                    None => problems.push("package is required when state is defined".into()),
+                }
+            }
+            None => {
+                if self.upgrade.is_none() {
+                    problems.push("Either state or upgrade is required".into());
+                }
+            }
+        }
+
+        problems
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AptApiCall {
     action: String,
@@ -113,10 +154,10 @@ impl Apply for AptApiCall {
     fn display(&self) -> String {
         match self.action.as_str() {
             "install" => {
-                return format!("Install - {}", self.package.clone().unwrap());
+                return format!("Install - {}", self.package.clone().unwrap_or_default());
             }
             "remove" => {
-                return format!("Remove - {}", self.package.clone().unwrap());
+                return format!("Remove - {}", self.package.clone().unwrap_or_default());
             }
             "upgrade" => {
                 return String::from("Upgrade");
@@ -130,84 +171,89 @@ impl Apply for AptApiCall {
     fn apply_moduleblock_change(&self, hosthandler: &mut HostHandler) -> ApiCallResult {
         match self.action.as_str() {
             "install" => {
-                hosthandler
-                    .run_cmd("apt-get update", self.privilege.clone())
-                    .unwrap();
-
-                let cmd = format!(
-                    "DEBIAN_FRONTEND=noninteractive apt-get install -y {}",
-                    self.package.clone().unwrap()
-                );
-                let cmd_result = hosthandler
-                    .run_cmd(cmd.as_str(), self.privilege.clone())
-                    .unwrap();
+                if let Err(error) =
+                    hosthandler.run(&Argv::new("apt-get").arg("update"), self.privilege.clone())
+                {
+                    return ApiCallResult::from_error(&error);
+                }
+
+                let cmd = Argv::new("apt-get")
+                    .env("DEBIAN_FRONTEND", "noninteractive")
+                    .args(["install", "-y", "--"])
+                    .arg(self.package.clone().unwrap_or_default());
+                let cmd_result = match hosthandler.run(&cmd, self.privilege.clone()) {
+                    Ok(cmd_result) => cmd_result,
+                    Err(error) => return ApiCallResult::from_error(&error),
+                };
 
                 if cmd_result.rc == 0 {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::ChangeSuccessful(format!(
                             "{} install successful",
-                            self.package.clone().unwrap()
+                            self.package.clone().unwrap_or_default()
                         )),
                     );
                 } else {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::Failure(format!(
                             "{} install failed",
-                            self.package.clone().unwrap()
+                            self.package.clone().unwrap_or_default()
                         )),
                     );
                 }
             }
             "remove" => {
-                let cmd = format!(
-                    "DEBIAN_FRONTEND=noninteractive apt-get remove --purge -y {}",
-                    self.package.clone().unwrap()
-                );
-                let cmd_result = hosthandler
-                    .run_cmd(cmd.as_str(), self.privilege.clone())
-                    .unwrap();
+                let cmd = Argv::new("apt-get")
+                    .env("DEBIAN_FRONTEND", "noninteractive")
+                    .args(["remove", "--purge", "-y", "--"])
+                    .arg(self.package.clone().unwrap_or_default());
+                let cmd_result = match hosthandler.run(&cmd, self.privilege.clone()) {
+                    Ok(cmd_result) => cmd_result,
+                    Err(error) => return ApiCallResult::from_error(&error),
+                };
 
                 if cmd_result.rc == 0 {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::ChangeSuccessful(format!(
                             "{} removal successful",
-                            self.package.clone().unwrap()
+                            self.package.clone().unwrap_or_default()
                         )),
                     );
                 } else {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::Failure(format!(
                             "{} removal failed",
-                            self.package.clone().unwrap()
+                            self.package.clone().unwrap_or_default()
                         )),
                     );
                 }
             }
             "upgrade" => {
-                hosthandler
-                    .run_cmd("apt-get update", self.privilege.clone())
-                    .unwrap();
-                let cmd = "DEBIAN_FRONTEND=noninteractive apt-get upgrade -y";
-                let cmd_result = hosthandler.run_cmd(cmd, self.privilege.clone()).unwrap();
+                if let Err(error) =
+                    hosthandler.run(&Argv::new("apt-get").arg("update"), self.privilege.clone())
+                {
+                    return ApiCallResult::from_error(&error);
+                }
+                let cmd = Argv::new("apt-get")
+                    .env("DEBIAN_FRONTEND", "noninteractive")
+                    .args(["upgrade", "-y"]);
+                let cmd_result = match hosthandler.run(&cmd, self.privilege.clone()) {
+                    Ok(cmd_result) => cmd_result,
+                    Err(error) => return ApiCallResult::from_error(&error),
+                };
 
                 if cmd_result.rc == 0 {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::ChangeSuccessful(String::from("APT upgrade successful")),
                     );
                 } else {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::Failure(String::from("APT upgrade failed")),
                     );
                 }
@@ -229,16 +275,15 @@ impl AptApiCall {
     }
 }
 
-fn is_package_installed(hosthandler: &mut HostHandler, package: String) -> bool {
-    let test = hosthandler
-        .run_cmd(format!("dpkg -s {}", package).as_str(), Privilege::Usual)
-        .unwrap();
+fn is_package_installed(hosthandler: &mut HostHandler, package: String) -> Result<bool, Error> {
+    let test = hosthandler.run(
+        &Argv::new("dpkg").args(["-s", "--"]).arg(package),
+        Privilege::Usual,
+    )?;
 
     if test.rc == 0 && test.stdout.contains("Status: install") {
-        true
-    } else if test.rc == 0 && test.stdout.contains("Status: deinstall") {
-        false
+        Ok(true)
     } else {
-        false
+        Ok(false)
     }
 }
@@ -1,16 +1,20 @@
 // YUM / DNF Module : handle packages in Fedora-like distributions
 
+use crate::connection::argv::Argv;
 use crate::connection::hosthandler::HostHandler;
 use crate::connection::specification::Privilege;
+use crate::error::module::ModuleError;
 use crate::error::Error;
 use crate::result::apicallresult::{ApiCallResult, ApiCallStatus};
 use crate::step::stepchange::StepChange;
 use crate::task::moduleblock::ModuleApiCall;
-use crate::task::moduleblock::{Apply, DryRun};
+use crate::task::moduleblock::{check_allowed_value, check_not_an_option, Apply, DryRun, Validate};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct YumDnfBlockExpectedState {
+    #[schemars(extend("anyOf" = [{ "enum": ["present", "absent"] }, { "pattern": "\\{[{%]" }]))]
     #[serde(skip_serializing_if = "Option::is_none")]
     state: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -28,14 +32,14 @@ impl DryRun for YumDnfBlockExpectedState {
     ) -> Result<StepChange, Error> {
         let mut tool = String::new();
 
-        if hosthandler.is_this_cmd_available("dnf").unwrap() {
+        if hosthandler.is_this_cmd_available("dnf")? {
             tool = String::from("dnf");
-        } else if hosthandler.is_this_cmd_available("yum").unwrap() {
+        } else if hosthandler.is_this_cmd_available("yum")? {
             tool = String::from("yum");
         } else {
-            return Err(Error::FailedDryRunEvaluation(
+            return Err(Error::Module(ModuleError::Unavailable(
                 "Neither YUM nor DNF work on this host".to_string(),
-            ));
+            )));
         }
 
         let mut changes: Vec<ModuleApiCall> = Vec::new();
@@ -43,25 +47,33 @@ impl DryRun for YumDnfBlockExpectedState {
         match &self.state {
             None => {}
             Some(state) => {
+                let package = match &self.package {
+                    Some(package) => package.clone(),
+                    None => {
+                        return Err(Error::Module(ModuleError::InvalidArguments(
+                            "package is required when state is defined".to_string(),
+                        )));
+                    }
+                };
                 match state.as_str() {
                     "present" => {
                         // Check is package is already installed or needs to be
                         if is_package_installed(
                             hosthandler,
                             &tool,
-                            self.package.clone().unwrap(),
+                            package.clone(),
                             privilege.clone(),
-                        ) {
+                        )? {
                             changes.push(ModuleApiCall::None(format!(
                                 "{} already present",
-                                self.package.clone().unwrap()
+                                package.clone()
                             )));
                         } else {
                             // Package is absent and needs to be installed
                             changes.push(ModuleApiCall::YumDnf(YumDnfApiCall::from(
                                 "install",
                                 &tool,
-                                Some(self.package.clone().unwrap()),
+                                Some(package.clone()),
                                 privilege.clone(),
                             )));
                         }
@@ -71,20 +83,20 @@ impl DryRun for YumDnfBlockExpectedState {
                         if is_package_installed(
                             hosthandler,
                             &tool,
-                            self.package.clone().unwrap(),
+                            package.clone(),
                             privilege.clone(),
-                        ) {
+                        )? {
                             // Package is present and needs to be removed
                             changes.push(ModuleApiCall::YumDnf(YumDnfApiCall::from(
                                 "remove",
                                 &tool,
-                                Some(self.package.clone().unwrap()),
+                                Some(package.clone()),
                                 privilege.clone(),
                             )));
                         } else {
                             changes.push(ModuleApiCall::None(format!(
                                 "{} already absent",
-                                self.package.clone().unwrap()
+                                package.clone()
                             )));
                         }
                     }
@@ -118,6 +130,35 @@ impl DryRun for YumDnfBlockExpectedState {
     }
 }
 
+impl Validate for YumDnfBlockExpectedState {
+    fn validate_block(&self) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+
+        match &self.state {
+            Some(state) => {
+                if let Some(problem) = check_allowed_value("state", state, &["present", "absent"]) {
+                    problems.push(problem);
+                }
+                match &self.package {
+                    Some(package) => {
+                        if let Some(problem) = check_not_an_option("package", package) {
+                            problems.push(problem);
+                        }
+                    }
+                    None => problems.push("package is required when state is defined".into()),
+                }
+            }
+            None => {
+                if self.upgrade.is_none() {
+                    problems.push("Either state or upgrade is required".into());
+                }
+            }
+        }
+
+        problems
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct YumDnfApiCall {
     action: String,
@@ -130,10 +171,10 @@ impl Apply for YumDnfApiCall {
     fn display(&self) -> String {
         match self.action.as_str() {
             "install" => {
-                return format!("Install - {}", self.package.clone().unwrap());
+                return format!("Install - {}", self.package.clone().unwrap_or_default());
             }
             "remove" => {
-                return format!("Remove - {}", self.package.clone().unwrap());
+                return format!("Remove - {}", self.package.clone().unwrap_or_default());
             }
             "upgrade" => {
                 return String::from("Upgrade");
@@ -147,74 +188,75 @@ impl Apply for YumDnfApiCall {
     fn apply_moduleblock_change(&self, hosthandler: &mut HostHandler) -> ApiCallResult {
         match self.action.as_str() {
             "install" => {
-                let cmd = format!("{} install -y {}", self.tool, self.package.clone().unwrap());
-                let cmd_result = hosthandler
-                    .run_cmd(cmd.as_str(), self.privilege.clone())
-                    .unwrap();
+                let cmd = Argv::new(&self.tool)
+                    .args(["install", "-y", "--"])
+                    .arg(self.package.clone().unwrap_or_default());
+                let cmd_result = match hosthandler.run(&cmd, self.privilege.clone()) {
+                    Ok(cmd_result) => cmd_result,
+                    Err(error) => return ApiCallResult::from_error(&error),
+                };
 
                 if cmd_result.rc == 0 {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::ChangeSuccessful(format!(
                             "{} install successful",
-                            self.package.clone().unwrap()
+                            self.package.clone().unwrap_or_default()
                         )),
                     );
                 } else {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::Failure(format!(
                             "{} install failed",
-                            self.package.clone().unwrap()
+                            self.package.clone().unwrap_or_default()
                         )),
                     );
                 }
             }
             "remove" => {
-                let cmd = format!("{} remove -y {}", self.tool, self.package.clone().unwrap());
-                let cmd_result = hosthandler
-                    .run_cmd(cmd.as_str(), self.privilege.clone())
-                    .unwrap();
+                let cmd = Argv::new(&self.tool)
+                    .args(["remove", "-y", "--"])
+                    .arg(self.package.clone().unwrap_or_default());
+                let cmd_result = match hosthandler.run(&cmd, self.privilege.clone()) {
+                    Ok(cmd_result) => cmd_result,
+                    Err(error) => return ApiCallResult::from_error(&error),
+                };
 
                 if cmd_result.rc == 0 {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::ChangeSuccessful(format!(
                             "{} removal successful",
-                            self.package.clone().unwrap()
+                            self.package.clone().unwrap_or_default()
                         )),
                     );
                 } else {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::Failure(format!(
                             "{} removal failed",
-                            self.package.clone().unwrap()
+                            self.package.clone().unwrap_or_default()
                         )),
                     );
                 }
             }
             "upgrade" => {
-                let cmd = format!("{} update -y --refresh", self.tool);
-                let cmd_result = hosthandler
-                    .run_cmd(cmd.as_str(), self.privilege.clone())
-                    .unwrap();
+                let cmd = Argv::new(&self.tool).args(["update", "-y", "--refresh"]);
+                let cmd_result = match hosthandler.run(&cmd, self.privilege.clone()) {
+                    Ok(cmd_result) => cmd_result,
+                    Err(error) => return ApiCallResult::from_error(&error),
+                };
 
                 if cmd_result.rc == 0 {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::ChangeSuccessful(String::from("Yum/DNF upgrade successful")),
                     );
                 } else {
                     println!("------{}", cmd_result.stdout);
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::Failure(String::from("Yum/DNF upgrade failed")),
                     );
                 }
@@ -229,13 +271,13 @@ impl Apply for YumDnfApiCall {
 impl YumDnfApiCall {
     pub fn from(
         action: &str,
-        tool: &String,
+        tool: &str,
         package: Option<String>,
         privilege: Privilege,
     ) -> YumDnfApiCall {
         YumDnfApiCall {
             action: action.to_string(),
-            tool: tool.clone(),
+            tool: tool.to_string(),
             package,
             privilege,
         }
@@ -244,20 +286,20 @@ impl YumDnfApiCall {
 
 fn is_package_installed(
     hosthandler: &mut HostHandler,
-    tool: &String,
+    tool: &str,
     package: String,
     privilege: Privilege,
-) -> bool {
-    let test = hosthandler
-        .run_cmd(
-            format!("{tool} list installed {}", package).as_str(),
-            privilege,
-        )
-        .unwrap();
+) -> Result<bool, Error> {
+    let test = hosthandler.run(
+        &Argv::new(tool)
+            .args(["list", "installed", "--"])
+            .arg(package),
+        privilege,
+    )?;
 
     if test.rc == 0 {
-        return true;
+        return Ok(true);
     } else {
-        return false;
+        return Ok(false);
     }
 }
@@ -1,15 +1,17 @@
 // Command module : <short description>
 
+use crate::connection::argv::Argv;
 use crate::connection::hosthandler::HostHandler;
 use crate::connection::specification::Privilege;
 use crate::error::Error;
 use crate::result::apicallresult::{ApiCallResult, ApiCallStatus};
 use crate::step::stepchange::StepChange;
 use crate::task::moduleblock::ModuleApiCall;
-use crate::task::moduleblock::{Apply, DryRun};
+use crate::task::moduleblock::{Apply, DryRun, Validate};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct CommandBlockExpectedState {
     #[serde(skip_serializing_if = "Option::is_none")]
     content: Option<String>,
@@ -38,6 +40,21 @@ impl DryRun for CommandBlockExpectedState {
     }
 }
 
+impl Validate for CommandBlockExpectedState {
+    fn validate_block(&self) -> Vec<String> {
+        match &self.content {
+            Some(content) => {
+                if content.trim().is_empty() {
+                    vec!["content can't be empty".into()]
+                } else {
+                    Vec::new()
+                }
+            }
+            None => vec!["content is required".into()],
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct CommandApiCall {
     cmd: String,
@@ -50,20 +67,21 @@ impl Apply for CommandApiCall {
     }
 
     fn apply_moduleblock_change(&self, hosthandler: &mut HostHandler) -> ApiCallResult {
-        let cmd_result = hosthandler
-            .run_cmd(self.cmd.as_str(), self.privilege.clone())
-            .unwrap();
+        // The whole command line (pipes, redirections...) runs with the step's privileges
+        let cmd = Argv::new("sh").arg("-c").arg(&self.cmd);
+        let cmd_result = match hosthandler.run(&cmd, self.privilege.clone()) {
+            Ok(cmd_result) => cmd_result,
+            Err(error) => return ApiCallResult::from_error(&error),
+        };
 
         if cmd_result.rc == 0 {
-            return ApiCallResult::from(
-                Some(cmd_result.rc),
-                Some(cmd_result.stdout),
+            return ApiCallResult::from_cmd(
+                cmd_result,
                 ApiCallStatus::ChangeSuccessful(String::from("Command successful")),
             );
         } else {
-            return ApiCallResult::from(
-                Some(cmd_result.rc),
-                Some(cmd_result.stdout),
+            return ApiCallResult::from_cmd(
+                cmd_result,
                 ApiCallStatus::Failure(String::from("Command failed")),
             );
         }
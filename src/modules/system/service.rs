@@ -1,17 +1,21 @@
 // Service Module : handle services running on a host
 
+use crate::connection::argv::Argv;
 use crate::connection::hosthandler::HostHandler;
 use crate::connection::specification::Privilege;
+use crate::error::module::ModuleError;
 use crate::error::Error;
 use crate::result::apicallresult::{ApiCallResult, ApiCallStatus};
 use crate::step::stepchange::StepChange;
 use crate::task::moduleblock::ModuleApiCall;
-use crate::task::moduleblock::{Apply, DryRun};
+use crate::task::moduleblock::{check_allowed_value, check_not_an_option, Apply, DryRun, Validate};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct ServiceBlockExpectedState {
     name: String,
+    #[schemars(extend("anyOf" = [{ "enum": ["started", "stopped"] }, { "pattern": "\\{[{%]" }]))]
     #[serde(skip_serializing_if = "Option::is_none")]
     state: Option<String>, // Either state...
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -26,20 +30,20 @@ impl DryRun for ServiceBlockExpectedState {
     ) -> Result<StepChange, Error> {
         // Prechecks
 
-        if !hosthandler.is_this_cmd_available("systemctl").unwrap() {
-            return Err(Error::FailedDryRunEvaluation(
+        if !hosthandler.is_this_cmd_available("systemctl")? {
+            return Err(Error::Module(ModuleError::Unavailable(
                 "SYSTEMCTL not available on this host".to_string(),
-            ));
+            )));
         }
 
         let service_is_running = match service_is_active(hosthandler, &self.name) {
             Ok(running_state) => running_state,
-            Err(e) => return Err(Error::FailedDryRunEvaluation(e)),
+            Err(e) => return Err(Error::Module(ModuleError::CheckFailed(e))),
         };
 
         let service_is_enabled = match service_is_enabled(hosthandler, &self.name) {
             Ok(enabled_state) => enabled_state,
-            Err(e) => return Err(Error::FailedDryRunEvaluation(e)),
+            Err(e) => return Err(Error::Module(ModuleError::CheckFailed(e))),
         };
 
         // Changes assessment
@@ -50,9 +54,9 @@ impl DryRun for ServiceBlockExpectedState {
         // - mutually exclusive
         if let (None, None) = (&self.state, &self.enabled) {
             // PROBLEM : both 'state' and 'enabled' are empty
-            return Err(Error::FailedDryRunEvaluation(
+            return Err(Error::Module(ModuleError::InvalidArguments(
                 "STATE and ENABLED fields are both empty in provided Task List".to_string(),
-            ));
+            )));
         } else {
             match &self.state {
                 Some(state_content) => {
@@ -142,6 +146,34 @@ impl DryRun for ServiceBlockExpectedState {
     }
 }
 
+impl Validate for ServiceBlockExpectedState {
+    fn validate_block(&self) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+
+        if self.name.is_empty() {
+            problems.push("name can't be empty".into());
+        }
+        if let Some(problem) = check_not_an_option("name", &self.name) {
+            problems.push(problem);
+        }
+        match &self.state {
+            Some(state) => {
+                if let Some(problem) = check_allowed_value("state", state, &["started", "stopped"])
+                {
+                    problems.push(problem);
+                }
+            }
+            None => {
+                if self.enabled.is_none() {
+                    problems.push("Either state or enabled is required".into());
+                }
+            }
+        }
+
+        problems
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ServiceApiCall {
     name: String,
@@ -173,89 +205,89 @@ impl Apply for ServiceApiCall {
     fn apply_moduleblock_change(&self, hosthandler: &mut HostHandler) -> ApiCallResult {
         match self.action.as_str() {
             "start" => {
-                let cmd_result = hosthandler
-                    .run_cmd(
-                        format!("systemctl start {}", self.name).as_str(),
-                        self.privilege.clone(),
-                    )
-                    .unwrap();
+                let cmd_result = match hosthandler.run(
+                    &Argv::new("systemctl").args(["start", "--"]).arg(&self.name),
+                    self.privilege.clone(),
+                ) {
+                    Ok(cmd_result) => cmd_result,
+                    Err(error) => return ApiCallResult::from_error(&error),
+                };
 
                 if cmd_result.rc == 0 {
-                    ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::ChangeSuccessful(format!("{} started", self.name.clone())),
                     )
                 } else {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::Failure(String::from("Failed to start service")),
                     );
                 }
             }
             "stop" => {
-                let cmd_result = hosthandler
-                    .run_cmd(
-                        format!("systemctl stop {}", self.name).as_str(),
-                        self.privilege.clone(),
-                    )
-                    .unwrap();
+                let cmd_result = match hosthandler.run(
+                    &Argv::new("systemctl").args(["stop", "--"]).arg(&self.name),
+                    self.privilege.clone(),
+                ) {
+                    Ok(cmd_result) => cmd_result,
+                    Err(error) => return ApiCallResult::from_error(&error),
+                };
 
                 if cmd_result.rc == 0 {
-                    ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::ChangeSuccessful(format!("{} stopped", self.name.clone())),
                     )
                 } else {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::Failure(String::from("Failed to stop service")),
                     );
                 }
             }
             "enable" => {
-                let cmd_result = hosthandler
-                    .run_cmd(
-                        format!("systemctl enable {}", self.name).as_str(),
-                        self.privilege.clone(),
-                    )
-                    .unwrap();
+                let cmd_result = match hosthandler.run(
+                    &Argv::new("systemctl")
+                        .args(["enable", "--"])
+                        .arg(&self.name),
+                    self.privilege.clone(),
+                ) {
+                    Ok(cmd_result) => cmd_result,
+                    Err(error) => return ApiCallResult::from_error(&error),
+                };
 
                 if cmd_result.rc == 0 {
-                    ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::ChangeSuccessful(format!("{} enabled", self.name.clone())),
                     )
                 } else {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::Failure(String::from("Failed to enable service")),
                     );
                 }
             }
             "disable" => {
-                let cmd_result = hosthandler
-                    .run_cmd(
-                        format!("systemctl disable {}", self.name).as_str(),
-                        self.privilege.clone(),
-                    )
-                    .unwrap();
+                let cmd_result = match hosthandler.run(
+                    &Argv::new("systemctl")
+                        .args(["disable", "--"])
+                        .arg(&self.name),
+                    self.privilege.clone(),
+                ) {
+                    Ok(cmd_result) => cmd_result,
+                    Err(error) => return ApiCallResult::from_error(&error),
+                };
 
                 if cmd_result.rc == 0 {
-                    ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::ChangeSuccessful(format!("{} disabled", self.name.clone())),
                     )
                 } else {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::Failure(String::from("Failed to disable service")),
                     );
                 }
@@ -276,8 +308,8 @@ impl ServiceApiCall {
 }
 
 fn service_is_active(hosthandler: &mut HostHandler, name: &String) -> Result<bool, String> {
-    match hosthandler.run_cmd(
-        format!("systemctl is-active {}", name).as_str(),
+    match hosthandler.run(
+        &Argv::new("systemctl").args(["is-active", "--"]).arg(name),
         Privilege::Usual,
     ) {
         Ok(test_result) => {
@@ -292,8 +324,8 @@ fn service_is_active(hosthandler: &mut HostHandler, name: &String) -> Result<boo
 }
 
 fn service_is_enabled(hosthandler: &mut HostHandler, name: &String) -> Result<bool, String> {
-    match hosthandler.run_cmd(
-        format!("systemctl is-enabled {}", name).as_str(),
+    match hosthandler.run(
+        &Argv::new("systemctl").args(["is-enabled", "--"]).arg(name),
         Privilege::Usual,
     ) {
         Ok(test_result) => {
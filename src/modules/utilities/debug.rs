@@ -3,10 +3,11 @@ use crate::connection::specification::Privilege;
 use crate::error::Error;
 use crate::result::apicallresult::ApiCallResult;
 use crate::step::stepchange::StepChange;
-use crate::task::moduleblock::{Apply, DryRun};
+use crate::task::moduleblock::{Apply, DryRun, Validate};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct DebugBlockExpectedState {
     msg: String,
     // var: Option<String>, // TODO
@@ -22,6 +23,12 @@ impl DryRun for DebugBlockExpectedState {
     }
 }
 
+impl Validate for DebugBlockExpectedState {
+    fn validate_block(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DebugApiCall {}
 
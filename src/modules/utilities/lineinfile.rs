@@ -1,21 +1,30 @@
 // LineInFile module : manipulate lines in a file (add, delete)
 
+use crate::connection::argv::Argv;
 use crate::connection::hosthandler::HostHandler;
 use crate::connection::specification::Privilege;
+use crate::error::module::ModuleError;
 use crate::error::Error;
 use crate::result::apicallresult::{ApiCallResult, ApiCallStatus};
 use crate::step::stepchange::StepChange;
 use crate::task::moduleblock::ModuleApiCall;
-use crate::task::moduleblock::{Apply, DryRun};
+use crate::task::moduleblock::{check_allowed_value, is_templated, Apply, DryRun, Validate};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct LineInFileBlockExpectedState {
     filepath: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     line: Option<String>,
+    #[schemars(extend("anyOf" = [{ "enum": ["present", "absent"] }, { "pattern": "\\{[{%]" }]))]
     #[serde(skip_serializing_if = "Option::is_none")]
     state: Option<String>,
+    #[schemars(extend("anyOf" = [
+        { "enum": ["top", "bottom", "anywhere"] },
+        { "pattern": "^[1-9][0-9]*$" },
+        { "pattern": "\\{[{%]" }
+    ]))]
     #[serde(skip_serializing_if = "Option::is_none")]
     position: Option<String>, // "top" | "bottom" | "anywhere" (default) | "45" (specific line number)
 
@@ -32,43 +41,58 @@ impl DryRun for LineInFileBlockExpectedState {
         hosthandler: &mut HostHandler,
         privilege: Privilege,
     ) -> Result<StepChange, Error> {
-        if !hosthandler.is_this_cmd_available("sed").unwrap() {
-            return Err(Error::FailedDryRunEvaluation(
+        if !hosthandler.is_this_cmd_available("sed")? {
+            return Err(Error::Module(ModuleError::Unavailable(
                 "Sed command not available on this host".to_string(),
-            ));
+            )));
         }
 
-        let file_exists_check = hosthandler
-            .run_cmd(
-                format!("test -f {}", self.filepath).as_str(),
-                privilege.clone(),
-            )
-            .unwrap();
+        let file_exists_check = hosthandler.run(
+            &Argv::new("test").arg("-f").arg(&self.filepath),
+            privilege.clone(),
+        )?;
 
         if file_exists_check.rc != 0 {
-            return Err(Error::FailedDryRunEvaluation(format!(
+            return Err(Error::Module(ModuleError::CheckFailed(format!(
                 "{} not found or not a regular file",
                 self.filepath
-            )));
+            ))));
         }
 
         let mut changes: Vec<ModuleApiCall> = Vec::new();
 
         match &self.state {
             Some(state) => {
+                let line = match &self.line {
+                    Some(line) => line,
+                    None => {
+                        return Err(Error::Module(ModuleError::InvalidArguments(
+                            "line is required when state is defined".to_string(),
+                        )));
+                    }
+                };
                 let change = match state.as_str() {
                     "present" => {
                         let mut bottom = false;
-                        let filenumberoflines = hosthandler
-                            .run_cmd(
-                                format!("cat {} | wc -l", self.filepath).as_str(),
-                                privilege.clone(),
-                            )
-                            .unwrap()
+                        // Output looks like 12 /path/to/file
+                        let line_count_check = hosthandler.run(
+                            &Argv::new("wc").args(["-l", "--"]).arg(&self.filepath),
+                            privilege.clone(),
+                        )?;
+                        let line_count = line_count_check
                             .stdout
-                            .trim()
-                            .parse::<u32>()
-                            .unwrap();
+                            .split_whitespace()
+                            .next()
+                            .unwrap_or_default();
+                        let filenumberoflines = match line_count.parse::<u32>() {
+                            Ok(filenumberoflines) => filenumberoflines,
+                            Err(e) => {
+                                return Err(Error::Module(ModuleError::CheckFailed(format!(
+                                    "Unable to count lines of {} : {}",
+                                    self.filepath, e
+                                ))));
+                            }
+                        };
 
                         // Parse the position attribute (where the line is expected to be)
                         let expected_position: Option<u32> = match &self.position {
@@ -87,17 +111,17 @@ impl DryRun for LineInFileBlockExpectedState {
                                                 if linenumber <= filenumberoflines {
                                                     Some(linenumber)
                                                 } else {
-                                                    return Err(Error::FailedDryRunEvaluation(
+                                                    return Err(Error::Module(ModuleError::InvalidArguments(
                                                         "Position value out of range (use \"bottom\" instead)".to_string()
-                                                    ));
+                                                    )));
                                                 }
                                             }
                                             Err(e) => {
-                                                return Err(Error::FailedDryRunEvaluation(
-                                                    format!(
+                                                return Err(Error::Module(
+                                                    ModuleError::InvalidArguments(format!(
                                                         "Failed to parse position value : {}",
                                                         e
-                                                    ),
+                                                    )),
                                                 ));
                                             }
                                         }
@@ -107,12 +131,8 @@ impl DryRun for LineInFileBlockExpectedState {
                             None => None, // Default = "anywhere" = bottom if we need to create the line
                         };
 
-                        let file_actual_state = is_line_present(
-                            hosthandler,
-                            &self.line.as_ref().unwrap(),
-                            &self.filepath,
-                            &privilege,
-                        );
+                        let file_actual_state =
+                            is_line_present(hosthandler, line, &self.filepath, &privilege)?;
 
                         match file_actual_state {
                             Some(actual_line_numbers) => {
@@ -129,7 +149,7 @@ impl DryRun for LineInFileBlockExpectedState {
                                             if bottom {
                                                 ModuleApiCall::LineInFile(LineInFileApiCall {
                                                     action: "add".to_string(),
-                                                    line: self.line.as_ref().unwrap().clone(),
+                                                    line: line.clone(),
                                                     line_numbers: None,
                                                     position: None,
                                                     path: self.filepath.clone(),
@@ -138,7 +158,7 @@ impl DryRun for LineInFileBlockExpectedState {
                                             } else {
                                                 ModuleApiCall::LineInFile(LineInFileApiCall {
                                                     action: "add".to_string(),
-                                                    line: self.line.as_ref().unwrap().clone(),
+                                                    line: line.clone(),
                                                     line_numbers: None,
                                                     position: expected_position,
                                                     path: self.filepath.clone(),
@@ -161,7 +181,7 @@ impl DryRun for LineInFileBlockExpectedState {
                                 if bottom {
                                     ModuleApiCall::LineInFile(LineInFileApiCall {
                                         action: "add".to_string(),
-                                        line: self.line.as_ref().unwrap().clone(),
+                                        line: line.clone(),
                                         line_numbers: None,
                                         position: None,
                                         path: self.filepath.clone(),
@@ -170,7 +190,7 @@ impl DryRun for LineInFileBlockExpectedState {
                                 } else {
                                     ModuleApiCall::LineInFile(LineInFileApiCall {
                                         action: "add".to_string(),
-                                        line: self.line.as_ref().unwrap().clone(),
+                                        line: line.clone(),
                                         line_numbers: None,
                                         position: expected_position,
                                         path: self.filepath.clone(),
@@ -182,15 +202,10 @@ impl DryRun for LineInFileBlockExpectedState {
                     }
                     "absent" => {
                         // Check if line is already present
-                        match is_line_present(
-                            hosthandler,
-                            self.line.as_ref().unwrap(),
-                            &self.filepath,
-                            &privilege,
-                        ) {
+                        match is_line_present(hosthandler, line, &self.filepath, &privilege)? {
                             Some(line_numbers) => ModuleApiCall::LineInFile(LineInFileApiCall {
                                 action: "del".to_string(),
-                                line: self.line.as_ref().unwrap().clone(),
+                                line: line.clone(),
                                 line_numbers: Some(line_numbers),
                                 position: None,
                                 path: self.filepath.clone(),
@@ -213,6 +228,51 @@ impl DryRun for LineInFileBlockExpectedState {
     }
 }
 
+impl Validate for LineInFileBlockExpectedState {
+    fn validate_block(&self) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+
+        if self.filepath.is_empty() {
+            problems.push("filepath can't be empty".into());
+        }
+        match &self.state {
+            Some(state) => {
+                if let Some(problem) = check_allowed_value("state", state, &["present", "absent"]) {
+                    problems.push(problem);
+                }
+            }
+            None => {
+                problems.push("state is required".into());
+            }
+        }
+        if self.line.is_none() {
+            problems.push("line is required".into());
+        }
+        if let Some(position) = &self.position {
+            if !is_templated(position) {
+                match position.as_str() {
+                    "top" | "bottom" | "anywhere" => {}
+                    _ => match position.parse::<u32>() {
+                        Ok(linenumber) => {
+                            if linenumber == 0 {
+                                problems.push("position : line numbers start at 1".into());
+                            }
+                        }
+                        Err(_) => {
+                            problems.push(format!(
+                                "position : '{}' is not a valid value (expected \"top\", \"bottom\", \"anywhere\" or a line number)",
+                                position
+                            ));
+                        }
+                    },
+                }
+            }
+        }
+
+        problems
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct LineInFileApiCall {
     path: String,
@@ -232,7 +292,7 @@ impl Apply for LineInFileApiCall {
             "del" => {
                 return format!(
                     "Line present {:?} -> needs to be removed",
-                    self.line_numbers.as_ref().unwrap()
+                    self.line_numbers.clone().unwrap_or_default()
                 );
             }
             _ => {
@@ -246,26 +306,36 @@ impl Apply for LineInFileApiCall {
             "add" => {
                 // let mut cmd = String::new();
 
-                let cmd: String = match self.position {
+                // Both commands write what they get on stdin, the line
+                let append_cmd = Argv::new("tee")
+                    .args(["-a", "--"])
+                    .arg(&self.path)
+                    .discard_stdout();
+                let cmd = match self.position {
                     Some(linenumber) => {
                         // If the file is empty, the sed command won't work.
-                        let filesizecheck_cmd = format!("test -s {}", self.path);
-                        let filesizecheck = hosthandler
-                            .run_cmd(filesizecheck_cmd.as_str(), self.privilege.clone())
-                            .unwrap();
+                        let filesizecheck_cmd = Argv::new("test").arg("-s").arg(&self.path);
+                        let filesizecheck =
+                            match hosthandler.run(&filesizecheck_cmd, self.privilege.clone()) {
+                                Ok(filesizecheck) => filesizecheck,
+                                Err(error) => return ApiCallResult::from_error(&error),
+                            };
                         if filesizecheck.rc == 0 {
-                            // File not empty
-                            format!("sed -i \'{} i {}\' {}", linenumber, self.line, self.path)
+                            // File not empty : the output of 'cat' (the line given on stdin) is inserted before this line number
+                            Argv::new("sed")
+                                .arg("-i")
+                                .arg(format!("{}e cat", linenumber))
+                                .arg("--")
+                                .arg(&self.path)
                         } else {
                             // File empty
                             if linenumber == 1 {
                                 // Position = "top"
-                                format!("echo \'{}\' >> {}", self.line, self.path)
+                                append_cmd
                             } else {
                                 // Position = <any other value> which is out of range anyway
-                                return ApiCallResult::from(
-                                    Some(filesizecheck.rc),
-                                    Some(filesizecheck.stdout),
+                                return ApiCallResult::from_cmd(
+                                    filesizecheck,
                                     ApiCallStatus::Failure(String::from(
                                         "Position value out of range (use \"bottom\" instead)",
                                     )),
@@ -275,24 +345,28 @@ impl Apply for LineInFileApiCall {
                     }
                     None => {
                         // If no line number is specified, the default behavior is to add the line at the bottom of the file
-                        format!("echo \'{}\' >> {}", self.line, self.path)
+                        append_cmd
                     }
                 };
 
-                let cmd_result = hosthandler
-                    .run_cmd(cmd.as_str(), self.privilege.clone())
-                    .unwrap();
+                // The line is given on stdin : quotes, backslashes, $... are written as they are
+                let cmd_result = match hosthandler.run_with_stdin(
+                    &cmd,
+                    format!("{}\n", self.line).as_bytes(),
+                    self.privilege.clone(),
+                ) {
+                    Ok(cmd_result) => cmd_result,
+                    Err(error) => return ApiCallResult::from_error(&error),
+                };
 
                 if cmd_result.rc == 0 {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::ChangeSuccessful(String::from("Line added")),
                     );
                 } else {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::Failure(String::from("Failed to add line")),
                     );
                 }
@@ -303,32 +377,42 @@ impl Apply for LineInFileApiCall {
                 let formatted_line_numbers = self
                     .line_numbers
                     .clone()
-                    .unwrap()
+                    .unwrap_or_default()
                     .into_iter()
                     .map(|i| format!("{}d;", i))
                     .collect::<String>();
+                if formatted_line_numbers.is_empty() {
+                    return ApiCallResult::from(
+                        None,
+                        None,
+                        ApiCallStatus::Failure(String::from("No line number to remove")),
+                    );
+                }
                 let formatted_line_numbers = formatted_line_numbers
                     .split_at(formatted_line_numbers.len() - 1)
                     .0; // Delete the last ';
 
-                let cmd = format!("sed -i \'{}\' {}", formatted_line_numbers, self.path);
-                let cmd_result = hosthandler
-                    .run_cmd(cmd.as_str(), self.privilege.clone())
-                    .unwrap();
+                let cmd = Argv::new("sed")
+                    .arg("-i")
+                    .arg(formatted_line_numbers)
+                    .arg("--")
+                    .arg(&self.path);
+                let cmd_result = match hosthandler.run(&cmd, self.privilege.clone()) {
+                    Ok(cmd_result) => cmd_result,
+                    Err(error) => return ApiCallResult::from_error(&error),
+                };
 
                 if cmd_result.rc == 0 {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::ChangeSuccessful(format!(
                             "Line {:?} removed",
-                            self.line_numbers.as_ref().unwrap()
+                            self.line_numbers.clone().unwrap_or_default()
                         )),
                     );
                 } else {
-                    return ApiCallResult::from(
-                        Some(cmd_result.rc),
-                        Some(cmd_result.stdout),
+                    return ApiCallResult::from_cmd(
+                        cmd_result,
                         ApiCallStatus::Failure(String::from("Failed to remove line")),
                     );
                 }
@@ -346,21 +430,32 @@ fn is_line_present(
     line: &String,
     filepath: &String,
     privilege: &Privilege,
-) -> Option<Vec<u32>> {
-    let test = hosthandler
-        .run_cmd(
-            format!("grep -n -F -w \'{}\' {}", line, filepath).as_str(), //  Output looks like 4:my line content
-            privilege.clone(),
-        )
-        .unwrap();
+) -> Result<Option<Vec<u32>>, Error> {
+    // The line is the pattern, given on stdin. Output looks like 4:my line content
+    let test = hosthandler.run_with_stdin(
+        &Argv::new("grep")
+            .args(["-n", "-F", "-w", "-f", "-", "--"])
+            .arg(filepath),
+        format!("{}\n", line).as_bytes(),
+        privilege.clone(),
+    )?;
 
     if test.rc == 0 {
         let mut line_numbers: Vec<u32> = Vec::new();
         for line in test.stdout.lines() {
-            line_numbers.push(line.split(':').next().unwrap().parse::<u32>().unwrap());
+            let line_number = line.split(':').next().unwrap_or_default();
+            match line_number.parse::<u32>() {
+                Ok(line_number) => line_numbers.push(line_number),
+                Err(e) => {
+                    return Err(Error::Module(ModuleError::CheckFailed(format!(
+                        "Unexpected grep output '{}' : {}",
+                        line, e
+                    ))));
+                }
+            }
         }
-        return Some(line_numbers);
+        return Ok(Some(line_numbers));
     } else {
-        return None;
+        return Ok(None);
     }
 }
@@ -2,13 +2,15 @@
 
 use crate::connection::hosthandler::HostHandler;
 use crate::connection::specification::Privilege;
+use crate::error::module::ModuleError;
 use crate::error::Error;
 use crate::result::apicallresult::ApiCallResult;
 use crate::step::stepchange::StepChange;
-use crate::task::moduleblock::{Apply, DryRun};
+use crate::task::moduleblock::{Apply, DryRun, Validate};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 pub struct PingBlockExpectedState {}
 
 impl DryRun for PingBlockExpectedState {
@@ -23,13 +25,19 @@ impl DryRun for PingBlockExpectedState {
         if cmd_result.rc == 0 {
             return Ok(StepChange::AlreadyMatched("Host reachable".to_string()));
         } else {
-            return Err(Error::FailedDryRunEvaluation(
+            return Err(Error::Module(ModuleError::CheckFailed(
                 "Host unreachable".to_string(),
-            ));
+            )));
         }
     }
 }
 
+impl Validate for PingBlockExpectedState {
+    fn validate_block(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PingApiCall {
     privilege: Privilege,
@@ -37,7 +45,7 @@ pub struct PingApiCall {
 
 impl Apply for PingApiCall {
     fn display(&self) -> String {
-        return format!("Check SSH connectivity with remote host");
+        return "Check SSH connectivity with remote host".to_string();
     }
 
     fn apply_moduleblock_change(&self, _hosthandler: &mut HostHandler) -> ApiCallResult {
@@ -1,3 +1,4 @@
+use crate::connection::specification::ConnectionReport;
 use crate::job::job::Job;
 use crate::task::moduleblock::ModuleBlockExpectedState;
 use crate::workflow::stepflow::StepFlow;
@@ -12,6 +13,8 @@ pub struct JobOutput {
     timestamp_start: String,
     timestamp_end: String,
     final_status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    connection: Option<ConnectionReport>,
     tasks: Vec<TaskOutput>,
 }
 
@@ -22,6 +25,7 @@ impl JobOutput {
             timestamp_start: String::new(),
             timestamp_end: String::new(),
             final_status: String::new(),
+            connection: None,
             tasks: Vec::new(),
         }
     }
@@ -33,12 +37,16 @@ impl JobOutput {
         job_output.timestamp_start = job.timestamp_start.as_ref().unwrap_or(&"".into()).to_string();
         job_output.timestamp_end = job.timestamp_end.as_ref().unwrap_or(&"".into()).to_string();
         job_output.final_status = format!("{:?}", job.final_status);
+        job_output.connection = job
+            .connection_report
+            .clone()
+            .filter(|connection_report| !connection_report.is_empty());
 
         let mut tasks_output: Vec<TaskOutput> = Vec::new();
 
-        if job.hostworkflow.is_some() {
-            for task_flow in job.hostworkflow.as_ref().unwrap().clone().task_flows {
-                tasks_output.push(TaskOutput::from_taskflow(&task_flow, &job.vars));
+        if let Some(host_work_flow) = &job.hostworkflow {
+            for task_flow in host_work_flow.task_flows.iter() {
+                tasks_output.push(TaskOutput::from_taskflow(task_flow, &job.vars));
             }
         }
         job_output.tasks = tasks_output;
@@ -61,7 +69,7 @@ impl TaskOutput {
         }
 
         TaskOutput {
-            name: task_flow.name.as_ref().unwrap().to_string(),
+            name: task_flow.name.clone().unwrap_or_default(),
             steps: steps_output,
         }
     }
@@ -81,9 +89,15 @@ impl StepOutput {
         let raw_output = match step_flow.step_status {
             StepStatus::ApplyFailed => {
                 let mut api_call_results_output = String::new();
-                for api_call_result in step_flow.step_result.clone().unwrap().apicallresults {
-                    api_call_results_output
-                        .push_str(format!("{}\n", api_call_result.output.unwrap()).as_str());
+                if let Some(step_result) = &step_flow.step_result {
+                    for api_call_result in step_result.apicallresults.iter() {
+                        if let Some(output) = &api_call_result.output {
+                            api_call_results_output.push_str(format!("{}\n", output).as_str());
+                        }
+                        if let Some(stderr) = &api_call_result.stderr {
+                            api_call_results_output.push_str(format!("{}\n", stderr).as_str());
+                        }
+                    }
                 }
                 Some(api_call_results_output)
             }
@@ -91,13 +105,14 @@ impl StepOutput {
         };
 
         StepOutput {
-            name: step_flow.step_expected.name.as_ref().unwrap().to_string(),
+            name: step_flow.step_expected.name.clone().unwrap_or_default(),
+            // Shown as written if variables can't be resolved
             expected_state: step_flow
                 .step_expected
                 .moduleblock
                 .clone()
                 .consider_vars(vars)
-                .unwrap(),
+                .unwrap_or_else(|_| step_flow.step_expected.moduleblock.clone()),
             status: format!("{:?}", step_flow.step_status),
             raw_output,
         }
@@ -2,3 +2,4 @@
 
 pub mod job_output;
 pub mod joblist_output;
+pub mod streaming;
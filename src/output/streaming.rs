@@ -0,0 +1,114 @@
+//! Live output of commands, delivered line by line while a step is being applied
+
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputLine {
+    pub host: String,
+    /// Name of the step, or its position for an unnamed one (ex: "task 1, step 2")
+    pub step: String,
+    pub stream: OutputStream,
+    /// Without the line ending
+    pub line: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Called with each line of output, as soon as it is received
+pub type OnLine<'a> = dyn Fn(OutputStream, &str) + 'a;
+
+/// Where live output lines are delivered. Lines are sent from the threads running the Jobs.
+#[derive(Clone)]
+pub struct OutputSink(Arc<dyn Fn(OutputLine) + Send + Sync>);
+
+impl OutputSink {
+    pub fn from_callback<F>(callback: F) -> OutputSink
+    where
+        F: Fn(OutputLine) + Send + Sync + 'static,
+    {
+        OutputSink(Arc::new(callback))
+    }
+
+    /// Lines are dropped once the receiver is gone
+    pub fn from_sender(sender: Sender<OutputLine>) -> OutputSink {
+        OutputSink(Arc::new(move |line| {
+            let _ = sender.send(line);
+        }))
+    }
+
+    pub fn send(&self, line: OutputLine) {
+        (self.0)(line)
+    }
+}
+
+impl std::fmt::Debug for OutputSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "OutputSink")
+    }
+}
+
+/// Rebuilds lines from the chunks read on a stream. Invalid UTF-8 is replaced.
+pub struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    pub fn new() -> LineBuffer {
+        LineBuffer {
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, chunk: &[u8], on_line: &mut dyn FnMut(&str)) {
+        self.pending.extend_from_slice(chunk);
+        while let Some(position) = self.pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=position).collect();
+            on_line(String::from_utf8_lossy(&line[..line.len() - 1]).trim_end_matches('\r'));
+        }
+    }
+
+    /// The last line may not end with a newline
+    pub fn flush(&mut self, on_line: &mut dyn FnMut(&str)) {
+        if !self.pending.is_empty() {
+            on_line(String::from_utf8_lossy(&self.pending).as_ref());
+            self.pending.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_rebuilt_across_chunks() {
+        let mut lines: Vec<String> = Vec::new();
+        let mut line_buffer = LineBuffer::new();
+
+        for chunk in [
+            "Readi",
+            "ng package lists...\r\nBuilding",
+            " dependency tree\n\nDo",
+            "ne",
+        ] {
+            line_buffer.push(chunk.as_bytes(), &mut |line| lines.push(line.to_string()));
+        }
+        line_buffer.flush(&mut |line| lines.push(line.to_string()));
+
+        assert_eq!(
+            lines,
+            vec![
+                "Reading package lists...",
+                "Building dependency tree",
+                "",
+                "Done"
+            ]
+        );
+    }
+}
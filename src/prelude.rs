@@ -1,6 +1,9 @@
 //! Rapidly get started by importing all main items
 
+pub use crate::connection::connectionmode::ssh2mode::SshBackend;
+pub use crate::connection::escalation::BecomeMethod;
 pub use crate::connection::host_connection::HostConnectionInfo;
+pub use crate::connection::pool::ConnectionPool;
 pub use crate::connection::specification::REFRESH_INTERVAL_MILLI_SECONDS;
 pub use crate::exitcode::*;
 pub use crate::host::hostlist::hostlist_get_all_hosts;
@@ -10,6 +13,9 @@ pub use crate::host::hosts::Host;
 pub use crate::host::parser::hostlist_parser;
 pub use crate::job::job::Job;
 pub use crate::job::joblist::JobList;
+pub use crate::output::streaming::{OutputLine, OutputSink, OutputStream};
+pub use crate::schema::{hostlist_json_schema, tasklist_json_schema};
 pub use crate::task::tasklist::RunningMode;
 pub use crate::task::tasklist::TaskList;
 pub use crate::task::tasklist::TaskListFileType;
+pub use crate::task::validation::{Diagnostic, DiagnosticLevel};
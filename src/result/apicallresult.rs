@@ -1,9 +1,13 @@
+use crate::error::Error;
+use crate::result::cmd::CmdResult;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiCallResult {
     pub rc: Option<i32>,
     pub output: Option<String>,
+    #[serde(default)]
+    pub stderr: Option<String>,
     pub status: ApiCallStatus,
 }
 
@@ -12,6 +16,7 @@ impl ApiCallResult {
         ApiCallResult {
             rc: None,
             output: None,
+            stderr: None,
             status: ApiCallStatus::Unset,
         }
     }
@@ -20,12 +25,38 @@ impl ApiCallResult {
         ApiCallResult {
             rc: None,
             output: None,
+            stderr: None,
             status: ApiCallStatus::None,
         }
     }
 
     pub fn from(rc: Option<i32>, output: Option<String>, status: ApiCallStatus) -> ApiCallResult {
-        ApiCallResult { rc, output, status }
+        ApiCallResult {
+            rc,
+            output,
+            stderr: None,
+            status,
+        }
+    }
+
+    /// Result of a command : its stdout is the output, its stderr is kept aside
+    pub fn from_cmd(cmd_result: CmdResult, status: ApiCallStatus) -> ApiCallResult {
+        ApiCallResult {
+            rc: Some(cmd_result.rc),
+            output: Some(cmd_result.stdout),
+            stderr: Some(cmd_result.stderr).filter(|stderr| !stderr.is_empty()),
+            status,
+        }
+    }
+
+    /// When the API call couldn't even be carried out (connection lost, unreadable output...)
+    pub fn from_error(error: &Error) -> ApiCallResult {
+        ApiCallResult {
+            rc: None,
+            output: Some(error.to_string()),
+            stderr: None,
+            status: ApiCallStatus::Failure(error.to_string()),
+        }
     }
 }
 
@@ -2,6 +2,7 @@
 pub struct CmdResult {
     pub rc: i32,
     pub stdout: String,
+    pub stderr: String,
 }
 
 impl CmdResult {
@@ -9,6 +10,7 @@ impl CmdResult {
         CmdResult {
             rc: 0,
             stdout: String::new(),
+            stderr: String::new(),
         }
     }
 }
@@ -0,0 +1,86 @@
+//! JSON Schemas of the TaskList and HostList formats, to validate files or get autocompletion in editors
+
+use crate::host::parser::HostListVarsUnparsed;
+use crate::task::contentformat::toml::TomlTaskList;
+use crate::task::taskblock::ParsingTaskBlock;
+use schemars::{schema_for, JsonSchema};
+
+// Only describes the documents : a YAML or JSON TaskList is an array of tasks, a TOML one a table of tasks
+#[derive(JsonSchema)]
+#[serde(untagged)]
+#[allow(dead_code)]
+enum TaskListDocument {
+    Tasks(Vec<ParsingTaskBlock>),
+    TomlTasks(TomlTaskList),
+}
+
+/// JSON Schema of a TaskList file (YAML, JSON or TOML), generated from the types used to parse it
+pub fn tasklist_json_schema() -> serde_json::Value {
+    let mut schema = schema_for!(TaskListDocument);
+    schema.insert("title".into(), "Dux TaskList".into());
+    schema.insert("$comment".into(), generated_by().into());
+    schema.to_value()
+}
+
+/// JSON Schema of a HostList file, generated from the types used to parse it
+pub fn hostlist_json_schema() -> serde_json::Value {
+    let mut schema = schema_for!(HostListVarsUnparsed);
+    schema.insert("title".into(), "Dux HostList".into());
+    schema.insert("$comment".into(), generated_by().into());
+    schema.to_value()
+}
+
+// Schemas follow the crate : a file valid for a given schema is valid for the matching duxcore version
+fn generated_by() -> String {
+    format!("Generated by duxcore {}", env!("CARGO_PKG_VERSION"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_module_is_part_of_the_tasklist_schema() {
+        let schema = tasklist_json_schema();
+        let step_properties = schema["$defs"]["ParsingStep"]["properties"]
+            .as_object()
+            .unwrap();
+
+        for module in [
+            "service",
+            "debug",
+            "lineinfile",
+            "command",
+            "apt",
+            "dnf",
+            "ping",
+            "yum",
+        ] {
+            assert!(step_properties.contains_key(module));
+        }
+        assert_eq!(schema["anyOf"][0]["type"], "array");
+    }
+
+    #[test]
+    fn toml_tasks_table_is_part_of_the_tasklist_schema() {
+        let schema = tasklist_json_schema();
+        assert_eq!(schema["anyOf"][1]["$ref"], "#/$defs/TomlTaskList");
+        let toml_tasks = &schema["$defs"]["TomlTaskList"];
+
+        assert_eq!(toml_tasks["required"], serde_json::json!(["tasks"]));
+        assert_eq!(toml_tasks["properties"]["tasks"]["type"], "array");
+    }
+
+    #[test]
+    fn keyword_values_are_enumerated_and_may_be_templated() {
+        let schema = tasklist_json_schema();
+        let apt_state = &schema["$defs"]["AptBlockExpectedState"]["properties"]["state"];
+
+        assert_eq!(
+            apt_state["anyOf"][0]["enum"],
+            serde_json::json!(["present", "absent"])
+        );
+        // Templated values are only checked once rendered
+        assert_eq!(apt_state["anyOf"][1]["pattern"], "\\{[{%]");
+    }
+}
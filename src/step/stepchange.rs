@@ -83,6 +83,7 @@ impl StepChange {
             step_result.apicallresults.push(ApiCallResult {
                 rc: result.rc,
                 output: result.output.clone(),
+                stderr: result.stderr.clone(),
                 status: result.status.clone(),
             })
         }
@@ -5,6 +5,8 @@ use serde::{Deserialize, Serialize};
 pub struct StepResult {
     pub rc: Option<i32>,        // Last RC of last ApiCallResult
     pub output: Option<String>, // Concatenation of all ApiCallResult outputs
+    #[serde(default)]
+    pub stderr: Option<String>, // Concatenation of all ApiCallResult stderr
     pub apicallresults: Vec<ApiCallResult>,
 }
 
@@ -13,6 +15,7 @@ impl StepResult {
         StepResult {
             rc: None,
             output: None,
+            stderr: None,
             apicallresults: Vec::new(),
         }
     }
@@ -21,27 +24,22 @@ impl StepResult {
         StepResult {
             rc: None,
             output: None,
+            stderr: None,
             apicallresults: Vec::from([ApiCallResult::none()]),
         }
     }
 
-    pub fn from(apicallresults: &Vec<ApiCallResult>) -> StepResult {
+    pub fn from(apicallresults: &[ApiCallResult]) -> StepResult {
         let mut final_rc: i32 = 0;
         let mut output_list = String::new();
+        let mut stderr_list = String::new();
 
-        for api_call_result in apicallresults.clone().iter() {
+        for api_call_result in apicallresults.iter() {
             if let Some(api_call_result_output) = &api_call_result.output {
-                output_list.push_str(
-                    format!(
-                        "{}",
-                        api_call_result_output
-                            .replace('\n', "\\n")
-                            .chars()
-                            .filter(|c| !c.is_control())
-                            .collect::<String>()
-                    )
-                    .as_str(),
-                );
+                output_list.push_str(flattened(api_call_result_output).as_str());
+            }
+            if let Some(api_call_result_stderr) = &api_call_result.stderr {
+                stderr_list.push_str(flattened(api_call_result_stderr).as_str());
             }
             match api_call_result.rc {
                 None | Some(0) => {}
@@ -55,7 +53,17 @@ impl StepResult {
         StepResult {
             rc: Some(final_rc),
             output: Some(output_list),
-            apicallresults: apicallresults.clone(),
+            stderr: Some(stderr_list),
+            apicallresults: apicallresults.to_vec(),
         }
     }
 }
+
+// Newlines are kept as '\n' and other control characters are removed
+fn flattened(content: &str) -> String {
+    content
+        .replace('\n', "\\n")
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect::<String>()
+}
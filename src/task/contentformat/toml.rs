@@ -0,0 +1,80 @@
+use crate::error::parse::ParseError;
+use crate::error::Error;
+use crate::task::taskblock::{tasklist_from_parsing_task_blocks, ParsingTaskBlock};
+use crate::task::tasklist::TaskList;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+/// A TOML document can't be an array : tasks are written as an array of tables ([[tasks]])
+#[derive(Deserialize, JsonSchema)]
+pub struct TomlTaskList {
+    pub tasks: Vec<ParsingTaskBlock>,
+}
+
+pub fn toml_tasklist_parser(tasklistcontent: &str) -> Result<TaskList, Error> {
+    match toml::from_str::<TomlTaskList>(tasklistcontent) {
+        Ok(parsed_content) => {
+            tasklist_from_parsing_task_blocks(&parsed_content.tasks, tasklistcontent)
+        }
+        Err(e) => Err(Error::from(ParseError::from_toml_error(
+            &e,
+            tasklistcontent,
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::parse::ParsingError;
+    use crate::task::moduleblock::ModuleBlockExpectedState;
+    use crate::task::tasklist::TaskListFileType;
+
+    #[test]
+    fn toml_tasklist_parsing() {
+        let content = r#"
+[[tasks]]
+name = "Prerequisites"
+
+[[tasks.steps]]
+name = "Check connectivity"
+ping = {}
+
+[[tasks.steps]]
+name = "Install git"
+with_sudo = true
+apt = { package = "git", state = "present" }
+"#;
+        let tasklist = TaskList::from_str(content, TaskListFileType::Unknown).unwrap();
+
+        assert_eq!(tasklist.tasks.len(), 1);
+        assert_eq!(tasklist.tasks[0].steps.len(), 2);
+        assert!(matches!(
+            tasklist.tasks[0].steps[0].moduleblock,
+            ModuleBlockExpectedState::Ping(_)
+        ));
+        assert_eq!(tasklist.tasks[0].steps[1].with_sudo, Some(true));
+        assert_eq!(
+            TaskListFileType::from_path("/etc/dux/tasks.TOML"),
+            TaskListFileType::Toml
+        );
+    }
+
+    #[test]
+    fn toml_errors_are_located() {
+        let content = r#"[[tasks]]
+name = "Prerequisites"
+
+[[tasks.steps]]
+name = "Install git"
+apt = { package = "git", upgrade = "yes" }
+"#;
+        match toml_tasklist_parser(content) {
+            Err(Error::Parsing(ParsingError::Invalid(parse_error))) => {
+                assert_eq!(parse_error.line, Some(6));
+                assert_eq!(parse_error.column, Some(36));
+            }
+            other => panic!("unexpected result : {:?}", other),
+        }
+    }
+}
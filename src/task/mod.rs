@@ -5,3 +5,4 @@ pub mod moduleblock;
 pub mod step;
 pub mod taskblock;
 pub mod tasklist;
+pub mod validation;
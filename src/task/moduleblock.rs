@@ -1,5 +1,7 @@
 use crate::connection::hosthandler::HostHandler;
 use crate::connection::specification::Privilege;
+use crate::error::module::ModuleError;
+use crate::error::workflow::WorkflowError;
 use crate::error::Error;
 use crate::modules::prelude::*;
 use crate::result::apicallresult::ApiCallResult;
@@ -33,12 +35,26 @@ impl ModuleBlockExpectedState {
     ) -> Result<ModuleBlockExpectedState, Error> {
         // TODO : is this the best way to do this ?
 
-        let serialized_self = serde_json::to_string(self).unwrap();
+        let serialized_self = match serde_json::to_string(self) {
+            Ok(serialized_self) => serialized_self,
+            Err(error) => {
+                return Err(Error::Module(ModuleError::InvalidArguments(format!(
+                    "{}",
+                    error
+                ))));
+            }
+        };
         let context_wise_serialized_self =
-            Tera::one_off(serialized_self.as_str(), tera_context, true).unwrap();
+            match Tera::one_off(serialized_self.as_str(), tera_context, true) {
+                Ok(rendered) => rendered,
+                Err(error) => return Err(Error::Workflow(WorkflowError::Template(error))),
+            };
         match serde_json::from_str::<ModuleBlockExpectedState>(&context_wise_serialized_self) {
             Ok(context_wise_moduleblock) => Ok(context_wise_moduleblock),
-            Err(error) => Err(Error::FailureToParseContent(format!("{}", error))),
+            Err(error) => Err(Error::Module(ModuleError::InvalidArguments(format!(
+                "{}",
+                error
+            )))),
         }
     }
 
@@ -47,20 +63,15 @@ impl ModuleBlockExpectedState {
         vars: &Option<serde_json::Value>,
     ) -> Result<ModuleBlockExpectedState, Error> {
         // TODO : is this the best way to do this ?
-        let serialized_self = serde_json::to_string(self).unwrap();
-
-        let temp_tera_context = match vars {
-            Some(var_list) => Context::from_value(var_list.clone()).unwrap(),
+        let mut temp_tera_context = match vars {
+            Some(var_list) => match Context::from_value(var_list.clone()) {
+                Ok(context) => context,
+                Err(error) => return Err(Error::Workflow(WorkflowError::Template(error))),
+            },
             None => Context::new(),
         };
 
-        let context_wise_serialized_self =
-            Tera::one_off(serialized_self.as_str(), &temp_tera_context, true).unwrap();
-
-        match serde_json::from_str::<ModuleBlockExpectedState>(&context_wise_serialized_self) {
-            Ok(context_wise_moduleblock) => Ok(context_wise_moduleblock),
-            Err(error) => Err(Error::FailureToParseContent(format!("{}", error))),
-        }
+        self.consider_context(&mut temp_tera_context)
     }
 
     pub fn dry_run_moduleblock(
@@ -85,6 +96,21 @@ impl ModuleBlockExpectedState {
 
         mbchange_result
     }
+
+    pub fn validate_moduleblock(&self) -> Vec<String> {
+        match &self {
+            ModuleBlockExpectedState::None => Vec::new(),
+            // **BEACON_5**
+            ModuleBlockExpectedState::Service(block) => block.validate_block(),
+            ModuleBlockExpectedState::Debug(block) => block.validate_block(),
+            ModuleBlockExpectedState::LineInFile(block) => block.validate_block(),
+            ModuleBlockExpectedState::Command(block) => block.validate_block(),
+            ModuleBlockExpectedState::Apt(block) => block.validate_block(),
+            ModuleBlockExpectedState::Dnf(block) => block.validate_block(),
+            ModuleBlockExpectedState::Ping(block) => block.validate_block(),
+            ModuleBlockExpectedState::Yum(block) => block.validate_block(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -112,3 +138,38 @@ pub trait Apply {
     fn display(&self) -> String;
     fn apply_moduleblock_change(&self, hosthandler: &mut HostHandler) -> ApiCallResult;
 }
+
+pub trait Validate {
+    /// Checks the content of a block without connecting to any host : required fields, allowed values...
+    /// Each String of the returned list describes one problem.
+    fn validate_block(&self) -> Vec<String>;
+}
+
+/// Values containing Tera expressions can only be checked at runtime, once the context is known
+pub fn is_templated(value: &str) -> bool {
+    value.contains("{{") || value.contains("{%")
+}
+
+/// Returns a problem description if the (non-templated) value is not one of the allowed values
+pub fn check_allowed_value(field: &str, value: &str, allowed_values: &[&str]) -> Option<String> {
+    if is_templated(value) || allowed_values.contains(&value) {
+        None
+    } else {
+        Some(format!(
+            "{} : '{}' is not a valid value (expected one of {:?})",
+            field, value, allowed_values
+        ))
+    }
+}
+
+/// Returns a problem description if the value would be read as an option by the command it is given to
+pub fn check_not_an_option(field: &str, value: &str) -> Option<String> {
+    if value.starts_with('-') {
+        Some(format!(
+            "{} : '{}' starts with '-' and would be read as an option",
+            field, value
+        ))
+    } else {
+        None
+    }
+}
@@ -1,6 +1,9 @@
+use crate::connection::escalation::BecomeMethod;
+use crate::error::parse::ParseError;
 use crate::error::Error;
 use crate::modules::prelude::*;
 use crate::task::moduleblock::ModuleBlockExpectedState;
+use schemars::JsonSchema;
 use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -8,8 +11,11 @@ pub struct Step {
     pub name: Option<String>,
     pub run_as: Option<String>,
     pub with_sudo: Option<bool>,
+    pub become_method: Option<BecomeMethod>, // Overrides the one of the host for this step
     pub allowed_to_fail: Option<bool>,
     pub register: Option<String>,
+    pub run_once: Option<bool>, // Only run this step on the first host reaching it, share the result with others
+    pub delegate_to: Option<String>, // Run this step on another host than the one of the Job
     // pub prelogic -> TODO
     // pub postlogic -> TODO
     pub moduleblock: ModuleBlockExpectedState,
@@ -31,13 +37,16 @@ where
     Deserialize::deserialize(deserializer).map(Some)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ParsingStep {
     pub name: Option<String>,
     pub run_as: Option<String>,
     pub with_sudo: Option<bool>,
+    pub become_method: Option<BecomeMethod>,
     pub allowed_to_fail: Option<bool>,
     pub register: Option<String>,
+    pub run_once: Option<bool>,
+    pub delegate_to: Option<String>,
     // pub prelogic -> TODO
     // pub postlogic -> TODO
 
@@ -96,27 +105,28 @@ impl ParsingStep {
         }
 
         if counter > 1 {
-            return Err(Error::FailedInitialization(
-                "Too much modules defined in this step. Only one module per step please.".into(),
-            ));
+            return Err(Error::from(ParseError::new(
+                "Too much modules defined in this step. Only one module per step please.",
+            )));
         } else {
             match moduleblock {
                 Some(module_block_expected_state) => {
                     return Ok(Step {
                         name: self.name.clone(),
                         run_as: self.run_as.clone(),
-                        with_sudo: self.with_sudo.clone(),
-                        allowed_to_fail: self.allowed_to_fail.clone(),
+                        with_sudo: self.with_sudo,
+                        become_method: self.become_method,
+                        allowed_to_fail: self.allowed_to_fail,
                         register: self.register.clone(),
+                        run_once: self.run_once,
+                        delegate_to: self.delegate_to.clone(),
                         // prelogic -> TODO
                         // postlogic -> TODO
                         moduleblock: module_block_expected_state,
                     });
                 }
                 None => {
-                    return Err(Error::FailedInitialization(
-                        "No module found in this step".into(),
-                    ));
+                    return Err(Error::from(ParseError::new("No module found in this step")));
                 }
             }
         }
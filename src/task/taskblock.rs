@@ -1,5 +1,8 @@
+use crate::error::parse::ParsingError;
 use crate::error::Error;
 use crate::task::step::{ParsingStep, Step};
+use crate::task::tasklist::TaskList;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -27,7 +30,7 @@ impl TaskBlock {
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ParsingTaskBlock {
     pub name: Option<String>,
     pub steps: Vec<ParsingStep>,
@@ -37,11 +40,16 @@ pub struct ParsingTaskBlock {
 impl ParsingTaskBlock {
     pub fn parse_task_block(&self) -> Result<TaskBlock, Error> {
         let mut steps: Vec<Step> = Vec::new();
-        for parsing_step in self.steps.iter() {
+        for (step_index, parsing_step) in self.steps.iter().enumerate() {
             match parsing_step.parsemodule() {
                 Ok(step) => {
                     steps.push(step);
                 }
+                Err(Error::Parsing(ParsingError::Invalid(parse_error))) => {
+                    return Err(Error::from(
+                        (*parse_error).in_step(step_index, &parsing_step.name),
+                    ));
+                }
                 Err(error) => {
                     return Err(error);
                 }
@@ -50,8 +58,34 @@ impl ParsingTaskBlock {
 
         Ok(TaskBlock {
             name: self.name.clone(),
-            steps: steps,
-            with_sudo: self.with_sudo.clone(),
+            steps,
+            with_sudo: self.with_sudo,
         })
     }
 }
+
+/// Turns parsed task blocks into a TaskList. Errors are located in the original content (whatever its format) as precisely as possible.
+pub fn tasklist_from_parsing_task_blocks(
+    parsing_task_blocks: &[ParsingTaskBlock],
+    content: &str,
+) -> Result<TaskList, Error> {
+    let mut tasks: Vec<TaskBlock> = Vec::new();
+    for (task_index, parsing_task_block) in parsing_task_blocks.iter().enumerate() {
+        match parsing_task_block.parse_task_block() {
+            Ok(task_block) => {
+                tasks.push(task_block);
+            }
+            Err(Error::Parsing(ParsingError::Invalid(parse_error))) => {
+                return Err(Error::from(
+                    (*parse_error)
+                        .in_task(task_index, &parsing_task_block.name)
+                        .locate_names(content),
+                ));
+            }
+            Err(error) => {
+                return Err(error);
+            }
+        }
+    }
+    Ok(TaskList::from(tasks))
+}
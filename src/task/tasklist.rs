@@ -1,5 +1,7 @@
+use crate::error::parse::{ParseError, ParsingError};
 use crate::error::Error;
 use crate::task::contentformat::json::json_tasklist_parser;
+use crate::task::contentformat::toml::toml_tasklist_parser;
 use crate::task::contentformat::yaml::yaml_tasklist_parser;
 use crate::task::taskblock::TaskBlock;
 use serde::{Deserialize, Serialize};
@@ -22,8 +24,9 @@ impl TaskList {
         match content_type {
             TaskListFileType::Yaml => yaml_tasklist_parser(raw_content),
             TaskListFileType::Json => json_tasklist_parser(raw_content),
+            TaskListFileType::Toml => toml_tasklist_parser(raw_content),
             TaskListFileType::Unknown => {
-                // Unknown format -> Try YAML -> Try JSON -> Failed
+                // Unknown format -> Try YAML -> Try JSON -> Try TOML -> Failed
                 match yaml_tasklist_parser(raw_content) {
                     Ok(task_list) => {
                         return Ok(task_list);
@@ -32,40 +35,86 @@ impl TaskList {
                         Ok(task_list) => {
                             return Ok(task_list);
                         }
-                        Err(json_try_error) => {
-                            return Err(Error::FailedInitialization(format!(
-                                "Unable to parse file. YAML : {:?}, JSON : {:?}",
-                                yaml_try_error, json_try_error
-                            )));
-                        }
+                        Err(json_try_error) => match toml_tasklist_parser(raw_content) {
+                            Ok(task_list) => {
+                                return Ok(task_list);
+                            }
+                            Err(toml_try_error) => {
+                                return Err(Error::Parsing(ParsingError::UnknownFormat(vec![
+                                    format_attempt("YAML", yaml_try_error),
+                                    format_attempt("JSON", json_try_error),
+                                    format_attempt("TOML", toml_try_error),
+                                ])));
+                            }
+                        },
                     },
                 }
             }
         }
     }
+    /// With TaskListFileType::Unknown, the format is deduced from the file extension (if any)
     pub fn from_file(file_path: &str, file_type: TaskListFileType) -> Result<TaskList, Error> {
+        let file_type = match file_type {
+            TaskListFileType::Unknown => TaskListFileType::from_path(file_path),
+            _ => file_type,
+        };
         match std::fs::read_to_string(file_path) {
-            Ok(file_content) => {
-                return TaskList::from_str(&file_content, file_type);
-            }
+            Ok(file_content) => match TaskList::from_str(&file_content, file_type) {
+                Err(Error::Parsing(parsing_error)) => {
+                    return Err(Error::Parsing(parsing_error.in_file(file_path)));
+                }
+                parsing_result => {
+                    return parsing_result;
+                }
+            },
             Err(error) => {
-                return Err(Error::FailedInitialization(format!(
-                    "{} : {}",
-                    file_path, error
-                )));
+                return Err(Error::Parsing(ParsingError::UnreadableFile {
+                    path: file_path.into(),
+                    source: error,
+                }));
             }
         }
     }
 }
 
+// Keeps how a format failed to parse the content, to explain why no format matched
+fn format_attempt(format: &str, error: Error) -> (String, ParseError) {
+    match error {
+        Error::Parsing(ParsingError::Invalid(parse_error)) => (format.to_string(), *parse_error),
+        other_error => (
+            format.to_string(),
+            ParseError::new(&other_error.to_string()),
+        ),
+    }
+}
+
 #[derive(PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum RunningMode {
     DryRun, // Only check what needs to be done to match the expected situation
     Apply,  // Actually apply the changes required to match the expected situation
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum TaskListFileType {
     Yaml,
     Json,
+    Toml,
     Unknown,
 }
+
+impl TaskListFileType {
+    /// Deduces the format from the file extension ('.yml', '.yaml', '.json' or '.toml')
+    pub fn from_path(file_path: &str) -> TaskListFileType {
+        match std::path::Path::new(file_path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase())
+            .as_deref()
+        {
+            Some("yml") | Some("yaml") => TaskListFileType::Yaml,
+            Some("json") => TaskListFileType::Json,
+            Some("toml") => TaskListFileType::Toml,
+            _ => TaskListFileType::Unknown,
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::task::step::Step;
+use crate::task::tasklist::TaskList;
+use serde::{Deserialize, Serialize};
+use tera::Tera;
+
+/// A problem found in a TaskList without connecting to any host
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub level: DiagnosticLevel,
+    pub task_index: usize,
+    pub task_name: Option<String>,
+    pub step_index: usize,
+    pub step_name: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DiagnosticLevel {
+    /// The step will fail (or panic) at runtime
+    Error,
+    /// The step will run but probably not the way it is expected to
+    Warning,
+}
+
+impl TaskList {
+    /// Checks each step of the TaskList : required fields and allowed values of modules, Tera syntax, consistency of step attributes...
+    /// An empty list means no problem was found.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+        for (task_index, task) in self.tasks.iter().enumerate() {
+            for (step_index, step) in task.steps.iter().enumerate() {
+                for (level, message) in validate_step(step) {
+                    diagnostics.push(Diagnostic {
+                        level,
+                        task_index,
+                        task_name: task.name.clone(),
+                        step_index,
+                        step_name: step.name.clone(),
+                        message,
+                    });
+                }
+            }
+        }
+
+        diagnostics
+    }
+}
+
+fn validate_step(step: &Step) -> Vec<(DiagnosticLevel, String)> {
+    let mut problems: Vec<(DiagnosticLevel, String)> = Vec::new();
+
+    if step.name.is_none() {
+        problems.push((DiagnosticLevel::Warning, "Step has no name".into()));
+    }
+
+    if let (Some(true), Some(username)) = (step.with_sudo, &step.run_as) {
+        problems.push((
+            DiagnosticLevel::Warning,
+            format!(
+                "run_as: {} is ignored because with_sudo is true (step runs as root)",
+                username
+            ),
+        ));
+    }
+
+    if let (Some(become_method), None, None | Some(false)) =
+        (step.become_method, &step.run_as, step.with_sudo)
+    {
+        problems.push((
+            DiagnosticLevel::Warning,
+            format!(
+                "become_method: {} is ignored because the step neither runs with_sudo nor run_as",
+                format!("{:?}", become_method).to_lowercase()
+            ),
+        ));
+    }
+
+    for message in step.moduleblock.validate_moduleblock() {
+        problems.push((DiagnosticLevel::Error, message));
+    }
+
+    // Tera syntax : the module block is rendered as a whole at runtime
+    let serialized_moduleblock = serde_json::to_string(&step.moduleblock).unwrap_or_default();
+    if let Err(error) = Tera::default().add_raw_template("moduleblock", &serialized_moduleblock) {
+        problems.push((
+            DiagnosticLevel::Error,
+            format!("Invalid template : {}", tera_error_details(&error)),
+        ));
+    }
+    if let Some(delegate_to) = &step.delegate_to {
+        if let Err(error) = Tera::default().add_raw_template("delegate_to", delegate_to) {
+            problems.push((
+                DiagnosticLevel::Error,
+                format!(
+                    "Invalid template in delegate_to : {}",
+                    tera_error_details(&error)
+                ),
+            ));
+        }
+    }
+
+    problems
+}
+
+// Tera errors only say "Failed to parse 'template name'", the actual explanation is in the source
+fn tera_error_details(error: &tera::Error) -> String {
+    match std::error::Error::source(error) {
+        Some(source) => format!("{}", source),
+        None => format!("{}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::task::tasklist::TaskListFileType;
+
+    #[test]
+    fn diagnostics_point_at_faulty_steps() {
+        let tasklist = TaskList::from_str(
+            "---
+- name: Web server
+  steps:
+    - name: Install package
+      apt:
+        package: apache2
+        state: instaled
+    - name: Package missing
+      apt:
+        state: present
+    - name: Templated values are not checked
+      apt:
+        package: '{{ package_name }}'
+        state: '{{ package_state }}'
+    - name: Broken template
+      debug:
+        msg: '{{ unclosed'
+",
+            TaskListFileType::Yaml,
+        )
+        .unwrap();
+
+        let diagnostics = tasklist.validate();
+
+        assert_eq!(diagnostics.len(), 3);
+        assert_eq!(diagnostics[0].step_index, 0);
+        assert_eq!(diagnostics[0].task_name, Some("Web server".into()));
+        assert!(diagnostics[0].message.contains("instaled"));
+        assert_eq!(diagnostics[1].step_name, Some("Package missing".into()));
+        assert_eq!(diagnostics[2].step_index, 3);
+        assert!(diagnostics[2].message.starts_with("Invalid template"));
+    }
+
+    #[test]
+    fn names_read_as_options_are_refused() {
+        let tasklist = TaskList::from_str(
+            "---
+- name: Options instead of names
+  steps:
+    - apt:
+        package: '-oDPkg::Pre-Invoke::=touch /tmp/pwned'
+        state: present
+    - dnf:
+        package: --setopt=tsflags=noscripts
+        state: absent
+    - service:
+        name: --all
+        state: started
+",
+            TaskListFileType::Yaml,
+        )
+        .unwrap();
+
+        let problems: Vec<String> = tasklist
+            .validate()
+            .into_iter()
+            .filter(|diagnostic| diagnostic.level == DiagnosticLevel::Error)
+            .map(|diagnostic| diagnostic.message)
+            .collect();
+
+        assert_eq!(problems.len(), 3);
+        assert!(problems
+            .iter()
+            .all(|problem| problem.contains("would be read as an option")));
+    }
+}
@@ -0,0 +1,212 @@
+use crate::connection::escalation::BecomeMethod;
+use crate::connection::host_connection::HostConnectionInfo;
+use crate::connection::hosthandler::HostHandler;
+use crate::connection::pool::{ConnectionPool, PooledHostHandler};
+use crate::error::workflow::WorkflowError;
+use crate::error::Error;
+use crate::job::job::Job;
+use crate::step::stepresult::StepResult;
+use crate::task::tasklist::TaskList;
+use crate::workflow::stepflow::StepFlow;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, RwLock};
+
+/// Position of a step in a TaskList : (task index, step index)
+pub type StepPosition = (usize, usize);
+
+// Filled by the first Job running the step, read by all others
+type RunOnceOutcome = Arc<OnceLock<Result<StepFlow, String>>>;
+
+/// Jobs of a JobList run in parallel and are otherwise isolated from each other. The JobListCoordinator withholds what they share :
+/// - the connection information of each Job, so a step can be delegated to another host of the list (`delegate_to`)
+/// - the outcome of `run_once` steps : the first Job reaching such a step runs it, all others with the same TaskList wait for it and reuse its result
+/// - a read-only view on the variables of every host (`hostvars`) and on the hosts of every group (`groups`), available in the Tera context of each step
+pub struct JobListCoordinator {
+    connections: HashMap<String, HostConnectionInfo>,
+    become_methods: HashMap<String, BecomeMethod>,
+    become_passwords: HashMap<String, String>,
+    connection_pool: Option<ConnectionPool>,
+    tasklists: HashMap<String, String>, // address -> key of its Job's TaskList
+    run_once_outcomes: Mutex<HashMap<(String, StepPosition), RunOnceOutcome>>, // (TaskList key, position) -> outcome
+    hostvars: RwLock<Map<String, Value>>, // address -> { variable name -> value }
+    groups: HashMap<String, Vec<String>>, // group name -> addresses
+    share_registered_vars: bool,
+}
+
+impl JobListCoordinator {
+    pub fn new() -> JobListCoordinator {
+        JobListCoordinator {
+            connections: HashMap::new(),
+            become_methods: HashMap::new(),
+            become_passwords: HashMap::new(),
+            connection_pool: None,
+            tasklists: HashMap::new(),
+            run_once_outcomes: Mutex::new(HashMap::new()),
+            hostvars: RwLock::new(Map::new()),
+            groups: HashMap::new(),
+            share_registered_vars: false,
+        }
+    }
+
+    pub fn from_jobs(jobs: &[Job]) -> JobListCoordinator {
+        let mut coordinator = JobListCoordinator::new();
+
+        let mut hostvars = Map::new();
+        let mut all_hosts: Vec<String> = Vec::new();
+
+        for job in jobs {
+            let address = job.get_address();
+
+            coordinator
+                .connections
+                .insert(address.clone(), job.host_connection_info.clone());
+            if let Some(become_method) = job.become_method {
+                coordinator
+                    .become_methods
+                    .insert(address.clone(), become_method);
+            }
+            if coordinator.connection_pool.is_none() {
+                coordinator.connection_pool = job.connection_pool.clone();
+            }
+            if let Some(password) = &job.become_password {
+                coordinator
+                    .become_passwords
+                    .insert(address.clone(), password.clone());
+            }
+            if let Some(tasklist) = &job.tasklist {
+                coordinator
+                    .tasklists
+                    .insert(address.clone(), tasklist_key(tasklist));
+            }
+
+            let mut vars = Map::new();
+            if let Some(host_vars) = &job.host.vars {
+                for (key, value) in host_vars {
+                    vars.insert(key.clone(), Value::String(value.clone()));
+                }
+            }
+            hostvars.insert(address.clone(), Value::Object(vars));
+
+            if let Some(host_groups) = &job.host.groups {
+                for group in host_groups {
+                    coordinator
+                        .groups
+                        .entry(group.clone())
+                        .or_default()
+                        .push(address.clone());
+                }
+            }
+            all_hosts.push(address);
+        }
+        coordinator.groups.insert("all".into(), all_hosts);
+        coordinator.hostvars = RwLock::new(hostvars);
+
+        coordinator
+    }
+
+    /// Values registered by a Job will be added to its host entry in `hostvars`, and thus be visible to other Jobs once registered.
+    pub fn with_registered_vars_sharing(mut self, share_registered_vars: bool) -> Self {
+        self.share_registered_vars = share_registered_vars;
+        self
+    }
+
+    /// Inserts `hostvars` and `groups` in the given context (overwriting any variable with the same name)
+    pub fn add_hostvars_to_context(&self, tera_context: &mut tera::Context) {
+        tera_context.insert(
+            "hostvars",
+            &*self.hostvars.read().unwrap_or_else(PoisonError::into_inner),
+        );
+        tera_context.insert("groups", &self.groups);
+    }
+
+    /// Takes `hostvars` and `groups` out of the given context : they are only needed while steps are rendered
+    pub fn remove_hostvars_from_context(&self, tera_context: &mut tera::Context) {
+        tera_context.remove("hostvars");
+        tera_context.remove("groups");
+    }
+
+    /// Makes a registered value of a host visible to other Jobs, if sharing of registered values is enabled
+    pub fn share_registered_var(&self, address: &str, variable_name: &str, value: &StepResult) {
+        if !self.share_registered_vars {
+            return;
+        }
+
+        let value = serde_json::to_value(value).unwrap_or(Value::Null);
+        let mut hostvars = self
+            .hostvars
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        match hostvars.get_mut(address) {
+            Some(Value::Object(vars)) => {
+                vars.insert(variable_name.to_string(), value);
+            }
+            _ => {
+                let mut vars = Map::new();
+                vars.insert(variable_name.to_string(), value);
+                hostvars.insert(address.to_string(), Value::Object(vars));
+            }
+        }
+    }
+
+    /// Runs `run` only if no other Job with the same TaskList already did it for this position, otherwise waits for this other Job to be done and returns its outcome.
+    /// address is the one of the Job running the step.
+    pub fn run_once<F>(
+        &self,
+        address: &str,
+        position: StepPosition,
+        run: F,
+    ) -> Result<StepFlow, String>
+    where
+        F: FnOnce() -> Result<StepFlow, String>,
+    {
+        let tasklist = self.tasklists.get(address).cloned().unwrap_or_default();
+        let outcome = self
+            .run_once_outcomes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry((tasklist, position))
+            .or_default()
+            .clone();
+
+        outcome.get_or_init(run).clone()
+    }
+
+    /// Builds and initializes a HostHandler for a delegate host (borrowed from the Jobs' ConnectionPool if any). The delegate needs to be one of the hosts of the JobList (its connection information is reused) or "localhost".
+    pub fn delegate_handler(&self, address: &str) -> Result<PooledHostHandler, Error> {
+        let host_connection_info = match self.connections.get(address) {
+            Some(host_connection_info) => host_connection_info.clone(),
+            None => {
+                if address == "localhost" {
+                    HostConnectionInfo::localhost_current_user()
+                } else {
+                    return Err(Error::Workflow(WorkflowError::UnknownDelegate(
+                        address.to_string(),
+                    )));
+                }
+            }
+        };
+
+        let mut host_handler = match &self.connection_pool {
+            Some(pool) => pool.borrow(address, &host_connection_info)?,
+            None => {
+                let mut host_handler =
+                    HostHandler::from(address.to_string(), host_connection_info)?;
+                host_handler.init()?;
+                PooledHostHandler::unpooled(host_handler)
+            }
+        };
+        if let Some(become_method) = self.become_methods.get(address) {
+            host_handler.become_method = *become_method;
+        }
+        host_handler.become_password = self.become_passwords.get(address).cloned();
+        Ok(host_handler)
+    }
+}
+
+// The same position in two different TaskLists is not the same step : TaskLists are told apart by their content
+fn tasklist_key(tasklist: &TaskList) -> String {
+    let content = serde_json::to_string(tasklist).unwrap_or_default();
+    format!("{:X}", Sha256::digest(content))
+}
@@ -1,6 +1,7 @@
 use crate::connection::hosthandler::HostHandler;
 use crate::error::Error;
 use crate::task::tasklist::TaskList;
+use crate::workflow::coordination::JobListCoordinator;
 use crate::workflow::taskflow::{TaskFlow, TaskStatus};
 use serde::{Deserialize, Serialize};
 
@@ -36,11 +37,12 @@ impl HostWorkFlow {
         &mut self,
         hosthandler: &mut HostHandler,
         tera_context: &mut tera::Context,
+        coordinator: &JobListCoordinator,
     ) -> Result<(), Error> {
         let mut changes_required = false;
 
-        for task_flow in self.task_flows.iter_mut() {
-            match task_flow.dry_run(hosthandler, tera_context) {
+        for (task_index, task_flow) in self.task_flows.iter_mut().enumerate() {
+            match task_flow.dry_run(hosthandler, tera_context, coordinator, task_index) {
                 Ok(()) => {
                     if let TaskStatus::ChangeRequired = task_flow.task_status {
                         changes_required = true;
@@ -65,6 +67,7 @@ impl HostWorkFlow {
         &mut self,
         hosthandler: &mut HostHandler,
         tera_context: &mut tera::Context,
+        coordinator: &JobListCoordinator,
     ) -> Result<(), Error> {
         if let HostWorkFlowStatus::AlreadyMatched = self.final_status {
             // Nothing to do, dry_run was performed before and concluded that nothing is to be
@@ -73,8 +76,8 @@ impl HostWorkFlow {
             let mut allowed_failures = false;
             let mut failures = false;
 
-            for task_flow in self.task_flows.iter_mut() {
-                match task_flow.apply(hosthandler, tera_context) {
+            for (task_index, task_flow) in self.task_flows.iter_mut().enumerate() {
+                match task_flow.apply(hosthandler, tera_context, coordinator, task_index) {
                     Ok(()) => match task_flow.task_status {
                         TaskStatus::ApplySuccesful => {
                             already_matched = false;
@@ -118,6 +121,7 @@ pub enum HostWorkFlowStatus {
     ApplySuccesful,
     ApplyWithAllowedFailure,
     ApplyFailed,
-    DryRunFailed,
-    ConnectionInitFailed(String)
+    DryRunFailed(String),
+    ConnectionInitFailed(String),
+    JobInitFailed(String),
 }
@@ -1,5 +1,6 @@
 //! Expected state -> required changes -> results
 
+pub mod coordination;
 pub mod hostworkflow;
 pub mod stepflow;
 pub mod taskflow;
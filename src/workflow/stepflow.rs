@@ -1,11 +1,14 @@
 use crate::connection::hosthandler::HostHandler;
 use crate::connection::specification::Privilege;
+use crate::error::workflow::WorkflowError;
 use crate::error::Error;
-use crate::result::apicallresult::ApiCallStatus;
+use crate::result::apicallresult::{ApiCallResult, ApiCallStatus};
 use crate::step::stepchange::StepChange;
 use crate::step::stepresult::StepResult;
 use crate::task::step::Step;
+use crate::workflow::coordination::{JobListCoordinator, StepPosition};
 use serde::{Deserialize, Serialize};
+use tera::Tera;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct StepFlow {
@@ -20,10 +23,7 @@ impl StepFlow {
     pub fn from(step: Step) -> StepFlow {
         StepFlow {
             step_expected: step.clone(),
-            allowed_to_fail: match step.allowed_to_fail {
-                Some(value) => value,
-                None => false,
-            },
+            allowed_to_fail: step.allowed_to_fail.unwrap_or_default(),
             step_change: None,
             step_result: None,
             step_status: StepStatus::NotRunYet,
@@ -34,30 +34,105 @@ impl StepFlow {
         &mut self,
         hosthandler: &mut HostHandler,
         tera_context: &mut tera::Context,
+        coordinator: &JobListCoordinator,
+        position: StepPosition,
     ) -> Result<(), Error> {
-        let privilege = match self.step_expected.with_sudo {
-            None => match &self.step_expected.run_as {
-                None => Privilege::Usual,
-                Some(username) => Privilege::AsUser(username.into()),
-            },
-            Some(value) => {
-                if value {
-                    Privilege::WithSudo
-                } else {
-                    match &self.step_expected.run_as {
-                        None => Privilege::Usual,
-                        Some(username) => Privilege::AsUser(username.into()),
-                    }
+        coordinator.add_hostvars_to_context(tera_context);
+
+        let dry_run_result = if let Some(true) = self.step_expected.run_once {
+            let address = hosthandler.hostaddress.clone();
+            let outcome = coordinator.run_once(&address, position, || {
+                let mut step_flow = self.clone();
+                match step_flow.dry_run_on_target(hosthandler, tera_context, coordinator) {
+                    Ok(()) => Ok(step_flow),
+                    Err(error) => Err(error.to_string()),
                 }
+            });
+            self.take_outcome(outcome)
+        } else {
+            self.dry_run_on_target(hosthandler, tera_context, coordinator)
+        };
+        // The escalation method of this step is not the one of the next
+        hosthandler.set_step_become_method(None);
+
+        dry_run_result
+    }
+
+    pub fn apply(
+        &mut self,
+        hosthandler: &mut HostHandler,
+        tera_context: &mut tera::Context,
+        coordinator: &JobListCoordinator,
+        position: StepPosition,
+    ) -> Result<(), Error> {
+        coordinator.add_hostvars_to_context(tera_context);
+
+        let apply_result = if let Some(true) = self.step_expected.run_once {
+            let address = hosthandler.hostaddress.clone();
+            let outcome = coordinator.run_once(&address, position, || {
+                let mut step_flow = self.clone();
+                match step_flow.apply_on_target(hosthandler, tera_context, coordinator, position) {
+                    Ok(()) => Ok(step_flow),
+                    Err(error) => Err(error.to_string()),
+                }
+            });
+            self.take_outcome(outcome)
+        } else {
+            self.apply_on_target(hosthandler, tera_context, coordinator, position)
+        };
+        hosthandler.set_step_become_method(None);
+
+        // A step which couldn't be carried out is a failed step, not a reason to stop the whole host
+        if let Err(error) = apply_result {
+            if self.allowed_to_fail {
+                self.step_status = StepStatus::ApplyFailedButAllowed;
+            } else {
+                self.step_status = StepStatus::ApplyFailed;
             }
+            self.step_result = Some(StepResult::from(&[ApiCallResult::from_error(&error)]));
+        }
+
+        // Register : push step result to context under the specified variable name
+        // With run_once, each Job registers the result of the Job which actually ran the step.
+        if let (Some(variable_name), Some(result)) =
+            (&self.step_expected.register, &self.step_result)
+        {
+            let registered_value = StepResult::from(&result.apicallresults);
+            coordinator.share_registered_var(
+                &hosthandler.hostaddress,
+                variable_name,
+                &registered_value,
+            );
+            tera_context.insert(variable_name, &registered_value);
+        }
+
+        Ok(())
+    }
+
+    fn dry_run_on_target(
+        &mut self,
+        hosthandler: &mut HostHandler,
+        tera_context: &mut tera::Context,
+        coordinator: &JobListCoordinator,
+    ) -> Result<(), Error> {
+        let privilege = self.privilege();
+
+        let mut delegate_handler = match self.delegate_address(tera_context)? {
+            Some(address) => Some(coordinator.delegate_handler(&address)?),
+            None => None,
+        };
+        let target_handler = match delegate_handler.as_mut() {
+            Some(delegate) => &mut **delegate,
+            None => hosthandler,
         };
+        target_handler.set_step_become_method(self.step_expected.become_method);
+        target_handler.set_checking(true);
 
         match self
             .step_expected
             .moduleblock
-            .consider_context(tera_context)
-            .unwrap() // TODO : If register of a step is used in another step later, dry_run is impossible -> handle this case
-            .dry_run_moduleblock(hosthandler, privilege)
+            .consider_context(tera_context)? // TODO : If register of a step is used in another step later, dry_run is impossible -> handle this case
+            .dry_run_moduleblock(target_handler, privilege)
         {
             Ok(mbchange) => {
                 match &mbchange {
@@ -77,35 +152,37 @@ impl StepFlow {
 
         Ok(())
     }
-    pub fn apply(
+
+    fn apply_on_target(
         &mut self,
         hosthandler: &mut HostHandler,
         tera_context: &mut tera::Context,
+        coordinator: &JobListCoordinator,
+        position: StepPosition,
     ) -> Result<(), Error> {
-        let privilege = match self.step_expected.with_sudo {
-            None => match &self.step_expected.run_as {
-                None => Privilege::Usual,
-                Some(username) => Privilege::AsUser(username.into()),
-            },
-            Some(value) => {
-                if value {
-                    Privilege::WithSudo
-                } else {
-                    match &self.step_expected.run_as {
-                        None => Privilege::Usual,
-                        Some(username) => Privilege::AsUser(username.into()),
-                    }
-                }
+        let privilege = self.privilege();
+
+        let mut delegate_handler = match self.delegate_address(tera_context)? {
+            Some(address) => {
+                let mut delegate = coordinator.delegate_handler(&address)?;
+                delegate.output_sink = hosthandler.output_sink.clone();
+                Some(delegate)
             }
+            None => None,
+        };
+        let target_handler = match delegate_handler.as_mut() {
+            Some(delegate) => &mut **delegate,
+            None => hosthandler,
         };
+        target_handler.set_step_become_method(self.step_expected.become_method);
+        target_handler.set_checking(true);
 
         // Dry run -> Changes
         match self
             .step_expected
             .moduleblock
-            .consider_context(tera_context)
-            .unwrap()
-            .dry_run_moduleblock(hosthandler, privilege)
+            .consider_context(tera_context)?
+            .dry_run_moduleblock(target_handler, privilege)
         {
             Ok(mbchange) => {
                 match &mbchange {
@@ -126,7 +203,11 @@ impl StepFlow {
         // Apply the changes
         match &self.step_change {
             Some(change) => {
-                let result = change.apply_moduleblockchange(hosthandler);
+                // Only the commands changing the host are streamed, not the checks of the dry run
+                target_handler.set_checking(false);
+                target_handler.set_current_step(Some(self.output_tag(position)));
+                let result = change.apply_moduleblockchange(target_handler);
+                target_handler.set_current_step(None);
                 let mut step_status = StepStatus::ApplySuccessful;
 
                 for apicallresult in result.apicallresults.clone().iter() {
@@ -143,23 +224,69 @@ impl StepFlow {
                     }
                 }
 
-                // Register : push step result to context under the specified variable name
-                if let Some(variable_name) = &self.step_expected.register {
-                    tera_context.insert(variable_name, &StepResult::from(&result.apicallresults));
-                }
-
                 self.step_status = step_status;
                 self.step_result = Some(result);
             }
             None => {
-                return Err(Error::WorkFlowNotFollowed(
+                return Err(Error::Workflow(WorkflowError::NotFollowed(
                     "StepStatus = ChangeRequired but StepChange is empty. Something needs to be done but no information on what to do is provided.".into()
-                ))
+                )))
             }
         }
 
         Ok(())
     }
+
+    // Output lines of an unnamed step are told apart by its position, counted from 1 like in the TaskList
+    fn output_tag(&self, (task_index, step_index): StepPosition) -> String {
+        match &self.step_expected.name {
+            Some(name) => name.clone(),
+            None => format!("task {}, step {}", task_index + 1, step_index + 1),
+        }
+    }
+
+    fn privilege(&self) -> Privilege {
+        match self.step_expected.with_sudo {
+            None => match &self.step_expected.run_as {
+                None => Privilege::Usual,
+                Some(username) => Privilege::AsUser(username.into()),
+            },
+            Some(value) => {
+                if value {
+                    Privilege::WithSudo
+                } else {
+                    match &self.step_expected.run_as {
+                        None => Privilege::Usual,
+                        Some(username) => Privilege::AsUser(username.into()),
+                    }
+                }
+            }
+        }
+    }
+
+    // delegate_to can be templated (ex: "{{ db_server }}")
+    fn delegate_address(&self, tera_context: &tera::Context) -> Result<Option<String>, Error> {
+        match &self.step_expected.delegate_to {
+            Some(delegate_to) => match Tera::one_off(delegate_to, tera_context, false) {
+                Ok(address) => Ok(Some(address.trim().to_string())),
+                Err(error) => Err(Error::Workflow(WorkflowError::Template(error))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // Copy the outcome of a run_once step (produced by whichever Job ran it) into this StepFlow
+    fn take_outcome(&mut self, outcome: Result<StepFlow, String>) -> Result<(), Error> {
+        match outcome {
+            Ok(step_flow) => {
+                self.step_change = step_flow.step_change;
+                self.step_result = step_flow.step_result;
+                self.step_status = step_flow.step_status;
+                Ok(())
+            }
+            Err(error) => Err(Error::Workflow(WorkflowError::RunOnceFailed(error))),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -171,3 +298,59 @@ pub enum StepStatus {
     ApplyFailedButAllowed,
     ApplyFailed,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::escalation::BecomeMethod;
+    use crate::connection::host_connection::HostConnectionInfo;
+    use crate::task::tasklist::{TaskList, TaskListFileType};
+
+    #[test]
+    fn escalation_method_of_a_step_is_forgotten_after_it() {
+        let tasklist = TaskList::from_str(
+            "---
+- name: Escalation
+  steps:
+    - name: With its own method
+      become_method: doas
+      command:
+        content: echo done
+",
+            TaskListFileType::Yaml,
+        )
+        .unwrap();
+        let mut hosthandler = HostHandler::from(
+            "localhost".to_string(),
+            HostConnectionInfo::localhost_current_user(),
+        )
+        .unwrap();
+        hosthandler.init().unwrap();
+        let coordinator = JobListCoordinator::new();
+
+        let mut step_flow = StepFlow::from(tasklist.tasks[0].steps[0].clone());
+        assert_eq!(
+            step_flow.step_expected.become_method,
+            Some(BecomeMethod::Doas)
+        );
+        step_flow
+            .dry_run(
+                &mut hosthandler,
+                &mut tera::Context::new(),
+                &coordinator,
+                (0, 0),
+            )
+            .unwrap();
+        assert_eq!(hosthandler.step_become_method, None);
+        step_flow
+            .apply(
+                &mut hosthandler,
+                &mut tera::Context::new(),
+                &coordinator,
+                (0, 0),
+            )
+            .unwrap();
+        assert_eq!(hosthandler.step_become_method, None);
+        assert!(matches!(step_flow.step_status, StepStatus::ApplySuccessful));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::connection::hosthandler::HostHandler;
 use crate::error::Error;
 use crate::task::taskblock::TaskBlock;
+use crate::workflow::coordination::JobListCoordinator;
 use crate::workflow::stepflow::{StepFlow, StepStatus};
 use serde::{Deserialize, Serialize};
 
@@ -42,11 +43,18 @@ impl TaskFlow {
         &mut self,
         hosthandler: &mut HostHandler,
         tera_context: &mut tera::Context,
+        coordinator: &JobListCoordinator,
+        task_index: usize,
     ) -> Result<(), Error> {
         let mut changes_required = false;
 
-        for step_flow in self.step_flows.iter_mut() {
-            match step_flow.dry_run(hosthandler, tera_context) {
+        for (step_index, step_flow) in self.step_flows.iter_mut().enumerate() {
+            match step_flow.dry_run(
+                hosthandler,
+                tera_context,
+                coordinator,
+                (task_index, step_index),
+            ) {
                 Ok(()) => {
                     if let StepStatus::ChangeRequired = step_flow.step_status {
                         changes_required = true;
@@ -71,11 +79,18 @@ impl TaskFlow {
         &mut self,
         hosthandler: &mut HostHandler,
         tera_context: &mut tera::Context,
+        coordinator: &JobListCoordinator,
+        task_index: usize,
     ) -> Result<(), Error> {
         let mut task_status = TaskStatus::ApplySuccesful;
 
-        for step_flow in self.step_flows.iter_mut() {
-            match step_flow.apply(hosthandler, tera_context) {
+        for (step_index, step_flow) in self.step_flows.iter_mut().enumerate() {
+            match step_flow.apply(
+                hosthandler,
+                tera_context,
+                coordinator,
+                (task_index, step_index),
+            ) {
                 Ok(()) => match &step_flow.step_status {
                     StepStatus::ApplyFailed => {
                         task_status = TaskStatus::ApplyFailed;